@@ -0,0 +1,338 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Derive macro for mapping instances of a Python class onto a Rust struct.
+//!
+//! Use it through the `derive` feature of `pickled`, which re-exports
+//! `pickled::PickleClass`.
+//!
+//! ```ignore
+//! use pickled::PickleClass;
+//!
+//! #[derive(PickleClass)]
+//! #[pickle(class = "shapes.Point")]
+//! struct Point {
+//!     #[pickle(arg)]
+//!     x: i64,
+//!     #[pickle(arg)]
+//!     y: i64,
+//!     #[pickle(rename = "name")]
+//!     label: String,
+//! }
+//! ```
+//!
+//! The struct implements `Serialize` and `Deserialize`.  Deserializing checks
+//! that the pickled object is an instance of the given class, and takes the
+//! fields from the arguments to `__new__` and from the object's state.
+//! Serializing writes the instance so that Python's `pickle.loads` recreates
+//! it, with `__new__` called with the positional arguments and the state set
+//! by `BUILD`.
+//!
+//! Field attributes:
+//!
+//! * `arg`: the field is the next positional argument to `__new__`, as
+//!   returned by `__getnewargs__`.
+//! * `rename = "attr"`: the field is the instance attribute `attr` instead of
+//!   an attribute with the field's name.
+//! * `slot`: the field is stored in `__slots__`, not in the instance `__dict__`.
+//!   On deserialization, attributes are looked up in both places.
+//! * `state`: the field is the complete object state, as passed to
+//!   `__setstate__`.  It excludes all attribute fields.
+//! * `default`: a missing attribute is replaced by `Default::default()`.
+//! * `skip`: the field is not pickled, and set to `Default::default()` when
+//!   unpickling.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    Data, DeriveInput, Error, Fields, Ident, LitStr, Result, parse_macro_input, parse_quote,
+};
+
+#[proc_macro_derive(PickleClass, attributes(pickle))]
+pub fn derive_pickle_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Arg,
+    Attr,
+    Slot,
+    State,
+    Skip,
+}
+
+struct Field {
+    ident: Ident,
+    name: String,
+    kind: Kind,
+    default: bool,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let class = parse_class(&input)?;
+    let (module, name) = match class.value().rsplit_once('.') {
+        Some((module, name)) if !module.is_empty() && !name.is_empty() => {
+            (module.to_owned(), name.to_owned())
+        }
+        _ => {
+            return Err(Error::new(
+                class.span(),
+                "class must be given as \"module.Name\"",
+            ));
+        }
+    };
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .map(parse_field)
+                .collect::<Result<Vec<_>>>()?,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "PickleClass requires a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "PickleClass can only be derived for structs",
+            ));
+        }
+    };
+
+    let has = |kind| fields.iter().any(|f| f.kind == kind);
+    let states = fields.iter().filter(|f| f.kind == Kind::State).count();
+    if states > 1 {
+        return Err(Error::new(
+            input.ident.span(),
+            "only one field can be the object state",
+        ));
+    }
+    if states == 1 && (has(Kind::Attr) || has(Kind::Slot)) {
+        return Err(Error::new(
+            input.ident.span(),
+            "a state field cannot be combined with attribute fields",
+        ));
+    }
+
+    let ser = expand_serialize(&input, &module, &name, &fields);
+    let de = expand_deserialize(&input, &module, &name, &fields);
+    Ok(quote! {
+        #ser
+        #de
+    })
+}
+
+fn parse_class(input: &DeriveInput) -> Result<LitStr> {
+    let mut class = None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("pickle") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("class") {
+                class = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown pickle container attribute"))
+            }
+        })?;
+    }
+    class.ok_or_else(|| {
+        Error::new(
+            input.ident.span(),
+            "missing #[pickle(class = \"module.Name\")] attribute",
+        )
+    })
+}
+
+fn parse_field(field: &syn::Field) -> Result<Field> {
+    let ident = field.ident.clone().expect("named field");
+    let mut name = ident.to_string().trim_start_matches("r#").to_owned();
+    let mut kind = Kind::Attr;
+    let mut default = false;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("pickle") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            let mut set_kind = |new| {
+                if kind != Kind::Attr {
+                    return Err(meta.error("conflicting pickle field attributes"));
+                }
+                kind = new;
+                Ok(())
+            };
+            if meta.path.is_ident("arg") {
+                set_kind(Kind::Arg)
+            } else if meta.path.is_ident("slot") {
+                set_kind(Kind::Slot)
+            } else if meta.path.is_ident("state") {
+                set_kind(Kind::State)
+            } else if meta.path.is_ident("skip") {
+                set_kind(Kind::Skip)
+            } else if meta.path.is_ident("default") {
+                default = true;
+                Ok(())
+            } else if meta.path.is_ident("rename") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("unknown pickle field attribute"))
+            }
+        })?;
+    }
+    if default && !matches!(kind, Kind::Attr | Kind::Slot) {
+        return Err(Error::new(
+            field.span(),
+            "`default` only applies to attribute fields",
+        ));
+    }
+    Ok(Field {
+        ident,
+        name,
+        kind,
+        default,
+    })
+}
+
+/// Build a `FieldList` expression from the given fields.
+fn field_list<'a>(fields: impl DoubleEndedIterator<Item = &'a Field>) -> TokenStream2 {
+    fields.rev().fold(quote!(()), |rest, field| {
+        let ident = &field.ident;
+        quote!((&self.#ident, #rest))
+    })
+}
+
+fn attrs(fields: &[Field], kind: Kind) -> impl DoubleEndedIterator<Item = &Field> {
+    fields.iter().filter(move |f| f.kind == kind)
+}
+
+fn attrs_expr(fields: &[Field], kind: Kind) -> TokenStream2 {
+    let names = attrs(fields, kind).map(|f| &f.name);
+    let list = field_list(attrs(fields, kind));
+    quote!(__private::Attrs(&[#(#names),*], #list))
+}
+
+fn expand_serialize(
+    input: &DeriveInput,
+    module: &str,
+    name: &str,
+    fields: &[Field],
+) -> TokenStream2 {
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::pickled::__private::serde::Serialize));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let args = field_list(attrs(fields, Kind::Arg));
+    let has = |kind| fields.iter().any(|f| f.kind == kind);
+    let state = if let Some(field) = fields.iter().find(|f| f.kind == Kind::State) {
+        let ident = &field.ident;
+        quote!(::core::option::Option::Some(&self.#ident))
+    } else if has(Kind::Slot) {
+        // Python pickles objects with `__slots__` as a `(dict, slots)` tuple.
+        let dict = if has(Kind::Attr) {
+            let dict = attrs_expr(fields, Kind::Attr);
+            quote!(::core::option::Option::Some(#dict))
+        } else {
+            quote!(::core::option::Option::<()>::None)
+        };
+        let slots = attrs_expr(fields, Kind::Slot);
+        quote!(::core::option::Option::Some(&(#dict, #slots)))
+    } else if has(Kind::Attr) {
+        let dict = attrs_expr(fields, Kind::Attr);
+        quote!(::core::option::Option::Some(&#dict))
+    } else {
+        quote!(::core::option::Option::<&()>::None)
+    };
+
+    quote! {
+        impl #impl_generics ::pickled::__private::serde::Serialize for #ident #ty_generics #where_clause {
+            fn serialize<__S>(&self, __serializer: __S) -> ::core::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::pickled::__private::serde::Serializer,
+            {
+                use ::pickled::__private;
+                __private::serialize_object(
+                    __serializer,
+                    #module,
+                    #name,
+                    &__private::Args(#args),
+                    #state,
+                )
+            }
+        }
+    }
+}
+
+fn expand_deserialize(
+    input: &DeriveInput,
+    module: &str,
+    name: &str,
+    fields: &[Field],
+) -> TokenStream2 {
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(
+            ::pickled::__private::serde::de::DeserializeOwned
+        ));
+    }
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+    let mut impl_generics = generics.clone();
+    impl_generics.params.insert(0, parse_quote!('de));
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+
+    let mut arg_index = 0usize;
+    let inits = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let attr = &field.name;
+        let value = match field.kind {
+            Kind::Arg => {
+                let index = arg_index;
+                arg_index += 1;
+                quote!(__fields.arg(#index))
+            }
+            Kind::Attr | Kind::Slot if field.default => quote!(__fields.attr_or_default(#attr)),
+            Kind::Attr | Kind::Slot => quote!(__fields.attr(#attr)),
+            Kind::State => quote!(__fields.state()),
+            Kind::Skip => return quote!(#ident: ::core::default::Default::default()),
+        };
+        quote!(#ident: #value.map_err(__D::Error::custom)?)
+    });
+
+    quote! {
+        impl #impl_generics ::pickled::__private::serde::Deserialize<'de> for #ident #ty_generics #where_clause {
+            fn deserialize<__D>(__deserializer: __D) -> ::core::result::Result<Self, __D::Error>
+            where
+                __D: ::pickled::__private::serde::Deserializer<'de>,
+            {
+                use ::pickled::__private::serde::de::Error as _;
+                let __object = <::pickled::value::Object as ::pickled::__private::serde::Deserialize>::deserialize(__deserializer)?;
+                let __fields = ::pickled::__private::ObjectFields::new(__object, #module, #name)
+                    .map_err(__D::Error::custom)?;
+                ::core::result::Result::Ok(#ident {
+                    #(#inits,)*
+                })
+            }
+        }
+    }
+}
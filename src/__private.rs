@@ -0,0 +1,172 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Support code for `#[derive(PickleClass)]`.  Not public API.
+
+use serde::de::DeserializeOwned;
+use serde::ser::{self, Serialize, SerializeMap, SerializeStruct, SerializeTuple};
+
+use std::result::Result as StdResult;
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::types;
+use crate::value::{Object, Value, from_value};
+
+pub use serde;
+
+/// Access to the fields of an object of a known class.
+pub struct ObjectFields {
+    object: Object,
+}
+
+impl ObjectFields {
+    /// Check that `object` is an instance of `module.name`.
+    pub fn new(object: Object, module: &str, name: &str) -> Result<Self> {
+        if object.module != module || object.name != name {
+            return Err(Error::Syntax(ErrorCode::Structure(format!(
+                "expected an instance of {module}.{name}, got {}",
+                object.class_path()
+            ))));
+        }
+        Ok(ObjectFields { object })
+    }
+
+    /// Positional argument to `__new__`.
+    pub fn arg<T: DeserializeOwned>(&self, index: usize) -> Result<T> {
+        match self.object.args.get(index) {
+            Some(value) => from_value(value.clone()),
+            None => Err(self.missing(format_args!("argument {index}"))),
+        }
+    }
+
+    /// Instance attribute, from `__dict__` or the `__slots__` state.  Missing
+    /// attributes are accepted for fields that can be decoded from `None`.
+    pub fn attr<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        match self.object.attr(name) {
+            Some(value) => from_value(value),
+            None => from_value(Value::None)
+                .map_err(|_| self.missing(format_args!("attribute `{name}`"))),
+        }
+    }
+
+    /// Instance attribute that is replaced by its default if missing.
+    pub fn attr_or_default<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T> {
+        match self.object.attr(name) {
+            Some(value) => from_value(value),
+            None => Ok(T::default()),
+        }
+    }
+
+    /// The complete state, as passed to `__setstate__`.
+    pub fn state<T: DeserializeOwned>(&self) -> Result<T> {
+        from_value(self.object.state.clone().unwrap_or(Value::None))
+    }
+
+    fn missing(&self, what: std::fmt::Arguments) -> Error {
+        Error::Syntax(ErrorCode::Structure(format!(
+            "missing {what} of {}",
+            self.object.class_path()
+        )))
+    }
+}
+
+/// A list of field references, built as `(&a, (&b, ()))`, so that any number
+/// of fields with different types can be serialized without going through
+/// `Value`.
+pub trait FieldList {
+    const LEN: usize;
+
+    fn serialize_elements<S: SerializeTuple>(&self, seq: &mut S) -> StdResult<(), S::Error>;
+
+    fn serialize_entries<M: SerializeMap>(
+        &self,
+        names: &[&'static str],
+        map: &mut M,
+    ) -> StdResult<(), M::Error>;
+}
+
+impl FieldList for () {
+    const LEN: usize = 0;
+
+    fn serialize_elements<S: SerializeTuple>(&self, _seq: &mut S) -> StdResult<(), S::Error> {
+        Ok(())
+    }
+
+    fn serialize_entries<M: SerializeMap>(
+        &self,
+        _names: &[&'static str],
+        _map: &mut M,
+    ) -> StdResult<(), M::Error> {
+        Ok(())
+    }
+}
+
+impl<A: Serialize + ?Sized, T: FieldList> FieldList for (&A, T) {
+    const LEN: usize = T::LEN + 1;
+
+    fn serialize_elements<S: SerializeTuple>(&self, seq: &mut S) -> StdResult<(), S::Error> {
+        seq.serialize_element(self.0)?;
+        self.1.serialize_elements(seq)
+    }
+
+    fn serialize_entries<M: SerializeMap>(
+        &self,
+        names: &[&'static str],
+        map: &mut M,
+    ) -> StdResult<(), M::Error> {
+        map.serialize_entry(names[0], self.0)?;
+        self.1.serialize_entries(&names[1..], map)
+    }
+}
+
+/// Positional arguments, serialized as a tuple.
+pub struct Args<T>(pub T);
+
+impl<T: FieldList> Serialize for Args<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_tuple(T::LEN)?;
+        self.0.serialize_elements(&mut seq)?;
+        seq.end()
+    }
+}
+
+/// Attributes, serialized as a dict from their names.
+pub struct Attrs<T>(pub &'static [&'static str], pub T);
+
+impl<T: FieldList> Serialize for Attrs<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(T::LEN))?;
+        self.1.serialize_entries(self.0, &mut map)?;
+        map.end()
+    }
+}
+
+/// Serialize an object of class `module.name` the same way as a
+/// `value::Object`.
+pub fn serialize_object<S, A, T>(
+    serializer: S,
+    module: &'static str,
+    name: &'static str,
+    args: &A,
+    state: Option<&T>,
+) -> StdResult<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+    A: Serialize,
+    T: Serialize + ?Sized,
+{
+    let mut object = serializer.serialize_struct(types::OBJECT, 5)?;
+    object.serialize_field("module", module)?;
+    object.serialize_field("name", name)?;
+    object.serialize_field("new", &true)?;
+    object.skip_field("kwargs")?;
+    object.serialize_field("args", args)?;
+    match state {
+        Some(state) => object.serialize_field("state", state)?,
+        None => object.skip_field("state")?,
+    }
+    object.end()
+}
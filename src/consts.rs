@@ -77,6 +77,8 @@ pub enum Opcode {
     NewObj = b'\x81',          // build object by applying cls.__new__ to argtuple
     NewObjEx = b'\x92',        // like NEWOBJ but work with keyword only arguments
     ByteArray8 = b'\x96',      // push bytearray
+    PersId = b'P',             // push persistent object; id is taken from string arg
+    BinPersId = b'Q',          //  "       "         "  ;  "  "   "     "  stack
 }
 
 impl Opcode {
@@ -160,18 +162,99 @@ impl TryFrom<u8> for Opcode {
             b'\x81' => Ok(Opcode::NewObj),
             b'\x92' => Ok(Opcode::NewObjEx),
             b'\x96' => Ok(Opcode::ByteArray8),
+            b'P' => Ok(Opcode::PersId),
+            b'Q' => Ok(Opcode::BinPersId),
             _ => Err(ErrorCode::Unsupported(value as char)),
         }
     }
 }
 
+/// The name pickle.py uses for an opcode byte, including the opcodes we don't
+/// support.
+pub fn opcode_name(byte: u8) -> Option<&'static str> {
+    let name = match Opcode::try_from(byte) {
+        Ok(opcode) => match opcode {
+            Opcode::Mark => "MARK",
+            Opcode::Stop => "STOP",
+            Opcode::Pop => "POP",
+            Opcode::PopMark => "POP_MARK",
+            Opcode::Dup => "DUP",
+            Opcode::Float => "FLOAT",
+            Opcode::Int => "INT",
+            Opcode::BinInt => "BININT",
+            Opcode::BinInt1 => "BININT1",
+            Opcode::Long => "LONG",
+            Opcode::BinInt2 => "BININT2",
+            Opcode::None => "NONE",
+            Opcode::String => "STRING",
+            Opcode::BinString => "BINSTRING",
+            Opcode::ShortBinString => "SHORT_BINSTRING",
+            Opcode::Unicode => "UNICODE",
+            Opcode::BinUnicode => "BINUNICODE",
+            Opcode::Append => "APPEND",
+            Opcode::Dict => "DICT",
+            Opcode::EmptyDict => "EMPTY_DICT",
+            Opcode::Appends => "APPENDS",
+            Opcode::List => "LIST",
+            Opcode::EmptyList => "EMPTY_LIST",
+            Opcode::SetItem => "SETITEM",
+            Opcode::Tuple => "TUPLE",
+            Opcode::EmptyTuple => "EMPTY_TUPLE",
+            Opcode::SetItems => "SETITEMS",
+            Opcode::BinFloat => "BINFLOAT",
+            Opcode::Put => "PUT",
+            Opcode::BinPut => "BINPUT",
+            Opcode::LongBinPut => "LONG_BINPUT",
+            Opcode::Get => "GET",
+            Opcode::BinGet => "BINGET",
+            Opcode::LongBinGet => "LONG_BINGET",
+            Opcode::Global => "GLOBAL",
+            Opcode::StackGlobal => "STACK_GLOBAL",
+            Opcode::Reduce => "REDUCE",
+            Opcode::Proto => "PROTO",
+            Opcode::Tuple1 => "TUPLE1",
+            Opcode::Tuple2 => "TUPLE2",
+            Opcode::Tuple3 => "TUPLE3",
+            Opcode::NewTrue => "NEWTRUE",
+            Opcode::NewFalse => "NEWFALSE",
+            Opcode::Long1 => "LONG1",
+            Opcode::Long4 => "LONG4",
+            Opcode::BinBytes => "BINBYTES",
+            Opcode::ShortBinBytes => "SHORT_BINBYTES",
+            Opcode::ShortBinUnicode => "SHORT_BINUNICODE",
+            Opcode::BinUnicode8 => "BINUNICODE8",
+            Opcode::BinBytes8 => "BINBYTES8",
+            Opcode::EmptySet => "EMPTY_SET",
+            Opcode::AddItems => "ADDITEMS",
+            Opcode::FrozenSet => "FROZENSET",
+            Opcode::Memoize => "MEMOIZE",
+            Opcode::Frame => "FRAME",
+            Opcode::Inst => "INST",
+            Opcode::Obj => "OBJ",
+            Opcode::Build => "BUILD",
+            Opcode::NewObj => "NEWOBJ",
+            Opcode::NewObjEx => "NEWOBJ_EX",
+            Opcode::ByteArray8 => "BYTEARRAY8",
+            Opcode::PersId => "PERSID",
+            Opcode::BinPersId => "BINPERSID",
+        },
+        Err(_) => match byte {
+            b'\x97' => "NEXT_BUFFER",
+            b'\x98' => "READONLY_BUFFER",
+            b'\x82' => "EXT1",
+            b'\x83' => "EXT2",
+            b'\x84' => "EXT4",
+            _ => return None,
+        },
+    };
+    Some(name)
+}
+
 // Ops used for out-of-band buffers; these are unsupported.
 // pub const NEXT_BUFFER      : u8 = b'\x97'; // push next out-of-band buffer
 // pub const READONLY_BUFFER  : u8 = b'\x98'; // make top of stack readonly
 
 // Ops only used for recursive objects; these are unsupported.
-// pub const PERSID           : u8 = b'P';    // push persistent object; id is taken from string arg
-// pub const BINPERSID        : u8 = b'Q';    //  "       "         "  ;  "  "   "     "  stack
 // pub const EXT1             : u8 = b'\x82'; // push object from extension registry; 1-byte index
 // pub const EXT2             : u8 = b'\x83'; // ditto, but 2-byte index
 // pub const EXT4             : u8 = b'\x84'; // ditto, but 4-byte index
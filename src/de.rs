@@ -16,23 +16,30 @@ use num_bigint::{BigInt, Sign};
 use num_traits::ToPrimitive;
 use serde::de::Visitor;
 use serde::{de, forward_to_deserialize_any};
+use std::any::Any;
 use std::borrow::Cow;
 use std::char;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::io;
 use std::io::{BufRead, BufReader, Read};
 use std::iter::FusedIterator;
 use std::mem;
+use std::rc::Weak;
 use std::str;
 use std::str::FromStr;
 use std::vec;
 
-use crate::value::{RawHashableValue, Shared, SharedFrozen};
+use crate::value::{RawHashableValue, Shared, SharedFrozen, wtf8_lossy};
 
+use super::compat;
 use super::consts::*;
 use super::error::{Error, ErrorCode, Result};
+use super::ndarray::{self, ArrayData, Dtype, NdArray};
+use super::types;
+use super::types::{EnumValues, PyEnum};
 use super::value;
+use super::value_impls;
 
 const MEMO_REF_COUNTING: bool = false;
 
@@ -47,6 +54,16 @@ enum Global {
     Int,           // builtins/__builtin__.int
     Encode,        // _codecs.encode
     Reconstructor, // copy_reg._reconstructor
+    Namespace,     // types.SimpleNamespace
+    NewObj,        // copyreg.__newobj__
+    NewObjEx,      // copyreg.__newobj_ex__
+    OrderedDict,   // collections.OrderedDict
+    DefaultDict,   // collections.defaultdict
+    Deque,         // collections.deque
+    Counter,       // collections.Counter
+    Getattr,       // builtins.getattr
+    Array,         // array.array
+    ArrayRecon,    // array._array_reconstructor
     // anything else (may be a classobj that is later discarded)
     Other {
         modname: Cow<'static, str>,
@@ -54,6 +71,103 @@ enum Global {
     },
 }
 
+impl Global {
+    // The module and name of the global, as in Python 3.
+    fn into_names(self) -> (Cow<'static, str>, Cow<'static, str>) {
+        let (modname, globname) = match self {
+            Global::Set => ("builtins", "set"),
+            Global::Frozenset => ("builtins", "frozenset"),
+            Global::Bytearray => ("builtins", "bytearray"),
+            Global::List => ("builtins", "list"),
+            Global::Int => ("builtins", "int"),
+            Global::Encode => ("_codecs", "encode"),
+            Global::Reconstructor => ("copyreg", "_reconstructor"),
+            Global::Namespace => ("types", "SimpleNamespace"),
+            Global::NewObj => ("copyreg", "__newobj__"),
+            Global::NewObjEx => ("copyreg", "__newobj_ex__"),
+            Global::OrderedDict => ("collections", "OrderedDict"),
+            Global::DefaultDict => ("collections", "defaultdict"),
+            Global::Deque => ("collections", "deque"),
+            Global::Counter => ("collections", "Counter"),
+            Global::Getattr => ("builtins", "getattr"),
+            Global::Array => ("array", "array"),
+            Global::ArrayRecon => ("array", "_array_reconstructor"),
+            Global::Other { modname, globname } => return (modname, globname),
+        };
+        (Cow::Borrowed(modname), Cow::Borrowed(globname))
+    }
+}
+
+/// How an object was created.  This determines what it is replaced by if it
+/// doesn't get any state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Construct {
+    New,    // cls.__new__(cls, *args) by NEWOBJ; replaced by args or an empty dict
+    Inst,   // cls(*args) by OBJ or INST; replaced by an empty dict
+    Reduce, // callable(*args) by REDUCE; could be anything, so it's unresolved
+}
+
+/// A class instance.  The class is kept so that the object can be converted
+/// to a `value::Object` on request; otherwise it is replaced by its state.
+#[derive(Clone, Debug, PartialEq)]
+struct Object {
+    modname: Cow<'static, str>,
+    globname: Cow<'static, str>,
+    construct: Construct,
+    args: Vec<Value>,
+    kwargs: Vec<(Value, Value)>,
+    items: Option<Value>, // contents of list, dict and set subclass instances
+    state: Option<Value>,
+}
+
+impl Object {
+    // The fields of an object, as expected by the `Deserialize` impl of
+    // `value::Object`.  The leading special key lets `Value`'s `Deserialize`
+    // impl recognize objects nested in other objects.
+    fn into_fields(self) -> Value {
+        let key = |name: &str| Value::String(SharedFrozen::new(name.into()));
+        let mut fields = vec![
+            (key(types::OBJECT), Value::None),
+            (
+                key("module"),
+                Value::String(SharedFrozen::new(self.modname.into_owned())),
+            ),
+            (
+                key("name"),
+                Value::String(SharedFrozen::new(self.globname.into_owned())),
+            ),
+            (key("new"), Value::Bool(self.construct == Construct::New)),
+            (key("kwargs"), Value::Dict(Shared::new(self.kwargs))),
+            (key("args"), Value::Tuple(SharedFrozen::new(self.args))),
+        ];
+        if let Some(items) = self.items {
+            fields.push((key("items"), items));
+        }
+        if let Some(state) = self.state {
+            fields.push((key("state"), state));
+        }
+        Value::Dict(Shared::new(fields))
+    }
+
+    // Find the enum variant named like the object's class, either with the
+    // full "module.Name" path or just the name.
+    fn class_variant(&self, variants: &[&'static str]) -> Option<&'static str> {
+        value::class_variant(&self.modname, &self.globname, variants)
+    }
+
+    // Turn the object into the `(variant, content)` enum representation, with
+    // the state as the content, or the arguments if there is no state.
+    fn into_variant(self, variant: &str) -> Value {
+        let mut items = vec![Value::String(SharedFrozen::new(variant.into()))];
+        match self.state {
+            Some(state) => items.push(state),
+            None if !self.args.is_empty() => items.push(Value::Tuple(SharedFrozen::new(self.args))),
+            None => {}
+        }
+        Value::Tuple(SharedFrozen::new(items))
+    }
+}
+
 /// Our intermediate representation of a value.
 ///
 /// The most striking difference to `value::Value` is that it contains a variant
@@ -73,21 +187,231 @@ enum Value {
     Int(BigInt),
     F64(f64),
     Bytes(SharedFrozen<Vec<u8>>),
+    ByteArray(Shared<Vec<u8>>),
+    Str(SharedFrozen<Vec<u8>>), // Python 2 str, decoded depending on its use
     String(SharedFrozen<String>),
+    WtfString(SharedFrozen<Vec<u8>>), // string with lone surrogates
     List(Shared<Vec<Value>>),
     Tuple(SharedFrozen<Vec<Value>>),
     Set(Shared<Vec<Value>>),
     FrozenSet(SharedFrozen<Vec<Value>>),
     Dict(Shared<Vec<(Value, Value)>>),
+    Object(Shared<Object>),
+    PersId(Box<Value>), // reference to an object outside the pickle
+}
+
+impl Value {
+    // The address of a container, to look up the offset of the opcode that
+    // created it, and a weak reference that keeps the address from being
+    // reused while it is recorded.
+    fn container_ref(&self) -> Option<(usize, Weak<dyn Any>)> {
+        match self {
+            Value::List(v) | Value::Set(v) => Some((v.provenance(), v.downgrade())),
+            Value::Tuple(v) | Value::FrozenSet(v) => Some((v.provenance(), v.downgrade())),
+            Value::Dict(v) => Some((v.provenance(), v.downgrade())),
+            Value::Object(v) => Some((v.provenance(), v.downgrade())),
+            _ => None,
+        }
+    }
+
+    // A short, readable description of the value for error messages.
+    fn summary(&self) -> String {
+        fn items(what: &str, len: usize) -> String {
+            format!("{what} ({len} item{})", if len == 1 { "" } else { "s" })
+        }
+        match self {
+            Value::MemoRef(id) => format!("memo #{id}"),
+            Value::Global(global) => {
+                let (modname, globname) = global.clone().into_names();
+                format!("global {modname}.{globname}")
+            }
+            Value::None => "None".into(),
+            Value::Bool(b) => (if *b { "True" } else { "False" }).into(),
+            Value::I64(i) => i.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::F64(f) => f.to_string(),
+            Value::Str(b) => format!("str {}", Value::Bytes(b.clone()).summary()),
+            Value::ByteArray(b) => {
+                let bytes = Value::Bytes(SharedFrozen::new(b.inner().clone()));
+                format!("bytearray {}", bytes.summary())
+            }
+            Value::Bytes(b) => {
+                let b = b.inner();
+                match b.len() {
+                    0..=32 => format!("b\"{}\"", b.escape_ascii()),
+                    len => format!("b\"{}...\" ({len} bytes)", b[..32].escape_ascii()),
+                }
+            }
+            Value::String(s) => {
+                let s = s.inner();
+                match s.chars().count() {
+                    0..=32 => format!("{s:?}"),
+                    len => {
+                        let start: String = s.chars().take(32).collect();
+                        format!("{start:?}... ({len} chars)")
+                    }
+                }
+            }
+            Value::WtfString(s) => {
+                Value::String(SharedFrozen::new(wtf8_lossy(s.inner()))).summary()
+            }
+            Value::List(v) => items("list", v.inner().len()),
+            Value::Tuple(v) => items("tuple", v.inner().len()),
+            Value::Set(v) => items("set", v.inner().len()),
+            Value::FrozenSet(v) => items("frozenset", v.inner().len()),
+            Value::Dict(v) => items("dict", v.inner().len()),
+            Value::Object(v) => {
+                let object = v.inner();
+                format!("{}.{} object", object.modname, object.globname)
+            }
+            Value::PersId(pid) => format!("persistent ID {}", pid.summary()),
+        }
+    }
+}
+
+/// The state of the pickle machine when decoding stopped, see
+/// `Deserializer::snapshot`.
+pub(crate) struct Snapshot {
+    pub error: Option<Error>,
+    pub opcodes: Vec<(usize, u8)>, // offset and byte of the last opcodes
+    pub stack: Vec<String>,
+    pub stack_len: usize,
+    pub marks: Vec<usize>, // number of items on each stack below a MARK
+    pub memo: Vec<(MemoId, String)>,
+    pub memo_len: usize,
+}
+
+// Records the opcodes that were executed, for `Deserializer::snapshot`.
+struct Trace {
+    opcodes: VecDeque<(usize, u8)>,
+    history: usize,
+    stop: Option<usize>,
+}
+
+/// The encoding used to decode Python 2 `str` objects, like the `encoding`
+/// argument of Python's `Unpickler`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StrEncoding {
+    /// Keep them as bytes objects (`encoding="bytes"`).
+    #[default]
+    Bytes,
+    /// Decode them as ASCII (`encoding="ASCII"`, Python's default).
+    Ascii,
+    /// Decode them as Latin-1, which can't fail (`encoding="latin1"`).
+    Latin1,
+    /// Decode them as UTF-8 (`encoding="utf-8"`).
+    Utf8,
+}
+
+/// What to do with Python 2 `str` objects that can't be decoded, like the
+/// `errors` argument of Python's `Unpickler`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StrErrors {
+    /// Fail with an error (`errors="strict"`).
+    #[default]
+    Strict,
+    /// Replace undecodable bytes with U+FFFD (`errors="replace"`).
+    Replace,
+    /// Keep the str as a bytes object.
+    Bytes,
+}
+
+/// What to do with strings that contain lone surrogates.  Python pickles
+/// strings with the `surrogatepass` error handler, so they can contain
+/// surrogates (e.g. from `os.fsdecode` of filenames that aren't UTF-8), which
+/// aren't valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Surrogates {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Replace each surrogate with U+FFFD.
+    Replace,
+    /// Keep such strings as `Value::WtfString`, which is written back
+    /// unchanged.
+    Keep,
+}
+
+impl Surrogates {
+    // Decode UTF-8 in which surrogates may be encoded like other code points.
+    fn decode(self, bytes: Vec<u8>) -> std::result::Result<Value, ErrorCode> {
+        let bytes = match String::from_utf8(bytes) {
+            Ok(s) => return Ok(Value::String(SharedFrozen::new(s))),
+            Err(err) => err.into_bytes(),
+        };
+        if self == Surrogates::Error || !only_surrogates(&bytes) {
+            return Err(ErrorCode::StringNotUTF8);
+        }
+        match self {
+            Surrogates::Keep => Ok(Value::WtfString(SharedFrozen::new(bytes))),
+            _ => Ok(Value::String(SharedFrozen::new(wtf8_lossy(&bytes)))),
+        }
+    }
+}
+
+// Check that the only invalid sequences in UTF-8 are encoded surrogates.
+fn only_surrogates(mut bytes: &[u8]) -> bool {
+    while let Err(err) = std::str::from_utf8(bytes) {
+        match bytes[err.valid_up_to()..] {
+            [0xed, 0xa0..=0xbf, 0x80..=0xbf, ..] => bytes = &bytes[err.valid_up_to() + 3..],
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct StrMode {
+    encoding: StrEncoding,
+    errors: StrErrors,
+}
+
+impl StrMode {
+    fn decode(self, bytes: Vec<u8>) -> std::result::Result<Value, ErrorCode> {
+        let string = |s: String| Value::String(SharedFrozen::new(s));
+        let bytes = match self.encoding {
+            StrEncoding::Bytes => return Ok(Value::Bytes(SharedFrozen::new(bytes))),
+            StrEncoding::Latin1 => return Ok(string(bytes.iter().map(|&b| b as char).collect())),
+            StrEncoding::Ascii if bytes.is_ascii() => {
+                return Ok(string(bytes.iter().map(|&b| b as char).collect()));
+            }
+            StrEncoding::Ascii => bytes,
+            StrEncoding::Utf8 => match String::from_utf8(bytes) {
+                Ok(s) => return Ok(string(s)),
+                Err(err) => err.into_bytes(),
+            },
+        };
+        match (self.errors, self.encoding) {
+            (StrErrors::Strict, StrEncoding::Utf8) => Err(ErrorCode::StringNotUTF8),
+            (StrErrors::Strict, _) => Err(ErrorCode::InvalidValue(format!(
+                "str is not ASCII: b\"{}\"",
+                bytes.escape_ascii()
+            ))),
+            (StrErrors::Replace, StrEncoding::Utf8) => {
+                Ok(string(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+            (StrErrors::Replace, _) => Ok(string(
+                (bytes.iter())
+                    .map(|&b| if b.is_ascii() { b as char } else { '\u{fffd}' })
+                    .collect(),
+            )),
+            (StrErrors::Bytes, _) => Ok(Value::Bytes(SharedFrozen::new(bytes))),
+        }
+    }
 }
 
 /// Options for deserializing.
 #[derive(Clone, Debug, Default)]
 pub struct DeOptions {
-    decode_strings: bool,
+    strs: StrMode,
+    str_keys: Option<StrMode>,
+    surrogates: Surrogates,
     replace_unresolved_globals: bool,
     replace_recursive_structures: bool,
     replace_reconstructor_objects_with_dict: bool,
+    keep_objects: bool,
+    enums: BTreeMap<&'static str, PyEnum>,
+    stringify_keys: bool,
 }
 
 impl DeOptions {
@@ -100,8 +424,45 @@ impl DeOptions {
     }
 
     /// Activate decoding strings saved as STRING.
-    pub fn decode_strings(mut self) -> Self {
-        self.decode_strings = true;
+    ///
+    /// This is the same as `str_encoding(StrEncoding::Utf8, StrErrors::Strict)`.
+    pub fn decode_strings(self) -> Self {
+        self.str_encoding(StrEncoding::Utf8, StrErrors::Strict)
+    }
+
+    /// Set how Python 2 `str` objects (saved as STRING, only protocols 0-2)
+    /// are decoded, like the `encoding` and `errors` arguments of Python's
+    /// `Unpickler`.  By default, they are kept as bytes.
+    ///
+    /// Unless set with [`DeOptions::str_key_encoding`], this also applies to
+    /// dict keys and attribute names.
+    pub fn str_encoding(mut self, encoding: StrEncoding, errors: StrErrors) -> Self {
+        self.strs = StrMode { encoding, errors };
+        self
+    }
+
+    /// Set how Python 2 `str` objects used as dict keys (including attribute
+    /// names in object state) are decoded, independently of other strs.
+    ///
+    /// This is what is needed to load e.g. NumPy arrays or datetimes pickled by
+    /// Python 2, where attribute names should become strings, while the raw
+    /// data must stay bytes:
+    ///
+    /// ```
+    /// # use pickled::{DeOptions, StrEncoding, StrErrors};
+    /// let options = DeOptions::new()
+    ///     .str_encoding(StrEncoding::Bytes, StrErrors::Strict)
+    ///     .str_key_encoding(StrEncoding::Ascii, StrErrors::Bytes);
+    /// ```
+    pub fn str_key_encoding(mut self, encoding: StrEncoding, errors: StrErrors) -> Self {
+        self.str_keys = Some(StrMode { encoding, errors });
+        self
+    }
+
+    /// Set what to do with strings that contain lone surrogates.  By default,
+    /// they are an error.
+    pub fn surrogates(mut self, surrogates: Surrogates) -> Self {
+        self.surrogates = surrogates;
         self
     }
 
@@ -122,6 +483,104 @@ impl DeOptions {
         self.replace_reconstructor_objects_with_dict = true;
         self
     }
+
+    /// Activate keeping class instances as `Value::Object` instead of
+    /// replacing them by their state.  Through serde, such objects are
+    /// presented as maps of their class, arguments and state.
+    ///
+    /// This also keeps the `collections` types `OrderedDict`, `defaultdict`,
+    /// `deque` and `Counter`, which are otherwise replaced by plain dicts and
+    /// lists, with their arguments, such as a deque's `maxlen`.  The default
+    /// factory of a `defaultdict` is given as its class path, e.g.
+    /// `"builtins.list"`.  Other references to classes and functions, like
+    /// the class passed to pandas' `_new_Index(cls, data)`, are kept as
+    /// `Value::Global`.
+    ///
+    /// Likewise, UUIDs, paths, IP addresses, ranges, slices and the
+    /// `Ellipsis` and `NotImplemented` singletons are kept as objects instead
+    /// of being replaced by strings and dicts.
+    pub fn keep_objects(mut self) -> Self {
+        self.keep_objects = true;
+        self
+    }
+
+    /// Activate presenting dict keys as strings when decoding through serde,
+    /// so that dicts with e.g. int, tuple or `None` keys can be decoded into
+    /// string-keyed maps.  String keys that look like numbers or booleans can
+    /// then also be decoded into maps with such keys.
+    ///
+    /// Keys are converted like `HashableValue::to_string_key` does, and other
+    /// keys (e.g. tuples) are rendered with their Python representation.
+    pub fn stringify_keys(mut self) -> Self {
+        self.stringify_keys = true;
+        self
+    }
+
+    /// Decode members of the Python `enum.Enum` subclass `class` (given as
+    /// `"module.Name"`) into the Rust enum named `rust_name`, matching the
+    /// member values given by `values` to its variants.
+    ///
+    /// Members of other enum classes are still accepted if their value is a
+    /// string that names a variant.
+    pub fn python_enum(mut self, rust_name: &'static str, class: &str, values: EnumValues) -> Self {
+        self.enums.insert(rust_name, PyEnum::new(class, values));
+        self
+    }
+}
+
+// Whether an object is a joblib `NumpyArrayWrapper`, which is followed in
+// the stream by the data of the array it stands for.
+fn is_joblib_wrapper(object: &Object) -> bool {
+    object.globname == "NumpyArrayWrapper"
+        && matches!(
+            &*object.modname,
+            "joblib.numpy_pickle" | "sklearn.externals.joblib.numpy_pickle"
+        )
+}
+
+/// Decode the raw items of an array in one of the machine formats of
+/// `array._array_reconstructor`: integers of 1 to 8 bytes, floats, or UTF-16
+/// and UTF-32 characters (which become one-character strings).  Odd format
+/// codes above 1 are big-endian.
+fn decode_array(code: i64, bytes: &[u8]) -> Option<Vec<Value>> {
+    fn items<B: ByteOrder>(code: i64, bytes: &[u8]) -> Option<Vec<Value>> {
+        let char = |c: char| Value::String(SharedFrozen::new(c.to_string()));
+        if let 18 | 19 = code {
+            if !bytes.len().is_multiple_of(2) {
+                return None;
+            }
+            let units = bytes.chunks(2).map(B::read_u16);
+            return char::decode_utf16(units)
+                .map(|c| c.ok().map(char))
+                .collect();
+        }
+        let size = match code {
+            0 | 1 => 1,
+            2..=13 => 2 << ((code - 2) / 4),
+            14 | 15 | 20 | 21 => 4,
+            16 | 17 => 8,
+            _ => return None,
+        };
+        if !bytes.len().is_multiple_of(size) {
+            return None;
+        }
+        let item = |b: &[u8]| match code {
+            0 | 2 | 3 | 6 | 7 | 10 | 11 => {
+                let u = B::read_uint(b, size);
+                Some(i64::try_from(u).map_or_else(|_| Value::Int(u.into()), Value::I64))
+            }
+            14 | 15 => Some(Value::F64(B::read_f32(b).into())),
+            16 | 17 => Some(Value::F64(B::read_f64(b))),
+            20 | 21 => char::from_u32(B::read_u32(b)).map(char),
+            _ => Some(Value::I64(B::read_int(b, size))),
+        };
+        bytes.chunks(size).map(item).collect()
+    }
+    if code > 1 && code % 2 == 1 {
+        items::<BigEndian>(code, bytes)
+    } else {
+        items::<LittleEndian>(code, bytes)
+    }
 }
 
 /// Decodes pickle streams into values.
@@ -136,6 +595,10 @@ pub struct Deserializer<R: Read> {
     converted_rc: HashMap<usize, value::Value>, // shared items that have already been converted
     strings_rc: HashMap<Vec<u8>, Value>,
     tuple_rc: BTreeMap<Vec<value::RawHashableValue>, Value>,
+    offsets: HashMap<usize, (usize, Weak<dyn Any>)>, // opcode offsets of containers
+    trace: Option<Trace>,
+    in_object: bool,   // set while deserializing the fields of an object
+    proto: Option<u8>, // protocol of the current pickle, from its PROTO opcode
 }
 
 impl<R: Read> Deserializer<R> {
@@ -152,6 +615,10 @@ impl<R: Read> Deserializer<R> {
             converted_rc: Default::default(),
             strings_rc: Default::default(),
             tuple_rc: Default::default(),
+            offsets: Default::default(),
+            trace: None,
+            in_object: false,
+            proto: None,
         }
     }
 
@@ -206,6 +673,8 @@ impl<R: Read> Deserializer<R> {
     /// ```
     pub fn reset_memo(&mut self) {
         self.memo.clear();
+        // Cached tuples may hold references into the old memo.
+        self.tuple_rc.clear();
     }
 
     /// Decode a Value from this pickle.  This is different from going through
@@ -216,6 +685,39 @@ impl<R: Read> Deserializer<R> {
         self.convert_value(internal_value)
     }
 
+    /// Decode a pickled class, alone or in a tuple, as ZODB pickles the class
+    /// of an object.  Classes are given by their dotted names.
+    pub(crate) fn deserialize_class(&mut self) -> Result<value::Value> {
+        let internal_value = self.parse_value()?;
+        let internal_value = self.class_names(internal_value, &mut Vec::new());
+        self.convert_value(internal_value)
+    }
+
+    /// Run the pickle machine up to the opcode at offset `stop` (or until it
+    /// fails or finishes), and capture its state.  The last `history` opcodes,
+    /// stack items and memo entries are kept.
+    pub(crate) fn snapshot(mut self, stop: Option<usize>, history: usize) -> Snapshot {
+        self.trace = Some(Trace {
+            opcodes: VecDeque::with_capacity(history),
+            history,
+            stop,
+        });
+        let error = self.parse_value().err();
+        let trace = self.trace.take().unwrap();
+        let skip = self.stack.len().saturating_sub(history);
+        Snapshot {
+            error,
+            opcodes: trace.opcodes.into(),
+            stack: self.stack[skip..].iter().map(Value::summary).collect(),
+            stack_len: self.stack.len(),
+            marks: self.stacks.iter().map(Vec::len).collect(),
+            memo: (self.memo.iter().rev().take(history))
+                .map(|(&id, (value, _))| (id, value.summary()))
+                .collect(),
+            memo_len: self.memo.len(),
+        }
+    }
+
     /// Get the next value to deserialize, either by parsing the pickle stream
     /// or from `self.value`.
     fn get_next_value(&mut self) -> Result<Value> {
@@ -263,16 +765,32 @@ impl<R: Read> Deserializer<R> {
     /// Parse a value from the underlying stream.  This will consume the whole
     /// pickle until the STOP opcode.
     fn parse_value(&mut self) -> Result<Value> {
+        self.offsets.clear();
+        // The containers of earlier pickles may be gone, and their addresses
+        // reused.
+        self.converted_rc.clear();
+        self.proto = None;
         loop {
+            let start = self.pos;
+            if let Some(trace) = &self.trace
+                && trace.stop == Some(start)
+            {
+                return Ok(Value::None);
+            }
             let value = self.read_byte()?;
+            if let Some(trace) = &mut self.trace {
+                if trace.opcodes.len() == trace.history {
+                    trace.opcodes.pop_front();
+                }
+                trace.opcodes.push_back((start, value));
+            }
             let opcode = Opcode::try_from(value).map_err(|code| self.inner_error(code))?;
 
             match opcode {
                 // Specials
                 Opcode::Proto => {
-                    // Ignore this, as it is only important for instances (read
-                    // the version byte).
-                    self.read_byte()?;
+                    // Only needed to tell Python 2 pickles from others.
+                    self.proto = Some(self.read_byte()?);
                 }
                 Opcode::Frame => {
                     // We'll ignore framing. But we still have to gobble up the length.
@@ -452,7 +970,7 @@ impl<R: Read> Deserializer<R> {
                 }
                 Opcode::ByteArray8 => {
                     let string = self.read_u64_prefixed_bytes()?;
-                    self.stack.push(Value::Bytes(SharedFrozen::new(string)));
+                    self.stack.push(Value::ByteArray(Shared::new(string)));
                 }
 
                 // Tuples
@@ -550,6 +1068,22 @@ impl<R: Read> Deserializer<R> {
                     let value = self.decode_global(modname, globname)?;
                     self.stack.push(value);
                 }
+                // Persistent IDs, which refer to objects outside the pickle
+                Opcode::PersId => {
+                    let line = self.read_line()?;
+                    let pid = match String::from_utf8(line) {
+                        Ok(pid) => pid,
+                        Err(_) => return self.error(ErrorCode::StringNotUTF8),
+                    };
+                    let pid = Value::String(SharedFrozen::new(pid));
+                    self.stack.push(Value::PersId(Box::new(pid)));
+                }
+                Opcode::BinPersId => {
+                    let pid = self.pop_resolve()?;
+                    let pid = self.class_names(pid, &mut Vec::new());
+                    self.stack.push(Value::PersId(Box::new(pid)));
+                }
+
                 Opcode::Reduce => {
                     let argtuple = match self.pop_resolve()? {
                         Value::Tuple(args) => args,
@@ -559,53 +1093,280 @@ impl<R: Read> Deserializer<R> {
                     self.reduce_global(global, argtuple)?;
                 }
 
-                // Arbitrary classes - make a best effort attempt to recover some data
+                // Arbitrary classes - keep the class and arguments around
                 Opcode::Inst => {
-                    // pop module name and class name
-                    for _ in 0..2 {
-                        self.read_line()?;
-                    }
-                    // pop arguments to init
-                    self.pop_mark()?;
-                    // push empty dictionary instead of the class instance
-                    self.stack.push(Value::Dict(Shared::new(Vec::new())));
+                    let modname = self.read_line()?;
+                    let globname = self.read_line()?;
+                    let class = self.decode_global(modname, globname)?;
+                    let args = self.pop_mark()?;
+                    self.push_object(class, Construct::Inst, args, Vec::new());
                 }
                 Opcode::Obj => {
-                    // pop arguments to init
-                    self.pop_mark()?;
-                    // pop class object
-                    self.pop()?;
-                    self.stack.push(Value::Dict(Shared::new(Vec::new())));
+                    // the class object comes first after the MARK
+                    let mut args = self.pop_mark()?.into_iter();
+                    let class = match self.resolve(args.next()) {
+                        Some(class) => class,
+                        None => return self.error(ErrorCode::StackUnderflow),
+                    };
+                    self.push_object(class, Construct::Inst, args.collect(), Vec::new());
                 }
                 Opcode::NewObj => {
-                    // pop arguments and class object
-                    for _ in 0..2 {
-                        self.pop()?;
-                    }
-                    self.stack.push(Value::Dict(Shared::new(Vec::new())));
+                    let args = self.pop_resolve()?;
+                    let class = self.pop_resolve()?;
+                    let args = self.object_args(args)?;
+                    self.push_object(class, Construct::New, args, Vec::new());
                 }
                 Opcode::NewObjEx => {
-                    // pop keyword args, arguments and class object
-                    for _ in 0..3 {
-                        self.pop()?;
-                    }
-                    self.stack.push(Value::Dict(Shared::new(Vec::new())));
+                    let kwargs = self.pop_resolve()?;
+                    let args = self.pop_resolve()?;
+                    let class = self.pop_resolve()?;
+                    let args = self.object_args(args)?;
+                    let kwargs = self.object_kwargs(kwargs)?;
+                    self.push_object(class, Construct::New, args, kwargs);
                 }
                 Opcode::Build => {
                     // The top-of-stack for BUILD is used either as the instance __dict__,
                     // or an argument for __setstate__, in which case it can be *any* type
-                    // of object.  In both cases, we just replace the standin.
+                    // of object.  Objects keep it as their state, other standins are
+                    // replaced by it.
                     let state = self.pop()?;
                     let obj = self.pop()?; // remove the object standin
 
-                    let _standin = self.resolve(Some(obj.clone()));
+                    let standin = self.resolve(Some(obj.clone()));
+                    // The state of objects with `__slots__` is merged into one
+                    // attribute dict, unless the object is kept.
+                    let state = match self.slot_state(&state) {
+                        Some(attrs) if !self.options.keep_objects => attrs,
+                        Some(attrs) if !matches!(standin, Some(Value::Object(_))) => attrs,
+                        _ => state,
+                    };
 
-                    if let Value::MemoRef(id) = obj {
-                        self.memoize(id, state.clone())?;
-                        self.stack.push(state);
+                    match (standin, self.resolve(Some(state.clone()))) {
+                        (Some(Value::Object(object)), new_state) => {
+                            let old_state = object.inner().state.clone();
+                            match (self.resolve(old_state), new_state) {
+                                // Like `__dict__.update()`, for repeated BUILDs.
+                                // The earlier state may be shared, so it is
+                                // copied instead of updated.
+                                (Some(Value::Dict(old)), Some(Value::Dict(attrs))) => {
+                                    let mut merged = old.inner().clone();
+                                    merged.extend(attrs.inner().iter().cloned());
+                                    object.inner_mut().state =
+                                        Some(Value::Dict(Shared::new(merged)));
+                                }
+                                _ => object.inner_mut().state = Some(state),
+                            }
+                            if is_joblib_wrapper(&object.inner()) {
+                                self.read_joblib_array(&object)?;
+                            }
+                            self.stack.push(obj);
+                        }
+                        // A dict standin (e.g. a SimpleNamespace) gets the new
+                        // attributes merged in, like `__dict__.update()` does.
+                        (Some(Value::Dict(standin)), Some(Value::Dict(attrs))) => {
+                            let attrs = attrs.inner().clone();
+                            standin.inner_mut().extend(attrs);
+                            self.stack.push(obj);
+                        }
+                        _ => {
+                            if let Value::MemoRef(id) = obj {
+                                self.memoize(id, state.clone())?;
+                            }
+                            self.stack.push(state);
+                        }
                     }
                 }
             }
+
+            // Remember where new containers come from, for error messages.
+            if let Some((ptr, weak)) = self.stack.last().and_then(Value::container_ref) {
+                self.offsets.entry(ptr).or_insert((start, weak));
+            }
+        }
+    }
+
+    // Push an instance of `class`.  If the class isn't a global, we can't say
+    // anything about the object, and push an empty dict instead.
+    fn push_object(
+        &mut self,
+        class: Value,
+        construct: Construct,
+        args: Vec<Value>,
+        kwargs: Vec<(Value, Value)>,
+    ) {
+        let value = match class {
+            Value::Global(global) => {
+                let (modname, globname) = global.into_names();
+                Value::Object(Shared::new(Object {
+                    modname,
+                    globname,
+                    construct,
+                    args,
+                    kwargs,
+                    items: None,
+                    state: None,
+                }))
+            }
+            _ => Value::Dict(Shared::new(Vec::new())),
+        };
+        self.stack.push(value);
+    }
+
+    // Look through a memo reference without counting it as a use.
+    fn peek(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::MemoRef(id) => self.memo.get(id).map(|(value, _)| value.clone()),
+            other => Some(other.clone()),
+        }
+    }
+
+    // Merge a `(dict_or_None, slots_dict)` state, as `__getstate__` returns
+    // for classes with `__slots__`, into one attribute dict.
+    fn slot_state(&self, state: &Value) -> Option<Value> {
+        let Value::Tuple(pair) = self.peek(state)? else {
+            return None;
+        };
+        let [dict, slots] = &pair.inner()[..] else {
+            return None;
+        };
+        let mut attrs = match self.peek(dict)? {
+            Value::Dict(dict) => dict.inner().clone(),
+            Value::None => Vec::new(),
+            _ => return None,
+        };
+        match self.peek(slots)? {
+            Value::Dict(slots) => attrs.extend(slots.inner().iter().cloned()),
+            _ => return None,
+        }
+        Some(Value::Dict(Shared::new(attrs)))
+    }
+
+    // Look up an attribute in an instance `__dict__` state.
+    fn state_item(&self, state: &Value, name: &str) -> Option<Value> {
+        let Value::Dict(state) = self.peek(state)? else {
+            return None;
+        };
+        state.inner().iter().find_map(|(key, value)| {
+            match self.decode_key(self.peek(key)?).ok()? {
+                Value::String(s) if s.inner() == name => self.peek(value),
+                Value::Bytes(b) if b.inner() == name.as_bytes() => self.peek(value),
+                _ => None,
+            }
+        })
+    }
+
+    // The plain value of an instance of a common standard library type: a
+    // string for UUIDs, paths and IP addresses, and a dict of `start`, `stop`
+    // and `step` for ranges and slices.
+    fn stdlib_value(&self, object: &Object) -> Option<Value> {
+        let string = |s: String| Value::String(SharedFrozen::new(s));
+        let text = |value: &Value| match self.peek(value)? {
+            Value::String(s) => Some(s.inner().clone()),
+            _ => None,
+        };
+        let args = &object.args;
+        let value = match (&*object.modname, &*object.globname) {
+            ("uuid", "UUID") => {
+                let int = match self.state_item(object.state.as_ref()?, "int")? {
+                    Value::I64(i) => u128::try_from(i).ok()?,
+                    Value::Int(i) => u128::try_from(&i).ok()?,
+                    _ => return None,
+                };
+                let hex = format!("{int:032x}");
+                let (a, b, c, d, e) = (
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..],
+                );
+                string(format!("{a}-{b}-{c}-{d}-{e}"))
+            }
+            ("pathlib", name) if name.ends_with("Path") => {
+                // Paths are pickled with their parts, the first of which may
+                // be the root.
+                let sep = if name.contains("Windows") { '\\' } else { '/' };
+                let mut path = String::new();
+                for part in args {
+                    if !path.is_empty() && !path.ends_with(sep) {
+                        path.push(sep);
+                    }
+                    path.push_str(&text(part)?);
+                }
+                string(path)
+            }
+            ("ipaddress", name) if name.starts_with("IPv") => {
+                let [arg] = &args[..] else {
+                    return None;
+                };
+                // Addresses are pickled as integers or strings, networks and
+                // interfaces as strings.
+                match (name, self.peek(arg)?) {
+                    ("IPv4Address", Value::I64(i)) => {
+                        string(std::net::Ipv4Addr::from(u32::try_from(i).ok()?).to_string())
+                    }
+                    ("IPv6Address", Value::I64(i)) => {
+                        string(std::net::Ipv6Addr::from(u128::try_from(i).ok()?).to_string())
+                    }
+                    ("IPv6Address", Value::Int(i)) => {
+                        string(std::net::Ipv6Addr::from(u128::try_from(&i).ok()?).to_string())
+                    }
+                    (_, Value::String(s)) => Value::String(s),
+                    _ => return None,
+                }
+            }
+            ("builtins", "range" | "slice") => {
+                let [start, stop, step] = &args[..] else {
+                    return None;
+                };
+                let key = |name: &str| string(name.into());
+                Value::Dict(Shared::new(vec![
+                    (key("start"), start.clone()),
+                    (key("stop"), stop.clone()),
+                    (key("step"), step.clone()),
+                ]))
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // The value of the `Ellipsis` and `NotImplemented` singletons, which are
+    // pickled as bare globals: their name, or an object without arguments.
+    fn singleton_value(&self, global: &Global) -> Option<Value> {
+        let Global::Other { modname, globname } = global else {
+            return None;
+        };
+        if modname != "builtins" || !matches!(&**globname, "Ellipsis" | "NotImplemented") {
+            return None;
+        }
+        if !self.options.keep_objects {
+            return Some(Value::String(SharedFrozen::new(globname.to_string())));
+        }
+        Some(Value::Object(Shared::new(Object {
+            modname: modname.clone(),
+            globname: globname.clone(),
+            construct: Construct::Reduce,
+            args: Vec::new(),
+            kwargs: Vec::new(),
+            items: None,
+            state: None,
+        })))
+    }
+
+    // Get the items of the argument tuple for creating an object.
+    fn object_args(&self, args: Value) -> Result<Vec<Value>> {
+        match args {
+            Value::Tuple(args) => Ok(args.into_raw_or_cloned()),
+            other => Self::stack_error("tuple", &other, self.pos),
+        }
+    }
+
+    // Get the items of the keyword argument dict for creating an object.
+    fn object_kwargs(&self, kwargs: Value) -> Result<Vec<(Value, Value)>> {
+        match kwargs {
+            Value::Dict(kwargs) => Ok(kwargs.into_raw_or_cloned()),
+            other => Self::stack_error("dict", &other, self.pos),
         }
     }
 
@@ -946,7 +1707,8 @@ impl<R: Read> Deserializer<R> {
     // which only knows the \uXXXX and \UYYYYYYYY escapes. The backslash is escaped
     // in this way, too.
     fn decode_escaped_unicode(&self, s: &[u8]) -> Result<Value> {
-        let mut result = String::with_capacity(s.len());
+        let mut result = Vec::with_capacity(s.len());
+        let mut surrogates = false;
         let mut iter = s.iter();
         while let Some(&b) = iter.next() {
             match b {
@@ -965,31 +1727,55 @@ impl<R: Read> Deserializer<R> {
                         }
                     }
                     match char::from_u32(accum) {
-                        Some(v) => result.push(v),
+                        Some(v) => result.extend_from_slice(v.encode_utf8(&mut [0; 4]).as_bytes()),
+                        // Encode surrogates like other code points, for the
+                        // surrogate policy to decide.
+                        None if (0xd800..0xe000).contains(&accum) => {
+                            result.extend([
+                                0xe0 | (accum >> 12) as u8,
+                                0x80 | (accum >> 6 & 0x3f) as u8,
+                                0x80 | (accum & 0x3f) as u8,
+                            ]);
+                            surrogates = true;
+                        }
                         None => return self.error(ErrorCode::InvalidLiteral(s.into())),
                     }
                 }
-                _ => result.push(b as char),
+                _ => result.extend_from_slice((b as char).encode_utf8(&mut [0; 4]).as_bytes()),
             }
         }
-        Ok(Value::String(SharedFrozen::new(result)))
+        if surrogates && self.options.surrogates == Surrogates::Error {
+            return self.error(ErrorCode::InvalidLiteral(s.into()));
+        }
+        self.decode_unicode(result)
     }
 
-    // Decode a string - either as Unicode or as bytes.
+    // Decode a Python 2 str - either as Unicode or as bytes.  If keys are
+    // decoded differently, this is decided once we know how it's used.
     fn decode_string(&self, string: Vec<u8>) -> Result<Value> {
-        if self.options.decode_strings {
-            self.decode_unicode(string)
-        } else {
-            Ok(Value::Bytes(SharedFrozen::new(string)))
+        match self.options.str_keys {
+            Some(keys) if keys != self.options.strs => Ok(Value::Str(SharedFrozen::new(string))),
+            _ => (self.options.strs.decode(string)).map_err(|code| self.inner_error(code)),
         }
     }
 
-    // Decode a Unicode string from UTF-8.
+    // Decode a Python 2 str (also behind a memo reference) used as a dict key.
+    fn decode_key(&self, key: Value) -> Result<Value> {
+        let string = match key {
+            Value::Str(ref string) => string.inner().clone(),
+            Value::MemoRef(id) => match self.memo.get(&id) {
+                Some((Value::Str(string), _)) => string.inner().clone(),
+                _ => return Ok(key),
+            },
+            _ => return Ok(key),
+        };
+        let mode = self.options.str_keys.unwrap_or(self.options.strs);
+        (mode.decode(string)).map_err(|code| self.inner_error(code))
+    }
+
+    // Decode a Unicode string from UTF-8, which may contain surrogates.
     fn decode_unicode(&self, string: Vec<u8>) -> Result<Value> {
-        match String::from_utf8(string) {
-            Ok(v) => Ok(Value::String(SharedFrozen::new(v))),
-            Err(_) => self.error(ErrorCode::StringNotUTF8),
-        }
+        (self.options.surrogates.decode(string)).map_err(|code| self.inner_error(code))
     }
 
     // Decode a binary-encoded long integer.
@@ -1018,6 +1804,16 @@ impl<R: Read> Deserializer<R> {
                 f(&mut list);
                 return Ok(());
             }
+            // Instances of list subclasses keep their items separately.
+            Value::Object(ref object) => {
+                let mut object = object.inner_mut();
+                let items =
+                    (object.items).get_or_insert_with(|| Value::List(Shared::new(Vec::new())));
+                if let Value::List(list) = items {
+                    f(&mut list.inner_mut());
+                    return Ok(());
+                }
+            }
             _ => {
                 // Fallthrough to error
             }
@@ -1050,6 +1846,16 @@ impl<R: Read> Deserializer<R> {
                 f(&mut dict);
                 return Ok(());
             }
+            // Instances of dict subclasses keep their items separately.
+            Value::Object(ref object) => {
+                let mut object = object.inner_mut();
+                let items =
+                    (object.items).get_or_insert_with(|| Value::Dict(Shared::new(Vec::new())));
+                if let Value::Dict(dict) = items {
+                    f(&mut dict.inner_mut());
+                    return Ok(());
+                }
+            }
             _ => {
                 // Fallthrough to error
             }
@@ -1065,40 +1871,62 @@ impl<R: Read> Deserializer<R> {
     {
         let pos = self.pos;
         let top = self.top()?;
-        if let Value::Set(ref set) = *top {
-            let mut set = set.inner_mut();
-            f(&mut set);
-            Ok(())
-        } else {
-            Self::stack_error("set", top, pos)
+        match *top {
+            Value::Set(ref set) => {
+                let mut set = set.inner_mut();
+                f(&mut set);
+                return Ok(());
+            }
+            // Instances of set subclasses keep their items separately.
+            Value::Object(ref object) => {
+                let mut object = object.inner_mut();
+                let items =
+                    (object.items).get_or_insert_with(|| Value::Set(Shared::new(Vec::new())));
+                if let Value::Set(set) = items {
+                    f(&mut set.inner_mut());
+                    return Ok(());
+                }
+            }
+            _ => {}
         }
+        Self::stack_error("set", top, pos)
     }
 
     // Push the Value::Global referenced by modname and globname.
     fn decode_global(&mut self, modname: Vec<u8>, globname: Vec<u8>) -> Result<Value> {
-        let value = match (&*modname, &*globname) {
-            (b"_codecs", b"encode") => Value::Global(Global::Encode),
-            (b"__builtin__", b"set") | (b"builtins", b"set") => Value::Global(Global::Set),
-            (b"__builtin__", b"frozenset") | (b"builtins", b"frozenset") => {
-                Value::Global(Global::Frozenset)
-            }
-            (b"__builtin__", b"list") | (b"builtins", b"list") => Value::Global(Global::List),
-            (b"__builtin__", b"bytearray") | (b"builtins", b"bytearray") => {
-                Value::Global(Global::Bytearray)
-            }
-            (b"__builtin__", b"int") | (b"builtins", b"int") => Value::Global(Global::Int),
-            (b"copy_reg", b"_reconstructor") => Value::Global(Global::Reconstructor),
-            _ => {
-                let modname = String::from_utf8(modname)
-                    .map_err(|_| self.inner_error(ErrorCode::StringNotUTF8))?;
-                let globname = String::from_utf8(globname)
-                    .map_err(|_| self.inner_error(ErrorCode::StringNotUTF8))?;
-
-                Value::Global(Global::Other {
-                    modname: Cow::Owned(modname),
-                    globname: Cow::Owned(globname),
-                })
-            }
+        let modname =
+            String::from_utf8(modname).map_err(|_| self.inner_error(ErrorCode::StringNotUTF8))?;
+        let globname =
+            String::from_utf8(globname).map_err(|_| self.inner_error(ErrorCode::StringNotUTF8))?;
+        // Like Python, map Python 2 names to their Python 3 equivalents only
+        // in pickles of protocols below 3, which Python 2 can write.
+        let names = if self.proto.is_none_or(|proto| proto < 3) {
+            compat::to_py3(&modname, &globname)
+        } else {
+            (&*modname, &*globname)
+        };
+        let value = match names {
+            ("_codecs", "encode") => Value::Global(Global::Encode),
+            ("builtins", "set") => Value::Global(Global::Set),
+            ("builtins", "frozenset") => Value::Global(Global::Frozenset),
+            ("builtins", "list") => Value::Global(Global::List),
+            ("builtins", "bytearray") => Value::Global(Global::Bytearray),
+            ("builtins", "int") => Value::Global(Global::Int),
+            ("copyreg", "_reconstructor") => Value::Global(Global::Reconstructor),
+            ("types", "SimpleNamespace") => Value::Global(Global::Namespace),
+            ("copyreg", "__newobj__") => Value::Global(Global::NewObj),
+            ("copyreg", "__newobj_ex__") => Value::Global(Global::NewObjEx),
+            ("collections", "OrderedDict") => Value::Global(Global::OrderedDict),
+            ("collections", "defaultdict") => Value::Global(Global::DefaultDict),
+            ("collections", "deque") => Value::Global(Global::Deque),
+            ("collections", "Counter") => Value::Global(Global::Counter),
+            ("builtins", "getattr") => Value::Global(Global::Getattr),
+            ("array", "array") => Value::Global(Global::Array),
+            ("array", "_array_reconstructor") => Value::Global(Global::ArrayRecon),
+            (mapped_mod, mapped_glob) => Value::Global(Global::Other {
+                modname: Cow::Owned(mapped_mod.to_owned()),
+                globname: Cow::Owned(mapped_glob.to_owned()),
+            }),
         };
         Ok(value)
     }
@@ -1112,6 +1940,10 @@ impl<R: Read> Deserializer<R> {
                     self.stack.push(Value::Set(items));
                     Ok(())
                 }
+                Some(Value::Tuple(items)) => {
+                    self.stack.push(Value::Set(items.into()));
+                    Ok(())
+                }
                 _ => self.error(ErrorCode::InvalidValue("set() arg".into())),
             },
             Value::Global(Global::Frozenset) => match self.resolve(argtuple.pop()) {
@@ -1119,20 +1951,25 @@ impl<R: Read> Deserializer<R> {
                     self.stack.push(Value::FrozenSet(items.into()));
                     Ok(())
                 }
+                Some(Value::Tuple(items)) => {
+                    self.stack.push(Value::FrozenSet(items));
+                    Ok(())
+                }
                 _ => self.error(ErrorCode::InvalidValue("frozenset() arg".into())),
             },
             Value::Global(Global::Bytearray) => {
                 // On Py2, the call is encoded as bytearray(u"foo", "latin-1").
                 argtuple.truncate(1);
                 match self.resolve(argtuple.pop()) {
-                    Some(Value::Bytes(bytes)) => {
-                        self.stack.push(Value::Bytes(bytes));
+                    Some(Value::Bytes(bytes) | Value::Str(bytes)) => {
+                        self.stack
+                            .push(Value::ByteArray(Shared::new(bytes.into_raw_or_cloned())));
                         Ok(())
                     }
                     Some(Value::String(string)) => {
                         // The code points in the string are actually bytes values.
                         // So we need to collect them individually.
-                        self.stack.push(Value::Bytes(SharedFrozen::new(
+                        self.stack.push(Value::ByteArray(Shared::new(
                             string.inner().chars().map(|ch| ch as u32 as u8).collect(),
                         )));
                         Ok(())
@@ -1174,37 +2011,249 @@ impl<R: Read> Deserializer<R> {
                 }
             }
             Value::Global(Global::Reconstructor) => {
-                let _args = self.resolve(argtuple.pop());
-                let _func = self.resolve(argtuple.pop());
-
-                let value = if self.options.replace_reconstructor_objects_with_dict {
-                    Value::Dict(Shared::new(Default::default()))
-                } else {
+                // copyreg._reconstructor(cls, base, state): for subclasses of
+                // containers like list and dict, `state` holds the contents.
+                let state = self.resolve(argtuple.pop());
+                let _base = self.resolve(argtuple.pop());
+                let class = self.resolve(argtuple.pop());
+
+                let value = match (class, state) {
+                    (
+                        Some(Value::Global(global)),
+                        Some(items @ (Value::List(_) | Value::Dict(_) | Value::Set(_))),
+                    ) => {
+                        let (modname, globname) = global.into_names();
+                        Value::Object(Shared::new(Object {
+                            modname,
+                            globname,
+                            construct: Construct::New,
+                            args: Vec::new(),
+                            kwargs: Vec::new(),
+                            items: Some(items),
+                            state: None,
+                        }))
+                    }
+                    _ if self.options.replace_reconstructor_objects_with_dict => {
+                        Value::Dict(Shared::new(Default::default()))
+                    }
                     // If the user doesn't want to replace reconstructor objects, transition this to an unresolved global
                     // so that we can bubble up unresolved global errors.
-                    Value::Global(Global::Other {
-                        modname: Cow::Borrowed("copy_reg"),
+                    _ => Value::Global(Global::Other {
+                        modname: Cow::Borrowed("copyreg"),
                         globname: Cow::Borrowed("_reconstructor"),
-                    })
+                    }),
                 };
 
                 self.stack.push(value);
                 Ok(())
             }
-            Value::Global(Global::Other { modname, globname }) => {
-                // Anything else; just keep it on the stack as an opaque object.
-                // If it is a class object, it will get replaced later when the
-                // class is instantiated.
-                self.stack
-                    .push(Value::Global(Global::Other { modname, globname }));
+            Value::Global(Global::Namespace) => {
+                // SimpleNamespace objects are replaced by their attribute dict,
+                // which is usually filled in by the following BUILD.
+                let attrs = match self.resolve(argtuple.pop()) {
+                    Some(Value::Dict(items)) => items.inner().clone(),
+                    _ => Vec::new(),
+                };
+                self.stack.push(Value::Dict(Shared::new(attrs)));
+                Ok(())
+            }
+            Value::Global(Global::NewObj) => {
+                // cls.__new__(cls, *args), as NEWOBJ does
+                let mut args = argtuple.into_iter();
+                let class = match self.resolve(args.next()) {
+                    Some(class) => class,
+                    None => return self.error(ErrorCode::InvalidValue("__newobj__() arg".into())),
+                };
+                self.push_object(class, Construct::New, args.collect(), Vec::new());
+                Ok(())
+            }
+            Value::Global(Global::NewObjEx) => {
+                // cls.__new__(cls, *args, **kwargs), as NEWOBJ_EX does
+                let kwargs = self.resolve(argtuple.pop());
+                let args = self.resolve(argtuple.pop());
+                let class = self.resolve(argtuple.pop());
+                let (Some(class), Some(args), Some(kwargs)) = (class, args, kwargs) else {
+                    return self.error(ErrorCode::InvalidValue("__newobj_ex__() arg".into()));
+                };
+                let args = self.object_args(args)?;
+                let kwargs = self.object_kwargs(kwargs)?;
+                self.push_object(class, Construct::New, args, kwargs);
+                Ok(())
+            }
+            Value::Global(Global::Getattr) => {
+                // getattr(cls, name) is how protocols before 4 refer to nested
+                // names, like methods or nested classes.
+                let name = argtuple.get(1).cloned().map(|name| self.decode_key(name));
+                match (
+                    self.resolve(argtuple.first().cloned()),
+                    self.resolve(name.transpose()?),
+                ) {
+                    (Some(Value::Global(owner)), Some(Value::String(name))) => {
+                        let (modname, globname) = owner.into_names();
+                        let globname = format!("{globname}.{}", name.inner());
+                        self.stack.push(Value::Global(Global::Other {
+                            modname,
+                            globname: globname.into(),
+                        }));
+                    }
+                    _ => self.push_object(
+                        Value::Global(Global::Getattr),
+                        Construct::Reduce,
+                        argtuple,
+                        Vec::new(),
+                    ),
+                }
+                Ok(())
+            }
+            Value::Global(
+                global @ (Global::OrderedDict
+                | Global::DefaultDict
+                | Global::Deque
+                | Global::Counter),
+            ) => {
+                self.reduce_collection(global, argtuple);
+                Ok(())
+            }
+            Value::Global(Global::Array) => self.reduce_array(argtuple),
+            Value::Global(Global::ArrayRecon) => self.reconstruct_array(argtuple),
+            Value::Global(global @ Global::Other { .. }) => {
+                // Anything else; keep it as an object, which is replaced by its
+                // state if it gets one.
+                self.push_object(
+                    Value::Global(global),
+                    Construct::Reduce,
+                    argtuple,
+                    Vec::new(),
+                );
                 Ok(())
             }
             other => Self::stack_error("global reference", &other, self.pos),
         }
     }
 
+    // Handle the REDUCE opcode for the `collections` types.  Their items
+    // usually follow with SETITEMS or APPENDS.
+    fn reduce_collection(&mut self, global: Global, mut args: Vec<Value>) {
+        if self.options.keep_objects {
+            // The default factory is kept by name, since globals aren't values.
+            if let (Global::DefaultDict, Some(factory)) = (&global, args.first_mut())
+                && let Some(Value::Global(class)) = self.resolve(Some(factory.clone()))
+            {
+                let (modname, globname) = class.into_names();
+                let path = format!("{modname}.{globname}");
+                *factory = Value::String(SharedFrozen::new(path));
+            }
+            self.push_object(Value::Global(global), Construct::Reduce, args, Vec::new());
+            return;
+        }
+        let first = args.into_iter().next();
+        let value = match (global, self.resolve(first)) {
+            // Counter(dict), and OrderedDict([[key, value], ...]) from Python 2
+            (Global::Counter, Some(Value::Dict(items))) => Value::Dict(items),
+            (Global::OrderedDict, Some(Value::List(pairs))) => {
+                let mut items = Vec::new();
+                for pair in pairs.inner().iter() {
+                    match self.resolve(Some(pair.clone())) {
+                        Some(Value::List(pair)) => items.extend(pair.inner().iter().cloned()),
+                        Some(Value::Tuple(pair)) => items.extend(pair.inner().iter().cloned()),
+                        _ => {}
+                    }
+                }
+                let mut dict = Vec::new();
+                Self::extend_dict(&mut dict, items);
+                Value::Dict(Shared::new(dict))
+            }
+            // deque(iterable, maxlen)
+            (Global::Deque, Some(Value::List(items))) => Value::List(items),
+            (Global::Deque, Some(Value::Tuple(items))) => Value::List(items.into()),
+            (Global::Deque, _) => Value::List(Shared::new(Vec::new())),
+            _ => Value::Dict(Shared::new(Vec::new())),
+        };
+        self.stack.push(value);
+    }
+
+    // Handle `array.array(typecode, items)`, as pickled by Python 2, and by
+    // Python 3 for protocols before 3.
+    fn reduce_array(&mut self, args: Vec<Value>) -> Result<()> {
+        let typecode = self.array_typecode(args.first().cloned())?;
+        let items = match self.resolve(args.get(1).cloned()) {
+            None => Vec::new(),
+            Some(Value::List(items)) => items.inner().clone(),
+            Some(Value::Tuple(items)) => items.inner().clone(),
+            Some(other) => return Self::stack_error("list", &other, self.pos),
+        };
+        self.push_array(typecode, items)
+    }
+
+    // Handle `array._array_reconstructor(cls, typecode, mformat_code, bytes)`,
+    // as pickled by Python 3 for protocols 3 and up.
+    fn reconstruct_array(&mut self, args: Vec<Value>) -> Result<()> {
+        let [_, typecode, code, bytes] = &args[..] else {
+            return self.error(ErrorCode::InvalidValue(
+                "_array_reconstructor() args".into(),
+            ));
+        };
+        let typecode = self.array_typecode(Some(typecode.clone()))?;
+        let code = match self.resolve(Some(code.clone())) {
+            Some(Value::I64(code)) => code,
+            Some(other) => return Self::stack_error("int", &other, self.pos),
+            None => return self.error(ErrorCode::InvalidValue("array machine format".into())),
+        };
+        let items = match self.resolve(Some(bytes.clone())) {
+            Some(Value::Bytes(bytes)) => decode_array(code, bytes.inner()),
+            Some(other) => return Self::stack_error("bytes", &other, self.pos),
+            None => None,
+        };
+        match items {
+            Some(items) => self.push_array(typecode, items),
+            None => self.error(ErrorCode::InvalidValue(format!(
+                "array('{typecode}') in machine format {code}"
+            ))),
+        }
+    }
+
+    fn array_typecode(&mut self, typecode: Option<Value>) -> Result<String> {
+        match self.resolve(typecode) {
+            Some(Value::String(s)) => Ok(s.inner().clone()),
+            Some(Value::Str(s) | Value::Bytes(s)) => Ok(String::from_utf8_lossy(s.inner()).into()),
+            Some(other) => Self::stack_error("array typecode", &other, self.pos),
+            None => self.error(ErrorCode::InvalidValue("array() arg".into())),
+        }
+    }
+
+    // Push an array: by default its items as a list, or as a string for
+    // Unicode arrays; kept as an object, it is normalized to the
+    // `array.array(typecode, items)` form that both Python 2 and 3 load.
+    fn push_array(&mut self, typecode: String, items: Vec<Value>) -> Result<()> {
+        if self.options.keep_objects {
+            let args = vec![
+                Value::String(SharedFrozen::new(typecode)),
+                Value::List(Shared::new(items)),
+            ];
+            self.push_object(
+                Value::Global(Global::Array),
+                Construct::Reduce,
+                args,
+                Vec::new(),
+            );
+        } else if matches!(&*typecode, "u" | "w") {
+            let mut string = String::new();
+            for item in items {
+                match self.resolve(Some(item)) {
+                    Some(Value::String(c)) => string.push_str(c.inner()),
+                    Some(other) => return Self::stack_error("character", &other, self.pos),
+                    None => return self.error(ErrorCode::InvalidValue("array item".into())),
+                }
+            }
+            self.stack.push(Value::String(SharedFrozen::new(string)));
+        } else {
+            self.stack.push(Value::List(Shared::new(items)));
+        }
+        Ok(())
+    }
+
     fn stack_error<T>(what: &'static str, value: &Value, pos: usize) -> Result<T> {
-        let it = format!("{value:?}");
+        let it = value.summary();
         Err(Error::Eval(ErrorCode::InvalidStackTop(what, it), pos))
     }
 
@@ -1216,6 +2265,47 @@ impl<R: Read> Deserializer<R> {
         Error::Eval(reason, self.pos)
     }
 
+    // Look up the offset of the opcode that created a container.
+    fn offset_of(&self, value: &Value) -> Option<usize> {
+        let (ptr, _) = value.container_ref()?;
+        self.offsets.get(&ptr).map(|&(offset, _)| offset)
+    }
+
+    // Give a container made up from an object the offset of the object.
+    fn inherit_offset(&mut self, value: &Value, offset: Option<usize>) {
+        if let (Some((ptr, weak)), Some(offset)) = (value.container_ref(), offset) {
+            self.offsets.insert(ptr, (offset, weak));
+        }
+    }
+
+    // Render a dict key as a segment of an error path: identifiers as they
+    // are, other keys as `[repr]`.
+    fn path_segment(&mut self, key: Value) -> String {
+        let key = match self.decode_key(key) {
+            Ok(Value::MemoRef(id)) => match self.memo.get(&id) {
+                Some((key, _)) => key.clone(),
+                None => return "[?]".into(),
+            },
+            Ok(key) => key,
+            Err(_) => return "[?]".into(),
+        };
+        match key {
+            Value::String(ref s) => {
+                let s = s.inner();
+                if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return s.clone();
+                }
+                return format!("[{s:?}]");
+            }
+            Value::Bytes(ref b) => return format!("[b\"{}\"]", b.inner().escape_ascii()),
+            _ => {}
+        }
+        match self.convert_value(key) {
+            Ok(key) => format!("[{key}]"),
+            Err(_) => "[?]".into(),
+        }
+    }
+
     fn convert_value(&mut self, value: Value) -> Result<value::Value> {
         match value {
             Value::None => Ok(value::Value::None),
@@ -1230,7 +2320,13 @@ impl<R: Read> Deserializer<R> {
             }
             Value::F64(v) => Ok(value::Value::F64(v)),
             Value::Bytes(v) => Ok(value::Value::Bytes(v)),
+            Value::ByteArray(v) => Ok(value::Value::ByteArray(v)),
+            Value::Str(v) => {
+                let decoded = self.options.strs.decode(v.into_raw_or_cloned());
+                self.convert_value(decoded.map_err(Error::Syntax)?)
+            }
             Value::String(v) => Ok(value::Value::String(v)),
+            Value::WtfString(v) => Ok(value::Value::WtfString(v)),
             Value::List(v) => {
                 let inner_ptr = v.provenance();
 
@@ -1301,7 +2397,8 @@ impl<R: Read> Deserializer<R> {
                 let v = v.inner();
                 for (key, value) in v.iter() {
                     let real_key = self
-                        .convert_value(key.clone())
+                        .decode_key(key.clone())
+                        .and_then(|key| self.convert_value(key))
                         .and_then(|rv| rv.into_hashable())?;
 
                     let real_value = self.convert_value(value.clone())?;
@@ -1315,9 +2412,54 @@ impl<R: Read> Deserializer<R> {
 
                 Ok(new_value)
             }
+            Value::Object(v) => {
+                let object = v.inner().clone();
+                if let Some(array) = self.ndarray(&object)? {
+                    return Ok(value::Value::NdArray(Shared::new(array)));
+                }
+                if self.options.keep_objects {
+                    return Ok(value::Value::Object(Shared::new(
+                        self.convert_object(object)?,
+                    )));
+                }
+                if let Some(value) = self.stdlib_value(&object) {
+                    // The value is made up here, so its address mustn't stay
+                    // in the cache of converted containers.
+                    let ptr = value.container_ref().map(|(ptr, _)| ptr);
+                    let converted = self.convert_value(value);
+                    if let Some(ptr) = ptr {
+                        self.converted_rc.remove(&ptr);
+                    }
+                    return converted;
+                }
+                // Instances of container subclasses are replaced by their
+                // contents.
+                if let Some(items) = object.items {
+                    return self.convert_value(items);
+                }
+                match object.state {
+                    Some(state) => self.convert_value(state),
+                    None if object.construct == Construct::Reduce => {
+                        if self.options.replace_unresolved_globals {
+                            Ok(value::Value::None)
+                        } else {
+                            Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
+                        }
+                    }
+                    // Objects created by `__new__` only from arguments, like
+                    // namedtuples, are replaced by the argument tuple.
+                    None if object.construct == Construct::New && !object.args.is_empty() => {
+                        self.convert_value(Value::Tuple(SharedFrozen::new(object.args)))
+                    }
+                    None => Ok(value::Value::Dict(Shared::new(BTreeMap::new()))),
+                }
+            }
             Value::MemoRef(memo_id) => {
                 self.resolve_recursive(memo_id, (), |slf, (), value| slf.convert_value(value))
             }
+            Value::PersId(pid) => Ok(value::Value::PersistentId(Box::new(
+                self.convert_value(*pid)?,
+            ))),
             Value::Global(Global::Reconstructor) => {
                 // TODO: This I _think_ is unreachable? Global::Reconstructor instances should have been
                 // reduced to an empty dict by this point
@@ -1327,8 +2469,13 @@ impl<R: Read> Deserializer<R> {
                     Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
                 }
             }
-            Value::Global(_) => {
-                if self.options.replace_unresolved_globals {
+            Value::Global(global) => {
+                if let Some(value) = self.singleton_value(&global) {
+                    self.convert_value(value)
+                } else if self.options.keep_objects {
+                    let (modname, globname) = global.into_names();
+                    Ok(value::Value::Global(modname.into(), globname.into()))
+                } else if self.options.replace_unresolved_globals {
                     Ok(value::Value::None)
                 } else {
                     Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
@@ -1336,6 +2483,317 @@ impl<R: Read> Deserializer<R> {
             }
         }
     }
+
+    fn convert_object(&mut self, object: Object) -> Result<value::Object> {
+        let args = object
+            .args
+            .into_iter()
+            .map(|v| self.convert_value(v))
+            .collect::<Result<_>>()?;
+        let mut kwargs = BTreeMap::new();
+        for (key, value) in object.kwargs {
+            let key = (self.decode_key(key))
+                .and_then(|key| self.convert_value(key))
+                .and_then(|rv| rv.into_hashable())?;
+            kwargs.insert(key, self.convert_value(value)?);
+        }
+        let items = match object.items {
+            Some(items) => Some(self.convert_value(items)?),
+            None => None,
+        };
+        let state = match object.state {
+            Some(state) => Some(self.convert_value(state)?),
+            None => None,
+        };
+        Ok(value::Object {
+            module: object.modname.into_owned(),
+            name: object.globname.into_owned(),
+            new: object.construct == Construct::New,
+            args,
+            kwargs,
+            items,
+            state,
+        })
+    }
+
+    // Persistent IDs are often tuples naming a class, like PyTorch's
+    // `('storage', torch.FloatStorage, key, location, numel)` or ZODB's `(oid,
+    // class)` and `['m', (database, oid, class)]`; the class is kept by name,
+    // since globals are only values with `keep_objects`.  `path` holds the
+    // memo IDs of the containers being visited, to stop at recursive ones.
+    fn class_names(&self, value: Value, path: &mut Vec<MemoId>) -> Value {
+        let id = match value {
+            Value::MemoRef(id) if path.contains(&id) => return value,
+            Value::MemoRef(id) => Some(id),
+            _ => None,
+        };
+        let Some(resolved) = self.peek(&value) else {
+            return value;
+        };
+        path.extend(id);
+        let names = match resolved {
+            Value::Global(global) => {
+                let (modname, globname) = global.into_names();
+                Value::String(SharedFrozen::new(format!("{modname}.{globname}")))
+            }
+            Value::Tuple(items) => Value::Tuple(SharedFrozen::new(
+                (items.inner().iter())
+                    .map(|item| self.class_names(item.clone(), path))
+                    .collect(),
+            )),
+            Value::List(items) => Value::List(Shared::new(
+                (items.inner().iter())
+                    .map(|item| self.class_names(item.clone(), path))
+                    .collect(),
+            )),
+            _ => value,
+        };
+        if id.is_some() {
+            path.pop();
+        }
+        names
+    }
+
+    // Read the data of the array a joblib `NumpyArrayWrapper` stands for,
+    // which joblib writes right after the wrapper's BUILD, and attach it as
+    // the wrapper's argument.  Object arrays are written as a separate
+    // pickle, other arrays as raw bytes, optionally preceded by padding to
+    // align them.
+    fn read_joblib_array(&mut self, wrapper: &Shared<Object>) -> Result<()> {
+        let state = wrapper.inner().state.clone().unwrap_or(Value::None);
+        let item = |name| {
+            self.state_item(&state, name).ok_or_else(|| {
+                Error::Syntax(ErrorCode::Structure(format!("joblib array without {name}")))
+            })
+        };
+        let (shape, dtype) = (item("shape")?, item("dtype")?);
+        let alignment = self.state_item(&state, "numpy_array_alignment_bytes");
+        let dtype = self.numpy_dtype(dtype)?.ok_or_else(|| {
+            Error::Syntax(ErrorCode::Structure(
+                "unsupported joblib array dtype".into(),
+            ))
+        })?;
+        let data = if dtype.kind == 'O' {
+            self.parse_nested()?
+        } else {
+            let len = (self.numpy_shape(shape)?.into_iter())
+                .try_fold(dtype.size, usize::checked_mul)
+                .ok_or_else(|| {
+                    self.inner_error(ErrorCode::InvalidValue("joblib array size".into()))
+                })?;
+            if let Some(Value::I64(_)) = alignment {
+                let padding = self.read_byte()?;
+                self.read_bytes(padding.into())?;
+            }
+            Value::Bytes(SharedFrozen::new(self.read_bytes(len)?))
+        };
+        wrapper.inner_mut().args = vec![data];
+        Ok(())
+    }
+
+    // Parse a separate pickle embedded in the stream, with a memo of its
+    // own.  Memo references in the result are replaced by the values they
+    // refer to, since that memo is gone afterwards, and cached tuples (which
+    // may hold references into either memo) aren't shared.
+    fn parse_nested(&mut self) -> Result<Value> {
+        let memo = mem::take(&mut self.memo);
+        let stack = mem::take(&mut self.stack);
+        let stacks = mem::take(&mut self.stacks);
+        let offsets = mem::take(&mut self.offsets);
+        let tuples = mem::take(&mut self.tuple_rc);
+        let proto = self.proto;
+        let value = (self.parse_value()).and_then(|value| self.inline_memo(value, &mut Vec::new()));
+        self.memo = memo;
+        self.stack = stack;
+        self.stacks = stacks;
+        self.offsets = offsets;
+        self.tuple_rc = tuples;
+        self.proto = proto;
+        value
+    }
+
+    fn inline_memo(&self, value: Value, path: &mut Vec<MemoId>) -> Result<Value> {
+        let mut items = |items: &[Value]| -> Result<Vec<Value>> {
+            (items.iter())
+                .map(|item| self.inline_memo(item.clone(), path))
+                .collect()
+        };
+        Ok(match value {
+            Value::MemoRef(id) => {
+                if path.contains(&id) {
+                    return Err(Error::Syntax(ErrorCode::Recursive));
+                }
+                let Some((value, _)) = self.memo.get(&id) else {
+                    return Err(Error::Eval(ErrorCode::MissingMemo(id), self.pos));
+                };
+                path.push(id);
+                let value = self.inline_memo(value.clone(), path)?;
+                path.pop();
+                value
+            }
+            Value::List(v) => Value::List(Shared::new(items(&v.inner())?)),
+            Value::Tuple(v) => Value::Tuple(SharedFrozen::new(items(v.inner())?)),
+            Value::Set(v) => Value::Set(Shared::new(items(&v.inner())?)),
+            Value::FrozenSet(v) => Value::FrozenSet(SharedFrozen::new(items(v.inner())?)),
+            Value::Dict(v) => Value::Dict(Shared::new(self.inline_pairs(&v.inner(), path)?)),
+            Value::Object(object) => {
+                let mut object = object.inner().clone();
+                object.args = items(&object.args)?;
+                object.kwargs = self.inline_pairs(&object.kwargs, path)?;
+                if let Some(items) = object.items.take() {
+                    object.items = Some(self.inline_memo(items, path)?);
+                }
+                if let Some(state) = object.state.take() {
+                    object.state = Some(self.inline_memo(state, path)?);
+                }
+                Value::Object(Shared::new(object))
+            }
+            Value::PersId(pid) => Value::PersId(Box::new(self.inline_memo(*pid, path)?)),
+            other => other,
+        })
+    }
+
+    fn inline_pairs(
+        &self,
+        pairs: &[(Value, Value)],
+        path: &mut Vec<MemoId>,
+    ) -> Result<Vec<(Value, Value)>> {
+        (pairs.iter())
+            .map(|(key, value)| {
+                Ok((
+                    self.inline_memo(key.clone(), path)?,
+                    self.inline_memo(value.clone(), path)?,
+                ))
+            })
+            .collect()
+    }
+
+    // Build a NumPy array from the object NumPy pickles it as, if it is one:
+    // `_reconstruct(ndarray, (0,), b'b')` with the state `(version, shape,
+    // dtype, fortran_order, data)`, or `_frombuffer(buffer, dtype, shape,
+    // order)` for protocol 5.  joblib's `NumpyArrayWrapper` has the array
+    // data attached by `read_joblib_array`.
+    fn ndarray(&mut self, object: &Object) -> Result<Option<NdArray>> {
+        let invalid = || Error::Syntax(ErrorCode::InvalidValue("numpy array".into()));
+        let submodule = (object.modname.strip_prefix("numpy.core."))
+            .or_else(|| object.modname.strip_prefix("numpy._core."));
+        let (shape, dtype, fortran_order, data) = match (submodule, &*object.globname) {
+            (Some("multiarray"), "_reconstruct") => {
+                let Some(Value::Tuple(state)) = self.resolve(object.state.clone()) else {
+                    return Ok(None);
+                };
+                let [_, shape, dtype, fortran_order, data] = &state.inner()[..] else {
+                    return Err(invalid());
+                };
+                let fortran_order = match self.resolve(Some(fortran_order.clone())) {
+                    Some(Value::Bool(b)) => b,
+                    Some(Value::I64(i)) => i != 0,
+                    _ => return Err(invalid()),
+                };
+                (shape.clone(), dtype.clone(), fortran_order, data.clone())
+            }
+            (Some("numeric"), "_frombuffer") => {
+                let [data, dtype, shape, order] = &object.args[..] else {
+                    return Err(invalid());
+                };
+                let fortran_order = matches!(self.resolve(Some(order.clone())),
+                                             Some(Value::String(s)) if s.inner() == "F");
+                (shape.clone(), dtype.clone(), fortran_order, data.clone())
+            }
+            _ if is_joblib_wrapper(object) => {
+                let Some(data) = object.args.first() else {
+                    return Ok(None);
+                };
+                // Object arrays are pickled as a whole after the wrapper.
+                if let Some(Value::Object(array)) = self.peek(data) {
+                    let array = array.inner().clone();
+                    return self.ndarray(&array);
+                }
+                let state = object.state.clone().unwrap_or(Value::None);
+                let item = |name| self.state_item(&state, name).ok_or_else(invalid);
+                let (shape, dtype) = (item("shape")?, item("dtype")?);
+                let fortran_order = matches!(self.state_item(&state, "order"),
+                                             Some(Value::String(s)) if s.inner() == "F");
+                (shape, dtype, fortran_order, data.clone())
+            }
+            _ => return Ok(None),
+        };
+        let shape = self.numpy_shape(shape)?;
+        // Arrays of other dtypes are left as objects.
+        let Some(dtype) = self.numpy_dtype(dtype)? else {
+            return Ok(None);
+        };
+        let data = match self.resolve(Some(data)) {
+            Some(Value::Bytes(b) | Value::Str(b)) => ArrayData::Raw(b.into_raw_or_cloned()),
+            Some(Value::ByteArray(b)) => ArrayData::Raw(b.into_raw_or_cloned()),
+            Some(Value::List(items)) => ArrayData::Objects(
+                (items.inner().iter())
+                    .map(|item| self.convert_value(item.clone()))
+                    .collect::<Result<_>>()?,
+            ),
+            _ => return Err(invalid()),
+        };
+        Ok(Some(NdArray {
+            dtype,
+            shape,
+            fortran_order,
+            data,
+        }))
+    }
+
+    // The shape of a NumPy array, a tuple of lengths.
+    fn numpy_shape(&mut self, shape: Value) -> Result<Vec<usize>> {
+        let invalid = || Error::Syntax(ErrorCode::InvalidValue("numpy array shape".into()));
+        let shape = match self.resolve(Some(shape)) {
+            Some(Value::Tuple(shape)) => shape.inner().clone(),
+            _ => return Err(invalid()),
+        };
+        let shape = (shape.into_iter())
+            .map(|len| match self.resolve(Some(len)) {
+                Some(Value::I64(len)) => usize::try_from(len).map_err(|_| invalid()),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ndarray::checked_len(&shape).ok_or_else(invalid)?;
+        Ok(shape)
+    }
+
+    // A NumPy dtype, pickled as `dtype(descr, False, True)` with a state
+    // tuple for byte order, fields and metadata; `None` if it can't be
+    // represented.
+    fn numpy_dtype(&mut self, dtype: Value) -> Result<Option<Dtype>> {
+        let invalid = || Error::Syntax(ErrorCode::InvalidValue("numpy dtype".into()));
+        let dtype = match self.resolve(Some(dtype)) {
+            Some(Value::Object(dtype)) if dtype.inner().globname == "dtype" => {
+                dtype.inner().clone()
+            }
+            _ => return Err(invalid()),
+        };
+        let descr = match self.resolve(dtype.args.first().cloned()) {
+            Some(Value::String(s)) => s.inner().clone(),
+            Some(Value::Str(s) | Value::Bytes(s)) => {
+                String::from_utf8_lossy(s.inner()).into_owned()
+            }
+            _ => return Err(invalid()),
+        };
+        let state = match dtype.state {
+            Some(state) => Some(self.convert_value(state)?),
+            None => None,
+        };
+        let descr = value::Value::String(SharedFrozen::new(descr));
+        Dtype::from_pickle(&descr, state.as_ref())
+    }
+
+    /// Find the variant of the Rust enum `name` for a Python enum member.
+    fn enum_member(
+        &mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        object: Object,
+    ) -> Result<&'static str> {
+        let object = self.convert_object(object)?;
+        object.enum_member(name, variants, self.options.enums.get(name))
+    }
 }
 
 impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
@@ -1343,32 +2801,34 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
 
     fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
         let value = self.get_next_value()?;
+        let offset = self.offset_of(&value);
         match value {
             Value::None => visitor.visit_unit(),
             Value::Bool(v) => visitor.visit_bool(v),
             Value::I64(v) => visitor.visit_i64(v),
-            Value::Int(v) => {
-                if let Some(i) = v.to_i64() {
-                    visitor.visit_i64(i)
-                } else {
-                    Err(Error::Syntax(ErrorCode::InvalidValue(
-                        "integer too large".into(),
-                    )))
-                }
-            }
+            Value::Int(v) => value_impls::visit_bigint(v, visitor),
             Value::F64(v) => visitor.visit_f64(v),
             Value::Bytes(v) => {
                 let v = v.into_raw_or_cloned();
                 visitor.visit_byte_buf(v)
             }
+            Value::ByteArray(v) => visitor.visit_byte_buf(v.into_raw_or_cloned()),
+            Value::Str(v) => {
+                let decoded = self.options.strs.decode(v.into_raw_or_cloned());
+                self.value = Some(decoded.map_err(Error::Syntax)?);
+                self.deserialize_any(visitor)
+            }
             Value::String(v) => {
                 let v = v.into_raw_or_cloned();
                 visitor.visit_string(v)
             }
+            Value::WtfString(v) => visitor.visit_string(wtf8_lossy(v.inner())),
             Value::List(v) => {
                 let v = v.into_raw_or_cloned();
                 let len = v.len();
                 visitor.visit_seq(SeqAccess {
+                    offset,
+                    index: 0,
                     de: self,
                     iter: v.into_iter(),
                     len,
@@ -1377,6 +2837,8 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
             Value::Tuple(v) => {
                 let v = v.into_raw_or_cloned();
                 visitor.visit_seq(SeqAccess {
+                    offset,
+                    index: 0,
                     len: v.len(),
                     iter: v.into_iter(),
                     de: self,
@@ -1385,6 +2847,8 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
             Value::Set(v) => {
                 let v = v.into_raw_or_cloned();
                 visitor.visit_seq(SeqAccess {
+                    offset,
+                    index: 0,
                     de: self,
                     len: v.len(),
                     iter: v.into_iter(),
@@ -1393,6 +2857,8 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
             Value::FrozenSet(v) => {
                 let v = v.into_raw_or_cloned();
                 visitor.visit_seq(SeqAccess {
+                    offset,
+                    index: 0,
                     de: self,
                     len: v.len(),
                     iter: v.into_iter(),
@@ -1402,6 +2868,8 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
                 let v = v.into_raw_or_cloned();
                 let len = v.len();
                 visitor.visit_map(MapAccess {
+                    offset,
+                    key: None,
                     de: self,
                     iter: v.into_iter(),
                     value: None,
@@ -1414,13 +2882,75 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
                     slf.deserialize_any(visitor)
                 })
             }
-            Value::Global(_) => {
-                if self.options.replace_unresolved_globals {
+            // Serde sees the ID itself.
+            Value::PersId(pid) => {
+                self.value = Some(*pid);
+                self.deserialize_any(visitor)
+            }
+            Value::Global(global) => {
+                if let Some(value) = self.singleton_value(&global) {
+                    self.value = Some(value);
+                    self.deserialize_any(visitor)
+                } else if self.options.keep_objects {
+                    let (modname, globname) = global.into_names();
+                    visitor.visit_string(format!("{modname}.{globname}"))
+                } else if self.options.replace_unresolved_globals {
                     visitor.visit_unit()
                 } else {
                     Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
                 }
             }
+            Value::Object(v) => {
+                let object = v.into_raw_or_cloned();
+                if let Some(array) = self.ndarray(&object)? {
+                    let value = value::Value::NdArray(Shared::new(array));
+                    let mut de = value_impls::Deserializer::new(value);
+                    return de::Deserializer::deserialize_any(&mut de, visitor);
+                }
+                if self.in_object || self.options.keep_objects {
+                    let fields = object.into_fields();
+                    self.inherit_offset(&fields, offset);
+                    self.value = Some(fields);
+                    return self.deserialize_any(visitor);
+                }
+                if let Some(value) = self.stdlib_value(&object) {
+                    self.inherit_offset(&value, offset);
+                    self.value = Some(value);
+                    return self.deserialize_any(visitor);
+                }
+                if let Some(items) = object.items {
+                    self.inherit_offset(&items, offset);
+                    self.value = Some(items);
+                    return self.deserialize_any(visitor);
+                }
+                match object.state {
+                    Some(state) => {
+                        self.value = Some(state);
+                        self.deserialize_any(visitor)
+                    }
+                    None if object.construct == Construct::Reduce => {
+                        if self.options.replace_unresolved_globals {
+                            visitor.visit_unit()
+                        } else {
+                            Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
+                        }
+                    }
+                    None if object.construct == Construct::New && !object.args.is_empty() => {
+                        let args = Value::Tuple(SharedFrozen::new(object.args));
+                        self.inherit_offset(&args, offset);
+                        self.value = Some(args);
+                        self.deserialize_any(visitor)
+                    }
+                    None => visitor.visit_map(MapAccess {
+                        offset,
+                        key: None,
+                        de: self,
+                        iter: Vec::new().into_iter(),
+                        value: None,
+                        len: 0,
+                    }),
+                }
+            }
         }
     }
 
@@ -1439,26 +2969,145 @@ impl<'de: 'a, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
     #[inline]
     fn deserialize_newtype_struct<V: Visitor<'de>>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value> {
+        if name == types::BIGINT {
+            // Integers that don't fit the serde data model are passed on in
+            // their decimal representation.
+            match self.get_next_value()? {
+                Value::Int(v) => return visitor.visit_string(v.to_string()),
+                Value::MemoRef(memo_id) => {
+                    self.resolve_recursive(memo_id, (), |slf, (), value| {
+                        slf.value = Some(value);
+                        Ok(())
+                    })?;
+                    return self.deserialize_newtype_struct(name, visitor);
+                }
+                other => self.value = Some(other),
+            }
+        }
         visitor.visit_newtype_struct(self)
     }
 
+    #[inline]
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.get_next_value()? {
+            // Bytes can be decoded as a sequence of integers, e.g. into `Vec<u8>`.
+            Value::Bytes(bytes) => {
+                let items: Vec<_> = bytes
+                    .inner()
+                    .iter()
+                    .map(|&b| Value::I64(b.into()))
+                    .collect();
+                visitor.visit_seq(SeqAccess {
+                    offset: None,
+                    index: 0,
+                    len: items.len(),
+                    iter: items.into_iter(),
+                    de: self,
+                })
+            }
+            Value::Str(string) => {
+                let decoded = self.options.strs.decode(string.into_raw_or_cloned());
+                self.value = Some(decoded.map_err(Error::Syntax)?);
+                self.deserialize_seq(visitor)
+            }
+            Value::ByteArray(bytes) => {
+                let bytes = SharedFrozen::new(bytes.into_raw_or_cloned());
+                self.value = Some(Value::Bytes(bytes));
+                self.deserialize_seq(visitor)
+            }
+            Value::MemoRef(memo_id) => {
+                self.resolve_recursive(memo_id, visitor, |slf, visitor, value| {
+                    slf.value = Some(value);
+                    slf.deserialize_seq(visitor)
+                })
+            }
+            other => {
+                self.value = Some(other);
+                self.deserialize_any(visitor)
+            }
+        }
+    }
+
+    #[inline]
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    #[inline]
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name == types::OBJECT {
+            // Objects are presented with their class, see `value::Object`.
+            let value = self.get_next_value()?;
+            let offset = self.offset_of(&value);
+            match value {
+                Value::Object(v) => {
+                    let fields = v.into_raw_or_cloned().into_fields();
+                    self.inherit_offset(&fields, offset);
+                    self.value = Some(fields);
+                }
+                Value::MemoRef(memo_id) => {
+                    return self.resolve_recursive(memo_id, visitor, |slf, visitor, value| {
+                        slf.value = Some(value);
+                        slf.deserialize_struct(name, fields, visitor)
+                    });
+                }
+                other => return Deserializer::<R>::stack_error("object", &other, self.pos),
+            }
+            let in_object = mem::replace(&mut self.in_object, true);
+            let result = self.deserialize_any(visitor);
+            self.in_object = in_object;
+            return result;
+        }
+        self.deserialize_any(visitor)
+    }
+
     #[inline]
     fn deserialize_enum<V: Visitor<'de>>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value> {
+        match self.get_next_value()? {
+            // Objects select the variant named like their class.  Otherwise,
+            // they can be Python enum members, pickled as `Class(value)`.
+            Value::Object(v) => {
+                let object = v.into_raw_or_cloned();
+                let class_variant = match self.options.enums.contains_key(name) {
+                    true => None,
+                    false => object.class_variant(variants),
+                };
+                self.value = Some(match class_variant {
+                    Some(variant) => object.into_variant(variant),
+                    None => {
+                        let variant = self.enum_member(name, variants, object)?;
+                        Value::String(SharedFrozen::new(variant.into()))
+                    }
+                });
+            }
+            Value::MemoRef(memo_id) => {
+                return self.resolve_recursive(memo_id, visitor, |slf, visitor, value| {
+                    slf.value = Some(value);
+                    slf.deserialize_enum(name, variants, visitor)
+                });
+            }
+            other => self.value = Some(other),
+        }
         visitor.visit_enum(VariantAccess { de: self })
     }
 
     forward_to_deserialize_any! {
-        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char str string unit seq
-        bytes byte_buf map tuple_struct struct identifier
-        tuple ignored_any unit_struct
+        bool u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 char str string unit
+        bytes byte_buf map tuple_struct identifier
+        ignored_any unit_struct
     }
 }
 
@@ -1531,6 +3180,8 @@ impl<'de: 'a, 'a, R: Read + 'a> de::VariantAccess<'de> for VariantAccess<'a, R>
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
+        // Drop any content, e.g. the state of an object selecting the variant.
+        self.de.value = None;
         Ok(())
     }
 
@@ -1555,6 +3206,8 @@ struct SeqAccess<'a, R: Read + 'a> {
     de: &'a mut Deserializer<R>,
     iter: vec::IntoIter<Value>,
     len: usize,
+    offset: Option<usize>, // of the opcode creating the sequence, for errors
+    index: usize,
 }
 
 impl<'de: 'a, 'a, R: Read> de::SeqAccess<'de> for SeqAccess<'a, R> {
@@ -1567,8 +3220,12 @@ impl<'de: 'a, 'a, R: Read> de::SeqAccess<'de> for SeqAccess<'a, R> {
         match self.iter.next() {
             Some(value) => {
                 self.len -= 1;
+                self.index += 1;
                 self.de.value = Some(value);
-                Ok(Some(seed.deserialize(&mut *self.de)?))
+                match seed.deserialize(&mut *self.de) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => Err(err.at(format!("[{}]", self.index - 1), self.offset)),
+                }
             }
             None => Ok(None),
         }
@@ -1584,6 +3241,8 @@ struct MapAccess<'a, R: Read + 'a> {
     iter: vec::IntoIter<(Value, Value)>,
     value: Option<Value>,
     len: usize,
+    offset: Option<usize>, // of the opcode creating the map, for errors
+    key: Option<Value>,    // current key, for errors
 }
 
 impl<'de: 'a, 'a, R: Read> de::MapAccess<'de> for MapAccess<'a, R> {
@@ -1594,8 +3253,25 @@ impl<'de: 'a, 'a, R: Read> de::MapAccess<'de> for MapAccess<'a, R> {
             Some((key, value)) => {
                 self.len -= 1;
                 self.value = Some(value);
-                self.de.value = Some(key);
-                Ok(Some(seed.deserialize(&mut *self.de)?))
+                self.key = Some(key.clone());
+                let result = if self.de.options.stringify_keys {
+                    (self.de.decode_key(key))
+                        .and_then(|key| self.de.convert_value(key))
+                        .and_then(value::Value::into_hashable)
+                        .and_then(|key| {
+                            let key = match key.to_string_key() {
+                                Some(key) => key.into_owned(),
+                                None => key.to_string(),
+                            };
+                            seed.deserialize(KeyDeserializer(key))
+                        })
+                } else {
+                    self.de.decode_key(key).and_then(|key| {
+                        self.de.value = Some(key);
+                        seed.deserialize(&mut *self.de)
+                    })
+                };
+                result.map(Some).map_err(|err| self.error_at_key(err))
             }
             None => Ok(None),
         }
@@ -1605,6 +3281,7 @@ impl<'de: 'a, 'a, R: Read> de::MapAccess<'de> for MapAccess<'a, R> {
         let value = self.value.take().unwrap();
         self.de.value = Some(value);
         seed.deserialize(&mut *self.de)
+            .map_err(|err| self.error_at_key(err))
     }
 
     fn size_hint(&self) -> Option<usize> {
@@ -1612,6 +3289,79 @@ impl<'de: 'a, 'a, R: Read> de::MapAccess<'de> for MapAccess<'a, R> {
     }
 }
 
+impl<R: Read> MapAccess<'_, R> {
+    fn error_at_key(&mut self, err: Error) -> Error {
+        let segment = match self.key.take() {
+            Some(key) => self.de.path_segment(key),
+            None => "[?]".into(),
+        };
+        err.at(segment, self.offset)
+    }
+}
+
+// Deserializes a dict key converted to a string, parsing it again if a
+// number or boolean is requested.
+struct KeyDeserializer(String);
+
+macro_rules! parse_key {
+    ($($method:ident => $visit:ident($ty:ty),)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                match self.0.parse::<$ty>() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => visitor.visit_string(self.0),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.0)
+    }
+
+    parse_key! {
+        deserialize_bool => visit_bool(bool),
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_i128 => visit_i128(i128),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+        deserialize_u128 => visit_u128(u128),
+        deserialize_f32 => visit_f32(f32),
+        deserialize_f64 => visit_f64(f64),
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(de::IntoDeserializer::<Error>::into_deserializer(self.0))
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf option unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
 /// Decodes a value from a `std::io::Read`.
 pub fn from_reader<'de, R: io::Read, T: de::Deserialize<'de>>(
     rdr: R,
@@ -0,0 +1,149 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Opcode-level reports for decoding failures.
+
+use std::fmt::Write;
+
+use crate::consts::opcode_name;
+use crate::de::{DeOptions, Deserializer};
+use crate::error::Error;
+
+/// Number of opcodes, stack items and memo entries shown in a report.
+const HISTORY: usize = 16;
+
+/// Bytes shown before and after the failing opcode in the hex dump.
+const CONTEXT: usize = 32;
+
+/// Produce a report for an error that occurred while decoding `bytes` with
+/// `options`, for triaging pickles that fail to load.
+///
+/// The pickle is run again with the same options up to the failure point:
+/// for errors from the
+/// pickle machine, that's the opcode that failed, and for errors that carry
+/// a path (see [`Error::Path`]), the opcode that created the innermost
+/// container.  The report names that opcode, shows a hex dump around it and
+/// the last opcodes executed, and summarizes the stack, the stacks below
+/// MARKs and the memo at that point.
+///
+/// ```
+/// let bytes = b"\x80\x03]q\x00(K\x01\x97e.";
+/// let options = pickled::DeOptions::new();
+/// let err = pickled::value_from_slice(bytes, options.clone()).unwrap_err();
+/// let report = pickled::diagnose(bytes, &options, &err);
+/// assert!(report.contains("opcode: NEXT_BUFFER (0x97) at offset 8"));
+/// ```
+pub fn diagnose(bytes: &[u8], options: &DeOptions, error: &Error) -> String {
+    let stop = match *error {
+        Error::Path { offset, .. } => offset,
+        _ => None,
+    };
+    let snapshot = Deserializer::new(bytes, options.clone()).snapshot(stop, HISTORY);
+    // The failing opcode is the one we stopped at, or the last one executed.
+    let offset = match (stop, error, &snapshot.error) {
+        (Some(stop), _, _) => Some(stop),
+        (None, Error::Eval(..), Some(_)) => snapshot.opcodes.last().map(|&(start, _)| start),
+        _ => None,
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "error: {error}");
+    if let Some(ref replayed) = snapshot.error
+        && replayed.to_string() != error.to_string()
+    {
+        let _ = writeln!(out, "error when replaying: {replayed}");
+    }
+    match offset.and_then(|offset| bytes.get(offset).map(|&byte| (offset, byte))) {
+        Some((offset, byte)) => {
+            let _ = writeln!(out, "opcode: {} at offset {offset}", describe(byte));
+            out.push_str("\nbytes:\n");
+            hexdump(&mut out, bytes, offset);
+        }
+        None => out.push_str("opcode: unknown\n"),
+    }
+
+    out.push_str("\nlast opcodes:\n");
+    for &(start, byte) in &snapshot.opcodes {
+        let _ = writeln!(out, "  {start:>8}  {}", describe(byte));
+    }
+
+    let _ = writeln!(
+        out,
+        "\nstack ({}, top last):",
+        count(snapshot.stack_len, "item")
+    );
+    if snapshot.stack_len > snapshot.stack.len() {
+        let _ = writeln!(out, "  ...");
+    }
+    for item in &snapshot.stack {
+        let _ = writeln!(out, "  {item}");
+    }
+
+    let _ = writeln!(
+        out,
+        "\nmark stack ({}):",
+        count(snapshot.marks.len(), "mark")
+    );
+    for (level, &len) in snapshot.marks.iter().enumerate() {
+        let _ = writeln!(out, "  {level}: {}", count(len, "item"));
+    }
+
+    let entries = count(snapshot.memo_len, "entry");
+    let _ = writeln!(out, "\nmemo ({entries}, newest first):");
+    for (id, item) in &snapshot.memo {
+        let _ = writeln!(out, "  #{id}: {item}");
+    }
+    if snapshot.memo_len > snapshot.memo.len() {
+        let _ = writeln!(out, "  ...");
+    }
+    out
+}
+
+fn count(n: usize, what: &str) -> String {
+    match (n, what) {
+        (1, _) => format!("1 {what}"),
+        (_, "entry") => format!("{n} entries"),
+        _ => format!("{n} {what}s"),
+    }
+}
+
+fn describe(byte: u8) -> String {
+    match opcode_name(byte) {
+        Some(name) => format!("{name} (0x{byte:02x})"),
+        None => format!("unknown opcode 0x{byte:02x}"),
+    }
+}
+
+// Write a hex dump of the lines around `offset`, marking the byte at `offset`.
+fn hexdump(out: &mut String, bytes: &[u8], offset: usize) {
+    let first = offset.saturating_sub(CONTEXT) / 16 * 16;
+    let last = (offset + CONTEXT).min(bytes.len());
+    for start in (first..last).step_by(16) {
+        let line = &bytes[start..(start + 16).min(bytes.len())];
+        let mut hex = String::new();
+        for (i, byte) in line.iter().enumerate() {
+            let sep = match (start + i == offset, i > 0 && start + i - 1 == offset) {
+                (true, _) => '>',
+                (_, true) => '<',
+                _ => ' ',
+            };
+            let _ = write!(hex, "{sep}{byte:02x}");
+        }
+        if start + line.len() - 1 == offset {
+            hex.push('<');
+        }
+        let ascii: String = (line.iter())
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "  {start:08x} {hex:<49} |{ascii}|");
+    }
+}
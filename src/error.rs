@@ -6,6 +6,7 @@
 
 //! Error objects and codes
 
+use crate::consts;
 use serde::{de, ser};
 use std::error;
 use std::fmt;
@@ -49,7 +50,10 @@ pub enum ErrorCode {
 impl fmt::Display for ErrorCode {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ErrorCode::Unsupported(ch) => write!(fmt, "unsupported opcode {ch:?}"),
+            ErrorCode::Unsupported(ch) => match consts::opcode_name(ch as u8) {
+                Some(name) => write!(fmt, "unsupported opcode 0x{:02x} ({name})", ch as u32),
+                None => write!(fmt, "unsupported opcode 0x{:02x}", ch as u32),
+            },
             ErrorCode::EOFWhileParsing => write!(fmt, "EOF while parsing"),
             ErrorCode::StackUnderflow => write!(fmt, "pickle stack underflow"),
             ErrorCode::NegativeLength => write!(fmt, "negative length prefix"),
@@ -87,6 +91,57 @@ pub enum Error {
     Eval(ErrorCode, usize),
     /// Syntax error while transforming into Rust values.
     Syntax(ErrorCode),
+    /// Error while transforming a value nested in containers, with the path
+    /// to it (e.g. `users[12].address.zip`) and the offset of the opcode that
+    /// created the innermost container, if known.
+    Path {
+        path: String,
+        offset: Option<usize>,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The error without the paths of nested values, i.e. the error that
+    /// `Path` errors wrap.
+    pub fn innermost(&self) -> &Error {
+        match self {
+            Error::Path { error, .. } => error.innermost(),
+            error => error,
+        }
+    }
+
+    /// The code of the innermost error, unless it is an IO error.
+    pub fn code(&self) -> Option<&ErrorCode> {
+        match self.innermost() {
+            Error::Eval(code, _) | Error::Syntax(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    // Prepend a segment (a key or `[index]`) to the path of an error from a
+    // nested value.  The offset of the innermost container is kept.
+    pub(crate) fn at(self, segment: String, offset: Option<usize>) -> Error {
+        match self {
+            Error::Path {
+                path,
+                offset: inner,
+                error,
+            } => {
+                let sep = if path.starts_with('[') { "" } else { "." };
+                Error::Path {
+                    path: format!("{segment}{sep}{path}"),
+                    offset: inner.or(offset),
+                    error,
+                }
+            }
+            error => Error::Path {
+                path: segment,
+                offset,
+                error: Box::new(error),
+            },
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -103,11 +158,28 @@ impl fmt::Display for Error {
             Error::Io(ref error) => error.fmt(fmt),
             Error::Eval(ref code, offset) => write!(fmt, "eval error at offset {offset}: {code}"),
             Error::Syntax(ref code) => write!(fmt, "decoding error: {code}"),
+            Error::Path {
+                ref path,
+                offset: Some(offset),
+                ref error,
+            } => write!(fmt, "{error} at {path} (opcode at offset {offset})"),
+            Error::Path {
+                ref path,
+                offset: None,
+                ref error,
+            } => write!(fmt, "{error} at {path}"),
         }
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Path { error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
 
 impl de::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Error {
@@ -0,0 +1,88 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Files written by `joblib.dump`, e.g. scikit-learn models.
+//!
+//! joblib pickles NumPy arrays as a `NumpyArrayWrapper` object and writes the
+//! raw array data into the stream right after it, outside of any opcode.  The
+//! regular [`Deserializer`](crate::de::Deserializer) already reads that data
+//! in step with the pickle and turns the wrappers into
+//! [`Value::NdArray`]s, so uncompressed files can be read with
+//! [`value_from_reader`](crate::de::value_from_reader) as well.
+//!
+//! The functions of this module also detect and undo the compression that
+//! `joblib.dump(..., compress=...)` applies to the whole file: zlib, gzip,
+//! xz and lzma.  The bz2 and lz4 formats, and the separate `.npy` files of
+//! joblib before 0.10, aren't supported.
+//!
+//! ```no_run
+//! # fn main() -> pickled::Result<()> {
+//! let file = std::fs::File::open("model.joblib")?;
+//! let model = pickled::joblib::value_from_reader(file, Default::default())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, Cursor, Read};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::de::{self, DeOptions};
+use crate::error::{Error, ErrorCode, Result};
+use crate::value::Value;
+
+/// Decodes a joblib file from a `std::io::Read`.
+pub fn value_from_reader<R: Read>(mut rdr: R, options: DeOptions) -> Result<Value> {
+    let mut magic = [0; 6];
+    let mut len = 0;
+    while len < magic.len() {
+        match rdr.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(Error::Io(error)),
+        }
+    }
+    let magic = &magic[..len];
+    let rdr = Cursor::new(magic.to_vec()).chain(rdr);
+    match magic {
+        [0x78, ..] => de::value_from_reader(ZlibDecoder::new(rdr), options),
+        [0x1f, 0x8b, ..] => de::value_from_reader(GzDecoder::new(rdr), options),
+        [0xfd, b'7', b'z', b'X', b'Z', 0] => {
+            let mut data = Vec::new();
+            lzma_rs::xz_decompress(&mut io::BufReader::new(rdr), &mut data).map_err(lzma_error)?;
+            de::value_from_slice(&data, options)
+        }
+        [b']', 0, 0, ..] => {
+            let mut data = Vec::new();
+            lzma_rs::lzma_decompress(&mut io::BufReader::new(rdr), &mut data)
+                .map_err(lzma_error)?;
+            de::value_from_slice(&data, options)
+        }
+        [b'B', b'Z', b'h', ..] => Err(unsupported("bz2 compression")),
+        [0x04, 0x22, 0x4d, 0x18, ..] => Err(unsupported("lz4 compression")),
+        [b'Z', b'F', ..] => Err(unsupported("zfile format (joblib < 0.10)")),
+        _ => de::value_from_reader(rdr, options),
+    }
+}
+
+/// Decodes a joblib file from a byte slice.
+pub fn value_from_slice(data: &[u8], options: DeOptions) -> Result<Value> {
+    value_from_reader(data, options)
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::Syntax(ErrorCode::Structure(format!("unsupported joblib {what}")))
+}
+
+fn lzma_error(error: lzma_rs::error::Error) -> Error {
+    match error {
+        lzma_rs::error::Error::IoError(error) | lzma_rs::error::Error::HeaderTooShort(error) => {
+            Error::Io(error)
+        }
+        error => Error::Syntax(ErrorCode::InvalidValue(format!("joblib file: {error}"))),
+    }
+}
@@ -24,7 +24,8 @@
 //!
 //! * None
 //! * Boolean (Rust `bool`)
-//! * Integers (Rust `i64` or bigints from num)
+//! * Integers (Rust integers up to `i128`/`u128`, or bigints from num using
+//!   [`types::PyBigInt`])
 //! * Floats (Rust `f64`)
 //! * Bytes objects and bytearrays (see below)
 //! * (Unicode) strings (Rust `String`)
@@ -34,25 +35,62 @@
 //!
 //! When deserializing, arbitrary Python objects saved using a pickled instance
 //! dictionary or `__setstate__` are replaced by that state, since version
-//! 0.5 of this library.
+//! 0.5 of this library.  Objects without state that are created by `__new__`
+//! from positional arguments, like namedtuples, are replaced by the argument
+//! tuple.  With [`DeOptions::keep_objects`], they are kept as
+//! [`Value::Object`] instead, which records the class, the arguments to
+//! `__new__` and the state.
+//!
+//! *Note on standard library types:* UUIDs, `pathlib` paths and `ipaddress`
+//! addresses and networks are decoded to their string form, and ranges and
+//! slices to dicts of `start`, `stop` and `step`.  The `Ellipsis` and
+//! `NotImplemented` singletons become their names.  Kept as objects, they
+//! are written back as Python pickles them.  [`Value`] converts to and from
+//! `std::net::IpAddr`, and with the `uuid` feature, `uuid::Uuid`.  Arrays
+//! from the `array` module become lists of numbers (or strings, for Unicode
+//! arrays); [`types::PyArray`] writes them.
+//!
+//! *Note on persistent IDs:* objects that the pickler replaced by a
+//! persistent ID (`PERSID` and `BINPERSID`) are decoded to
+//! [`Value::PersistentId`], holding the ID; classes in an ID tuple become
+//! their dotted names.  The `torch` module, with the `torch` feature, uses
+//! them to read PyTorch checkpoints, and the [`zodb`] module to read ZODB
+//! databases.
+//!
+//! *Note on NumPy and pandas:* NumPy arrays are decoded to [`Value::NdArray`]
+//! (see the [`ndarray`] module), which serde sees as nested lists.  Arrays
+//! of dtypes it can't represent, like structured ones, are treated like other
+//! objects.  The
+//! [`npy`] module reads and writes the same arrays as `.npy` files, and the
+//! [`pandas`] module reads pickled DataFrames and Series into typed columns.
+//! Files written by `joblib.dump`, which places the raw data of arrays in the
+//! stream after their `NumpyArrayWrapper`, are decoded to arrays as well; the
+//! `joblib` module, with the `joblib` feature, also reads compressed ones.
+//!
+//! *Note on classes:* with the `derive` feature, `#[derive(PickleClass)]`
+//! maps instances of a specific Python class onto a Rust struct, in both
+//! directions.  See the `pickled-derive` crate for the supported attributes.
 //!
 //! *Note on enums:* Enum variants are serialized as Python tuples `(name,
 //! [data])` instead of mappings (or a plain string for unit variants), which is
 //! the representation selected by e.g. `serde_json`.  On deserialization, both
-//! the tuple form and the string/mapping form is accepted.
+//! the tuple form and the string/mapping form is accepted.  Instances of a
+//! Python class named like a variant (either `Name` or `module.Name`) are
+//! accepted as well, with the object state as the variant's content.
 //!
-//! *Note on bytes objects:* when deserializing bytes objects, you have to use a
-//! Rust wrapper type that enables deserialization from the serde data model's
-//! "bytes" type.  The [`serde_bytes`](https://docs.serde.rs/serde_bytes/) crate
-//! provides such wrappers.
+//! *Note on bytes objects:* bytes objects can be deserialized into `Vec<u8>`,
+//! `[u8; N]` and `Box<[u8]>`, as well as into the wrapper types in [`types`],
+//! which use the serde data model's "bytes" type.
 //!
-//! Likewise, `Vec<u8>`, `[u8; N]` and `&[u8]` are treated as sequences when
+//! However, `Vec<u8>`, `[u8; N]` and `&[u8]` are treated as sequences when
 //! serializing.  This means that they will be serialized as a tuple or list of
-//! integers unless you use one of the wrappers in `serde_bytes`.
+//! integers unless you use one of the wrappers in [`types`] (which also has
+//! wrappers for writing tuples, sets, bytearrays and big integers), or
+//! [`SerOptions::u8_sequences_as_bytes`] for the sequences other than arrays.
 //!
 //! # Unsupported features
 //!
-//! - Recursive objects using the `PERSID` and `EXT` type opcodes.
+//! - The `EXT` opcodes, which refer to the extension registry.
 //! - Out-of-band data as introduced in Pickle protocol 5.
 //!
 //! # Exported API
@@ -71,22 +109,44 @@
 pub use self::ser::{SerOptions, Serializer, to_vec, to_writer, value_to_vec, value_to_writer};
 
 pub use self::de::{
-    DeOptions, Deserializer, from_iter, from_reader, from_slice, value_from_iter,
-    value_from_reader, value_from_slice,
+    DeOptions, Deserializer, StrEncoding, StrErrors, Surrogates, from_iter, from_reader,
+    from_slice, value_from_iter, value_from_reader, value_from_slice,
 };
 
-pub use self::value::{HashableValue, Value, from_value, to_value};
+pub use self::value::{HashableValue, Object, Value, from_value, to_value};
 
+pub use self::diagnose::diagnose;
 pub use self::error::{Error, ErrorCode, Result};
 pub use num_bigint;
 pub use num_traits;
 
+#[cfg(feature = "derive")]
+pub use pickled_derive::PickleClass;
+
+// Lets the code generated by `pickled-derive` refer to `::pickled` inside
+// this crate as well.
+extern crate self as pickled;
+
+mod compat;
 mod consts;
 pub mod de;
+mod diagnose;
 pub mod error;
+#[cfg(feature = "joblib")]
+pub mod joblib;
+pub mod ndarray;
+pub mod npy;
+pub mod pandas;
 pub mod ser;
+#[cfg(feature = "torch")]
+pub mod torch;
+pub mod types;
 pub mod value;
 mod value_impls;
+pub mod zodb;
+
+#[doc(hidden)]
+pub mod __private;
 
 #[cfg(test)]
 #[path = "../test/mod.rs"]
@@ -0,0 +1,514 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! NumPy arrays.
+//!
+//! Arrays pickled by NumPy (through `numpy.core.multiarray._reconstruct`, or
+//! `numpy.core.numeric._frombuffer` for protocol 5, and the same functions in
+//! `numpy._core` since NumPy 2) are decoded to [`Value::NdArray`], and
+//! written back in the `_reconstruct` form, which all NumPy versions load.
+//!
+//! The items are kept as raw bytes in the layout given by the [`Dtype`];
+//! [`NdArray::elements`] decodes them into a typed vector.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::fmt;
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::value::{Shared, SharedFrozen, Value};
+
+/// A NumPy array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NdArray {
+    /// The type of the items.
+    pub dtype: Dtype,
+    /// The size of each dimension; empty for a 0-dimensional array.
+    pub shape: Vec<usize>,
+    /// Whether the items are stored in Fortran (column-major) order instead
+    /// of C (row-major) order.
+    pub fortran_order: bool,
+    /// The items.
+    pub data: ArrayData,
+}
+
+/// The items of a NumPy array, in storage order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArrayData {
+    /// The raw bytes of the items, laid out as the dtype says.
+    Raw(Vec<u8>),
+    /// The items of an array of the `object` dtype.
+    Objects(Vec<Value>),
+}
+
+/// The type of the items of a NumPy array, as in its `dtype.str`, e.g.
+/// `"<f8"`.  Structured dtypes and subarrays are not supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dtype {
+    /// `'<'` for little-endian, `'>'` for big-endian, or `'|'` if the byte
+    /// order doesn't matter.
+    pub byte_order: char,
+    /// The kind of item: `'b'` for booleans, `'i'` and `'u'` for signed and
+    /// unsigned integers, `'f'` and `'c'` for real and complex floats, `'M'`
+    /// and `'m'` for datetimes and timedeltas, `'S'` for byte strings, `'U'`
+    /// for strings, `'V'` for raw data and `'O'` for Python objects.
+    pub kind: char,
+    /// The size of an item in bytes.
+    pub size: usize,
+    /// The unit of datetimes and timedeltas, e.g. `"ns"`.
+    pub unit: Option<String>,
+}
+
+/// The items of a NumPy array decoded into a vector of the matching Rust
+/// type, in C order.
+///
+/// Datetimes and timedeltas are given as integers in their unit, strings
+/// and byte strings without their trailing NULs, and complex numbers as
+/// `(real, imag)` pairs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Elements {
+    Bool(Vec<bool>),
+    Int(Vec<i64>),
+    UInt(Vec<u64>),
+    Float(Vec<f64>),
+    Complex(Vec<(f64, f64)>),
+    Bytes(Vec<Vec<u8>>),
+    String(Vec<String>),
+    Object(Vec<Value>),
+}
+
+fn unsupported(what: String) -> Error {
+    Error::Syntax(ErrorCode::Structure(format!("unsupported {what}")))
+}
+
+impl Dtype {
+    /// Parse a dtype string like `"<f8"`, `"|b1"`, `"<U5"` (where the size
+    /// counts characters) or `"<M8[ns]"`.  Without a byte order, the native
+    /// one is assumed.
+    pub fn parse(descr: &str) -> Option<Dtype> {
+        let (byte_order, rest) = match descr.chars().next()? {
+            order @ ('<' | '>' | '|' | '=') => (order, &descr[1..]),
+            _ => ('=', descr),
+        };
+        let mut chars = rest.chars();
+        let kind = match chars.next()? {
+            '?' => 'b',
+            kind => kind,
+        };
+        let (digits, unit) = match chars.as_str().split_once('[') {
+            Some((digits, unit)) => (digits, Some(unit.strip_suffix(']')?.to_string())),
+            None => (chars.as_str(), None),
+        };
+        let size = match (kind, digits) {
+            ('b', "") => 1,
+            ('O', "") => std::mem::size_of::<usize>(),
+            ('M' | 'm', "") => 8,
+            (_, digits) => digits.parse().ok()?,
+        };
+        let size = if kind == 'U' {
+            size.checked_mul(4)?
+        } else {
+            size
+        };
+        if !valid_size(kind, size) || (unit.is_some() && !matches!(kind, 'M' | 'm')) {
+            return None;
+        }
+        // Like NumPy, give the byte order only where it matters.
+        let byte_order = match (kind, size) {
+            ('b' | 'O' | 'S' | 'V', _) | ('i' | 'u', 1) => '|',
+            (_, _) if byte_order == '=' || byte_order == '|' => {
+                if cfg!(target_endian = "big") {
+                    '>'
+                } else {
+                    '<'
+                }
+            }
+            _ => byte_order,
+        };
+        Some(Dtype {
+            byte_order,
+            kind,
+            size,
+            unit,
+        })
+    }
+
+    /// Return the dtype string, e.g. `"<f8"`.
+    pub fn descr(&self) -> String {
+        let size = match self.kind {
+            'U' => self.size / 4,
+            _ => self.size,
+        };
+        match (self.kind, &self.unit) {
+            ('O', _) => "|O".into(),
+            (kind, Some(unit)) => format!("{}{kind}{size}[{unit}]", self.byte_order),
+            (kind, None) => format!("{}{kind}{size}", self.byte_order),
+        }
+    }
+
+    /// Build a dtype from the arguments to `numpy.dtype` and the state it is
+    /// given by BUILD, as NumPy pickles it: `("f8", False, True)` and
+    /// `(version, byteorder, subarray, names, fields, elsize, alignment,
+    /// flags[, metadata])`.  Dtypes that can't be represented, like
+    /// structured ones, give `None`.
+    pub(crate) fn from_pickle(descr: &Value, state: Option<&Value>) -> Result<Option<Dtype>> {
+        let Value::String(descr) = descr else {
+            return Err(Error::Syntax(ErrorCode::InvalidValue("numpy dtype".into())));
+        };
+        let invalid = || {
+            Error::Syntax(ErrorCode::InvalidValue(format!(
+                "numpy dtype {}",
+                descr.inner()
+            )))
+        };
+        let Some(mut dtype) = Dtype::parse(descr.inner()) else {
+            return Ok(None);
+        };
+        let state = match state {
+            Some(Value::Tuple(state)) => state.inner().clone(),
+            Some(_) => return Err(invalid()),
+            None => return Ok(Some(dtype)),
+        };
+        if state.len() < 8 {
+            return Err(invalid());
+        }
+        if state[2] != Value::None || state[3] != Value::None {
+            return Ok(None);
+        }
+        if let Value::String(order) = &state[1] {
+            match (&**order.inner(), dtype.byte_order) {
+                (_, '|') => {}
+                ("<", _) => dtype.byte_order = '<',
+                (">", _) => dtype.byte_order = '>',
+                _ => {}
+            }
+        }
+        if let Value::I64(elsize) = state[5]
+            && elsize > 0
+        {
+            dtype.size = elsize as usize;
+            if !valid_size(dtype.kind, dtype.size) {
+                return Err(invalid());
+            }
+        }
+        // The unit of datetimes, as `(metadata, (b"ns", multiplier, 1, 1))`
+        if let Some(Value::Tuple(meta)) = state.get(8) {
+            let Some(Value::Tuple(meta)) = meta.inner().get(1) else {
+                return Err(invalid());
+            };
+            let meta = meta.inner();
+            let unit = match meta.first() {
+                Some(Value::Bytes(unit)) => String::from_utf8_lossy(unit.inner()).into_owned(),
+                Some(Value::String(unit)) => unit.inner().clone(),
+                _ => return Err(invalid()),
+            };
+            dtype.unit = Some(match meta.get(1) {
+                Some(Value::I64(num)) if *num != 1 => format!("{num}{unit}"),
+                _ => unit,
+            });
+        }
+        Ok(Some(dtype))
+    }
+
+    /// The arguments to `numpy.dtype` and its state, as NumPy pickles them.
+    pub(crate) fn to_pickle(&self) -> (Value, Value) {
+        let string = |s: &str| Value::String(SharedFrozen::new(s.into()));
+        let tuple = |items: Vec<Value>| Value::Tuple(SharedFrozen::new(items));
+        let size = match self.kind {
+            'U' => self.size / 4,
+            _ => self.size,
+        };
+        let args = tuple(vec![
+            string(&format!("{}{size}", self.kind)),
+            Value::Bool(false),
+            Value::Bool(true),
+        ]);
+        let flexible = matches!(self.kind, 'S' | 'U' | 'V');
+        let (elsize, alignment) = match self.kind {
+            'U' => (self.size as i64, 4),
+            _ if flexible => (self.size as i64, 1),
+            _ => (-1, -1),
+        };
+        let flags = if self.kind == 'O' { 63 } else { 0 };
+        let mut state = vec![
+            Value::I64(3),
+            string(&self.byte_order.to_string()),
+            Value::None,
+            Value::None,
+            Value::None,
+            Value::I64(elsize),
+            Value::I64(alignment),
+            Value::I64(flags),
+        ];
+        if let Some(unit) = &self.unit {
+            let digits = unit.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+            let num = unit[..digits].parse().unwrap_or(1);
+            let base = Value::Bytes(SharedFrozen::new(unit.as_bytes()[digits..].to_vec()));
+            state[0] = Value::I64(4);
+            let unit = tuple(vec![base, Value::I64(num), Value::I64(1), Value::I64(1)]);
+            state.push(tuple(vec![Value::None, unit]));
+        }
+        (args, tuple(state))
+    }
+}
+
+impl fmt::Display for Dtype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.descr())
+    }
+}
+
+impl NdArray {
+    /// Return the number of items, or `usize::MAX` if the shape has more.
+    pub fn len(&self) -> usize {
+        checked_len(&self.shape).unwrap_or(usize::MAX)
+    }
+
+    /// Return whether the array has no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decode the items into a typed vector, in C order.
+    pub fn elements(&self) -> Result<Elements> {
+        let elements = match &self.data {
+            ArrayData::Objects(items) => Elements::Object(items.clone()),
+            ArrayData::Raw(bytes) => {
+                let size =
+                    checked_len(&self.shape).and_then(|len| len.checked_mul(self.dtype.size));
+                if self.dtype.size == 0 || size != Some(bytes.len()) {
+                    return Err(Error::Syntax(ErrorCode::InvalidValue(format!(
+                        "{} bytes of data for array of {} with shape {:?}",
+                        bytes.len(),
+                        self.dtype,
+                        self.shape
+                    ))));
+                }
+                match self.dtype.byte_order {
+                    '>' => decode_raw::<BigEndian>(&self.dtype, bytes)?,
+                    _ => decode_raw::<LittleEndian>(&self.dtype, bytes)?,
+                }
+            }
+        };
+        if elements.len() != self.len() {
+            return Err(Error::Syntax(ErrorCode::InvalidValue(format!(
+                "{} items for array with shape {:?}",
+                elements.len(),
+                self.shape
+            ))));
+        }
+        Ok(match self.fortran_order && self.shape.len() > 1 {
+            true => elements.into_c_order(&self.shape),
+            false => elements,
+        })
+    }
+
+    /// Convert the array into nested lists of Python values, like NumPy's
+    /// `tolist()`.
+    pub fn to_list(&self) -> Result<Value> {
+        fn nest(items: &mut impl Iterator<Item = Value>, shape: &[usize]) -> Value {
+            match shape {
+                [] => items.next().unwrap_or(Value::None),
+                [len, rest @ ..] => {
+                    let list = (0..*len).map(|_| nest(items, rest)).collect();
+                    Value::List(Shared::new(list))
+                }
+            }
+        }
+        let mut items = self.elements()?.into_values().into_iter();
+        Ok(nest(&mut items, &self.shape))
+    }
+}
+
+impl fmt::Display for NdArray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shape: Vec<_> = self.shape.iter().map(|len| len.to_string()).collect();
+        let comma = if shape.len() == 1 { "," } else { "" };
+        write!(
+            f,
+            "ndarray(shape=({}{comma}), dtype='{}')",
+            shape.join(", "),
+            self.dtype
+        )
+    }
+}
+
+// Whether items of the kind can have the size in bytes.
+fn valid_size(kind: char, size: usize) -> bool {
+    match kind {
+        'b' => size == 1,
+        'i' | 'u' => matches!(size, 1 | 2 | 4 | 8),
+        'f' => matches!(size, 2 | 4 | 8),
+        'c' => matches!(size, 8 | 16),
+        'M' | 'm' => size == 8,
+        'O' => matches!(size, 4 | 8),
+        'U' => size.is_multiple_of(4),
+        'S' | 'V' => true,
+        _ => false,
+    }
+}
+
+// The number of items of an array with the shape, if it fits in a `usize`.
+pub(crate) fn checked_len(shape: &[usize]) -> Option<usize> {
+    shape.iter().try_fold(1usize, |len, &n| len.checked_mul(n))
+}
+
+// Decode raw items, in storage order.
+fn decode_raw<B: ByteOrder>(dtype: &Dtype, bytes: &[u8]) -> Result<Elements> {
+    let size = dtype.size;
+    let items = bytes.chunks(size);
+    Ok(match (dtype.kind, size) {
+        ('b', _) => Elements::Bool(bytes.iter().map(|&b| b != 0).collect()),
+        ('i' | 'M' | 'm', _) => Elements::Int(items.map(|b| B::read_int(b, size)).collect()),
+        ('u', _) => Elements::UInt(items.map(|b| B::read_uint(b, size)).collect()),
+        ('f', 2) => Elements::Float(items.map(|b| f16_to_f64(B::read_u16(b))).collect()),
+        ('f', 4) => Elements::Float(items.map(|b| B::read_f32(b).into()).collect()),
+        ('f', 8) => Elements::Float(items.map(B::read_f64).collect()),
+        ('c', 8) => Elements::Complex(
+            items
+                .map(|b| (B::read_f32(b).into(), B::read_f32(&b[4..]).into()))
+                .collect(),
+        ),
+        ('c', 16) => Elements::Complex(
+            items
+                .map(|b| (B::read_f64(b), B::read_f64(&b[8..])))
+                .collect(),
+        ),
+        ('S', _) => Elements::Bytes(items.map(|b| trim_nuls(b).to_vec()).collect()),
+        ('V', _) => Elements::Bytes(items.map(<[u8]>::to_vec).collect()),
+        ('U', _) => {
+            let string = |b: &[u8]| {
+                let chars = b.chunks(4).map(|c| char::from_u32(B::read_u32(c)));
+                let string: Option<String> = chars.collect();
+                string.map(|s| s.trim_end_matches('\0').to_string())
+            };
+            let strings: Option<Vec<String>> = items.map(string).collect();
+            Elements::String(strings.ok_or_else(|| {
+                Error::Syntax(ErrorCode::InvalidValue(
+                    "array of invalid characters".into(),
+                ))
+            })?)
+        }
+        _ => return Err(unsupported(format!("dtype {dtype} with raw data"))),
+    })
+}
+
+fn trim_nuls(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+// Convert an IEEE 754 half-precision float.
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = i32::from((bits >> 10) & 0x1f);
+    let frac = f64::from(bits & 0x3ff);
+    match exp {
+        0 => sign * frac * 2f64.powi(-24),
+        31 if frac == 0.0 => sign * f64::INFINITY,
+        31 => f64::NAN,
+        _ => sign * (1.0 + frac / 1024.0) * 2f64.powi(exp - 15),
+    }
+}
+
+// Reorder items stored in Fortran order into C order.
+fn c_order<T: Clone>(items: Vec<T>, shape: &[usize]) -> Vec<T> {
+    let mut strides = Vec::with_capacity(shape.len());
+    let mut stride = 1;
+    for len in shape {
+        strides.push(stride);
+        stride *= len;
+    }
+    (0..items.len())
+        .map(|mut index| {
+            let mut offset = 0;
+            for (len, stride) in shape.iter().zip(&strides).rev() {
+                offset += index % len * stride;
+                index /= len;
+            }
+            items[offset].clone()
+        })
+        .collect()
+}
+
+impl Elements {
+    /// Return the number of items.
+    pub fn len(&self) -> usize {
+        match self {
+            Elements::Bool(v) => v.len(),
+            Elements::Int(v) => v.len(),
+            Elements::UInt(v) => v.len(),
+            Elements::Float(v) => v.len(),
+            Elements::Complex(v) => v.len(),
+            Elements::Bytes(v) => v.len(),
+            Elements::String(v) => v.len(),
+            Elements::Object(v) => v.len(),
+        }
+    }
+
+    /// Return whether there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn into_c_order(self, shape: &[usize]) -> Elements {
+        match self {
+            Elements::Bool(v) => Elements::Bool(c_order(v, shape)),
+            Elements::Int(v) => Elements::Int(c_order(v, shape)),
+            Elements::UInt(v) => Elements::UInt(c_order(v, shape)),
+            Elements::Float(v) => Elements::Float(c_order(v, shape)),
+            Elements::Complex(v) => Elements::Complex(c_order(v, shape)),
+            Elements::Bytes(v) => Elements::Bytes(c_order(v, shape)),
+            Elements::String(v) => Elements::String(c_order(v, shape)),
+            Elements::Object(v) => Elements::Object(c_order(v, shape)),
+        }
+    }
+
+    // Split the items into `count` runs of equal length, e.g. the rows of a
+    // 2-D array.  The runs are empty if there are no items.
+    pub(crate) fn chunks(self, count: usize) -> Vec<Elements> {
+        fn split<T>(items: Vec<T>, count: usize) -> Vec<Vec<T>> {
+            let mut items = items.into_iter();
+            let len = items.len().checked_div(count).unwrap_or(0);
+            (0..count)
+                .map(|_| items.by_ref().take(len).collect())
+                .collect()
+        }
+        match self {
+            Elements::Bool(v) => split(v, count).into_iter().map(Elements::Bool).collect(),
+            Elements::Int(v) => split(v, count).into_iter().map(Elements::Int).collect(),
+            Elements::UInt(v) => split(v, count).into_iter().map(Elements::UInt).collect(),
+            Elements::Float(v) => split(v, count).into_iter().map(Elements::Float).collect(),
+            Elements::Complex(v) => split(v, count).into_iter().map(Elements::Complex).collect(),
+            Elements::Bytes(v) => split(v, count).into_iter().map(Elements::Bytes).collect(),
+            Elements::String(v) => split(v, count).into_iter().map(Elements::String).collect(),
+            Elements::Object(v) => split(v, count).into_iter().map(Elements::Object).collect(),
+        }
+    }
+
+    /// Convert the items into Python values; complex numbers become
+    /// `(real, imag)` tuples.
+    pub fn into_values(self) -> Vec<Value> {
+        let bytes = |b: Vec<u8>| Value::Bytes(SharedFrozen::new(b));
+        match self {
+            Elements::Bool(v) => v.into_iter().map(Value::Bool).collect(),
+            Elements::Int(v) => v.into_iter().map(Value::I64).collect(),
+            Elements::UInt(v) => (v.into_iter())
+                .map(|u| i64::try_from(u).map_or_else(|_| Value::Int(u.into()), Value::I64))
+                .collect(),
+            Elements::Float(v) => v.into_iter().map(Value::F64).collect(),
+            Elements::Complex(v) => (v.into_iter())
+                .map(|(re, im)| {
+                    Value::Tuple(SharedFrozen::new(vec![Value::F64(re), Value::F64(im)]))
+                })
+                .collect(),
+            Elements::Bytes(v) => v.into_iter().map(bytes).collect(),
+            Elements::String(v) => (v.into_iter())
+                .map(|s| Value::String(SharedFrozen::new(s)))
+                .collect(),
+            Elements::Object(v) => v,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! NumPy `.npy` files, as written by `numpy.save`.
+//!
+//! A `.npy` file starts with the magic string `\x93NUMPY`, a format version
+//! and a header that is a Python dict literal, e.g. `{'descr': '<f8',
+//! 'fortran_order': False, 'shape': (2, 3), }`.  The raw items follow, or
+//! for arrays of the `object` dtype, the array pickled as a whole.
+//!
+//! Arrays are read into the same [`NdArray`] that the pickle decoder
+//! produces.  Structured dtypes are not supported.
+//!
+//! ```no_run
+//! # fn main() -> pickled::Result<()> {
+//! let file = std::fs::File::open("weights.npy")?;
+//! let array = pickled::npy::read(file, Default::default())?;
+//! println!("{} {:?}", array.dtype, array.shape);
+//! # Ok(())
+//! # }
+//! ```
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use crate::de::{DeOptions, value_from_reader};
+use crate::error::{Error, ErrorCode, Result};
+use crate::ndarray::{ArrayData, Dtype, NdArray};
+use crate::ser::{SerOptions, value_to_writer};
+use crate::value::{HashableValue, Shared, SharedFrozen, Value};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+// The deepest nesting of containers in a header; NumPy's own headers nest
+// at most a few levels, in the descr of structured dtypes.
+const MAX_DEPTH: usize = 32;
+
+/// Read an array from a `.npy` file.
+///
+/// The options are used to decode the pickled items of `object` arrays.
+pub fn read<R: Read>(mut rdr: R, options: DeOptions) -> Result<NdArray> {
+    let mut magic = [0; 8];
+    rdr.read_exact(&mut magic)?;
+    if &magic[..6] != MAGIC {
+        return Err(invalid("bad magic string".into()));
+    }
+    let len = match magic[6] {
+        1 => {
+            let mut len = [0; 2];
+            rdr.read_exact(&mut len)?;
+            LittleEndian::read_u16(&len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0; 4];
+            rdr.read_exact(&mut len)?;
+            LittleEndian::read_u32(&len) as usize
+        }
+        major => {
+            return Err(Error::Syntax(ErrorCode::Structure(format!(
+                "unsupported .npy format version {major}.{}",
+                magic[7]
+            ))));
+        }
+    };
+    let mut header = Vec::new();
+    (&mut rdr).take(len as u64).read_to_end(&mut header)?;
+    if header.len() != len {
+        return Err(invalid("truncated header".into()));
+    }
+    // Versions 1 and 2 have a latin-1 header, version 3 a UTF-8 one.
+    let header = match magic[6] {
+        3 => String::from_utf8(header).map_err(|_| invalid("header is not UTF-8".into()))?,
+        _ => header.into_iter().map(char::from).collect(),
+    };
+    let (dtype, fortran_order, shape) = parse_header(&header)?;
+
+    if dtype.kind == 'O' {
+        return match value_from_reader(rdr, options)? {
+            Value::NdArray(array) => Ok(array.inner().clone()),
+            value => Err(invalid(format!("pickled {value} instead of an array"))),
+        };
+    }
+    let len = (shape.iter())
+        .try_fold(dtype.size, |len, &n| len.checked_mul(n))
+        .ok_or_else(|| invalid("array too large".into()))?;
+    let mut data = Vec::new();
+    rdr.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(invalid("truncated data".into()));
+    }
+    Ok(NdArray {
+        dtype,
+        shape,
+        fortran_order,
+        data: ArrayData::Raw(data),
+    })
+}
+
+/// Write an array as a `.npy` file.
+///
+/// The options are used to pickle the items of `object` arrays.
+pub fn write<W: Write>(writer: &mut W, array: &NdArray, options: SerOptions) -> Result<()> {
+    let shape = match &array.shape[..] {
+        [n] => format!("({n},)"),
+        shape => {
+            let shape = shape.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+            format!("({})", shape.join(", "))
+        }
+    };
+    let fortran_order = if array.fortran_order { "True" } else { "False" };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': {fortran_order}, 'shape': {shape}, }}",
+        array.dtype.descr()
+    );
+    // Like NumPy, pad the header with spaces so that the data is aligned to
+    // 64 bytes, and end it with a newline.
+    let (version, prefix) = if header.len() + 64 <= u16::MAX as usize {
+        (1, 10)
+    } else {
+        (2, 12)
+    };
+    let total = (prefix + header.len() + 1).next_multiple_of(64);
+    header.extend(std::iter::repeat_n(' ', total - prefix - header.len() - 1));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[version, 0])?;
+    let len = header.len();
+    if version == 1 {
+        writer.write_all(&(len as u16).to_le_bytes())?;
+    } else {
+        writer.write_all(&(len as u32).to_le_bytes())?;
+    }
+    writer.write_all(header.as_bytes())?;
+    match &array.data {
+        ArrayData::Raw(data) => writer.write_all(data)?,
+        ArrayData::Objects(_) => {
+            let array = Value::NdArray(Shared::new(array.clone()));
+            value_to_writer(writer, &array, options)?;
+        }
+    }
+    Ok(())
+}
+
+// Take the dtype, order and shape from the header.
+fn parse_header(header: &str) -> Result<(Dtype, bool, Vec<usize>)> {
+    let mut rest = header;
+    let header = match literal(&mut rest, MAX_DEPTH) {
+        Some(Value::Dict(header)) if rest.trim().is_empty() => header,
+        _ => return Err(invalid(format!("header {header:?}"))),
+    };
+    let header = header.inner();
+    let item = |name: &str| {
+        let key = HashableValue::String(SharedFrozen::new(name.into()));
+        (header.get(&key).cloned()).ok_or_else(|| invalid(format!("header without {name}")))
+    };
+    let dtype = match item("descr")? {
+        Value::String(descr) => {
+            let descr = descr.inner();
+            Dtype::parse(descr).ok_or_else(|| {
+                Error::Syntax(ErrorCode::Structure(format!(
+                    "unsupported numpy dtype {descr}"
+                )))
+            })?
+        }
+        Value::List(_) => {
+            return Err(Error::Syntax(ErrorCode::Structure(
+                "unsupported structured numpy dtype".into(),
+            )));
+        }
+        descr => return Err(invalid(format!("descr {descr}"))),
+    };
+    let fortran_order = match item("fortran_order")? {
+        Value::Bool(b) => b,
+        order => return Err(invalid(format!("fortran_order {order}"))),
+    };
+    let shape = match item("shape")? {
+        Value::Tuple(shape) => (shape.inner().iter())
+            .map(|n| match n {
+                Value::I64(n) => usize::try_from(*n).ok(),
+                _ => None,
+            })
+            .collect::<Option<_>>(),
+        _ => None,
+    };
+    let shape = shape.ok_or_else(|| invalid("shape".into()))?;
+    Ok((dtype, fortran_order, shape))
+}
+
+// Parse a Python literal of the kinds NumPy writes in headers: dicts, lists
+// and tuples, strings without escapes, integers (with Python 2's `L` suffix
+// in old files), booleans and `None`.  Containers may nest `depth` levels.
+fn literal(s: &mut &str, depth: usize) -> Option<Value> {
+    *s = s.trim_start();
+    let first = s.chars().next()?;
+    if matches!(first, '{' | '(' | '[') && depth == 0 {
+        return None;
+    }
+    let value = match first {
+        '{' => {
+            *s = &s[1..];
+            let mut dict = BTreeMap::new();
+            while !eat(s, '}') {
+                let key = literal(s, depth - 1)?.into_hashable().ok()?;
+                if !eat(s, ':') {
+                    return None;
+                }
+                dict.insert(key, literal(s, depth - 1)?);
+                if !eat(s, ',') && !s.trim_start().starts_with('}') {
+                    return None;
+                }
+            }
+            Value::Dict(Shared::new(dict))
+        }
+        '(' | '[' => {
+            *s = &s[1..];
+            let close = if first == '(' { ')' } else { ']' };
+            let mut items = Vec::new();
+            while !eat(s, close) {
+                items.push(literal(s, depth - 1)?);
+                if !eat(s, ',') && !s.trim_start().starts_with(close) {
+                    return None;
+                }
+            }
+            if first == '(' {
+                Value::Tuple(SharedFrozen::new(items))
+            } else {
+                Value::List(Shared::new(items))
+            }
+        }
+        '\'' | '"' => {
+            let end = s[1..].find(first)? + 1;
+            let string = &s[1..end];
+            *s = &s[end + 1..];
+            Value::String(SharedFrozen::new(string.into()))
+        }
+        _ => {
+            let end = (s.find(|c: char| !c.is_ascii_alphanumeric() && c != '-')).unwrap_or(s.len());
+            let (token, rest) = s.split_at(end);
+            *s = rest;
+            match token {
+                "True" => Value::Bool(true),
+                "False" => Value::Bool(false),
+                "None" => Value::None,
+                _ => Value::I64(token.strip_suffix('L').unwrap_or(token).parse().ok()?),
+            }
+        }
+    };
+    Some(value)
+}
+
+// Skip whitespace and the given character, if it comes next.
+fn eat(s: &mut &str, c: char) -> bool {
+    *s = s.trim_start();
+    match s.strip_prefix(c) {
+        Some(rest) => {
+            *s = rest;
+            true
+        }
+        None => false,
+    }
+}
+
+fn invalid(what: String) -> Error {
+    Error::Syntax(ErrorCode::InvalidValue(format!(".npy file: {what}")))
+}
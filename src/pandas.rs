@@ -0,0 +1,367 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! pandas DataFrames and Series.
+//!
+//! [`read_dataframe`] and [`read_series`] decode the pickles written by
+//! `DataFrame.to_pickle()` and `Series.to_pickle()` into typed columns.
+//! pandas keeps the data in a block manager, which is pickled either as
+//! `BlockManager(blocks, axes)` (pandas 1.4 and later) or with the older
+//! `(axes, values, items, extra_state)` state; both are understood.
+//!
+//! The columns must be backed by NumPy arrays (see [`crate::ndarray`]).
+//! Extension arrays, as used for categoricals, nullable integers or
+//! timezone-aware datetimes, are not supported and give an error.
+
+use std::io::Read;
+
+use crate::de::{DeOptions, value_from_reader};
+use crate::error::{Error, ErrorCode, Result};
+use crate::ndarray::{Dtype, Elements, NdArray};
+use crate::value::{HashableValue, SharedFrozen, Value};
+
+/// A pandas `DataFrame`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataFrame {
+    /// The row labels.
+    pub index: Index,
+    /// The columns, in order.
+    pub columns: Vec<Column>,
+}
+
+/// A column of a [`DataFrame`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    /// The column label, usually a string.
+    pub name: Value,
+    /// The NumPy dtype of the items.
+    pub dtype: Dtype,
+    /// The items, one per row.
+    pub values: Elements,
+}
+
+/// A pandas `Series`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    /// The name of the series, or `None`.
+    pub name: Value,
+    /// The labels of the items.
+    pub index: Index,
+    /// The NumPy dtype of the items.
+    pub dtype: Dtype,
+    /// The items.
+    pub values: Elements,
+}
+
+/// A pandas `Index`: the row labels of a [`DataFrame`] or [`Series`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Index {
+    /// The name of the index, or `None`.
+    pub name: Value,
+    /// The labels.
+    pub labels: Labels,
+}
+
+/// The labels of an [`Index`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Labels {
+    /// A `RangeIndex`, like Python's `range(start, stop, step)`.
+    Range { start: i64, stop: i64, step: i64 },
+    /// Labels stored in an array.
+    Array(Elements),
+}
+
+impl Index {
+    /// Return the number of labels.
+    pub fn len(&self) -> usize {
+        match self.labels {
+            Labels::Range { start, stop, step } => range_len(start, stop, step),
+            Labels::Array(ref elements) => elements.len(),
+        }
+    }
+
+    /// Return whether there are no labels.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the labels as Python values.
+    pub fn to_values(&self) -> Vec<Value> {
+        match self.labels {
+            Labels::Range { start, step, .. } => (0..self.len() as i64)
+                .map(|i| Value::I64(start + i * step))
+                .collect(),
+            Labels::Array(ref elements) => elements.clone().into_values(),
+        }
+    }
+}
+
+impl DataFrame {
+    /// Return the column with the given label, if there is one.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        (self.columns.iter()).find(|col| matches!(&col.name, Value::String(s) if s.inner() == name))
+    }
+
+    /// Convert a `DataFrame` decoded with [`DeOptions::keep_objects`].
+    pub fn from_value(value: &Value) -> Result<DataFrame> {
+        let (axes, blocks) = manager(value, "dataframe")?;
+        let [columns, index] = &axes[..] else {
+            return Err(invalid("pandas DataFrame axes"));
+        };
+        let (names, index) = (columns.to_values(), index.clone());
+        let mut columns = vec![None; names.len()];
+        for (array, placement) in blocks {
+            let values = array.elements()?;
+            if placement.len().checked_mul(index.len()) != Some(values.len()) {
+                return Err(invalid(format!("pandas block of shape {:?}", array.shape)));
+            }
+            let chunks = values.chunks(placement.len());
+            for (loc, values) in placement.into_iter().zip(chunks) {
+                let name = names
+                    .get(loc)
+                    .ok_or_else(|| invalid("pandas block placement"))?;
+                columns[loc] = Some(Column {
+                    name: name.clone(),
+                    dtype: array.dtype.clone(),
+                    values,
+                });
+            }
+        }
+        let columns = columns.into_iter().collect::<Option<_>>();
+        let columns = columns.ok_or_else(|| invalid("pandas DataFrame with missing columns"))?;
+        Ok(DataFrame { index, columns })
+    }
+}
+
+impl Series {
+    /// Convert a `Series` decoded with [`DeOptions::keep_objects`].
+    pub fn from_value(value: &Value) -> Result<Series> {
+        let (axes, blocks) = manager(value, "series")?;
+        let ([index], [(array, _)]) = (&axes[..], &blocks[..]) else {
+            return Err(invalid("pandas Series block manager"));
+        };
+        let values = array.elements()?;
+        if values.len() != index.len() {
+            return Err(invalid(format!("pandas block of shape {:?}", array.shape)));
+        }
+        let name = match value {
+            Value::Object(object) => object.inner().attr("_name"),
+            _ => None,
+        };
+        Ok(Series {
+            name: name.unwrap_or(Value::None),
+            index: index.clone(),
+            dtype: array.dtype.clone(),
+            values,
+        })
+    }
+}
+
+/// Decode a pickled `DataFrame` from a reader.
+///
+/// The [`DeOptions::keep_objects`] option is always enabled.
+pub fn read_dataframe<R: Read>(rdr: R, options: DeOptions) -> Result<DataFrame> {
+    DataFrame::from_value(&value_from_reader(rdr, options.keep_objects())?)
+}
+
+/// Decode a pickled `Series` from a reader.
+///
+/// The [`DeOptions::keep_objects`] option is always enabled.
+pub fn read_series<R: Read>(rdr: R, options: DeOptions) -> Result<Series> {
+    Series::from_value(&value_from_reader(rdr, options.keep_objects())?)
+}
+
+type Block = (NdArray, Vec<usize>);
+
+// Find the axes and blocks of the block manager of a DataFrame or Series.
+fn manager(value: &Value, typ: &str) -> Result<(Vec<Index>, Vec<Block>)> {
+    let Value::Object(object) = value else {
+        return Err(invalid(format!("expected a pandas {typ}, got {value}")));
+    };
+    let object = object.inner();
+    match object.attr("_typ") {
+        Some(Value::String(found)) if found.inner() == typ => {}
+        _ => {
+            return Err(invalid(format!(
+                "expected a pandas {typ}, got {}",
+                object.class_path()
+            )));
+        }
+    }
+    // pandas before 1.1 calls the manager `_data`.
+    let mgr = (object.attr("_mgr").or_else(|| object.attr("_data")))
+        .ok_or_else(|| invalid("pandas object without block manager"))?;
+    let Value::Object(mgr) = mgr else {
+        return Err(invalid("pandas block manager"));
+    };
+    let mgr = mgr.inner();
+    let (axes, blocks) = match (&mgr.args[..], &mgr.state) {
+        // BlockManager(blocks, axes) and SingleBlockManager(block, axis)
+        ([Value::List(blocks), Value::List(axes)], _) => {
+            let blocks = blocks.inner().iter().map(block).collect::<Result<_>>()?;
+            (axes.inner().clone(), blocks)
+        }
+        ([block_value, axis], _) => (vec![axis.clone()], vec![block(block_value)?]),
+        // The state `(axes, values, items, {"0.14.1": {"axes": ..., "blocks":
+        // [{"values": ..., "mgr_locs": ...}]}})`
+        ([], Some(Value::Tuple(state))) if state.inner().len() >= 4 => {
+            let extra = item(&state.inner()[3], "0.14.1");
+            let axes = extra.as_ref().and_then(|extra| item(extra, "axes"));
+            let blocks = extra.as_ref().and_then(|extra| item(extra, "blocks"));
+            let (Some(Value::List(axes)), Some(Value::List(blocks))) = (axes, blocks) else {
+                return Err(invalid("pandas block manager state"));
+            };
+            let blocks = (blocks.inner().iter())
+                .map(
+                    |block| match (item(block, "values"), item(block, "mgr_locs")) {
+                        (Some(values), Some(locs)) => Ok((array(&values)?, placement(&locs)?)),
+                        _ => Err(invalid("pandas block")),
+                    },
+                )
+                .collect::<Result<_>>()?;
+            let axes = axes.inner().clone();
+            (axes, blocks)
+        }
+        _ => {
+            return Err(invalid(format!(
+                "pandas block manager {}",
+                mgr.class_path()
+            )));
+        }
+    };
+    let axes = axes.iter().map(index).collect::<Result<_>>()?;
+    Ok((axes, blocks))
+}
+
+// Decode `_unpickle_block(values, placement, ndim)`.
+fn block(value: &Value) -> Result<Block> {
+    match value {
+        Value::Object(object) if object.inner().args.len() >= 2 => {
+            let args = &object.inner().args;
+            Ok((array(&args[0])?, placement(&args[1])?))
+        }
+        _ => Err(invalid(format!("pandas block, got {value}"))),
+    }
+}
+
+fn array(value: &Value) -> Result<NdArray> {
+    match value {
+        Value::NdArray(array) => Ok(array.inner().clone()),
+        // NumPy arrays that are kept as objects have unsupported dtypes.
+        Value::Object(object) if object.inner().module.starts_with("numpy.") => {
+            Err(Error::Syntax(ErrorCode::Structure(format!(
+                "unsupported numpy array {}",
+                object.inner().class_path()
+            ))))
+        }
+        Value::Object(object) => Err(Error::Syntax(ErrorCode::Structure(format!(
+            "unsupported pandas extension array {}",
+            object.inner().class_path()
+        )))),
+        _ => Err(invalid(format!("pandas block values, got {value}"))),
+    }
+}
+
+// Decode the column numbers of a block: a slice, or an array of ints.
+fn placement(value: &Value) -> Result<Vec<usize>> {
+    let int = |value: &Value| match value {
+        Value::I64(i) => Some(*i),
+        _ => None,
+    };
+    let locs = match value {
+        Value::Object(object) if object.inner().class_path() == "builtins.slice" => {
+            let args = &object.inner().args;
+            let (start, stop) = match &args[..] {
+                [start, stop, ..] => (int(start).unwrap_or(0), int(stop)),
+                _ => (0, None),
+            };
+            let step = args.get(2).and_then(int).unwrap_or(1);
+            let stop = stop.ok_or_else(|| invalid("pandas block placement"))?;
+            if step <= 0 {
+                return Err(invalid("pandas block placement"));
+            }
+            (start..stop).step_by(step as usize).collect()
+        }
+        Value::NdArray(array) => match array.inner().elements()? {
+            Elements::Int(locs) => locs,
+            Elements::UInt(locs) => locs.into_iter().map(|loc| loc as i64).collect(),
+            _ => return Err(invalid("pandas block placement")),
+        },
+        Value::List(locs) => (locs.inner().iter())
+            .map(|loc| int(loc).unwrap_or(-1))
+            .collect(),
+        _ => return Err(invalid(format!("pandas block placement, got {value}"))),
+    };
+    (locs.into_iter())
+        .map(|loc| usize::try_from(loc).map_err(|_| invalid("pandas block placement")))
+        .collect()
+}
+
+// Decode `_new_Index(cls, {"data": ..., "name": ...})`, or `{"start": ...,
+// "stop": ..., "step": ..., "name": ...}` for a RangeIndex.
+fn index(value: &Value) -> Result<Index> {
+    let unsupported = |what: String| {
+        Error::Syntax(ErrorCode::Structure(format!(
+            "unsupported pandas index {what}"
+        )))
+    };
+    let Value::Object(object) = value else {
+        return Err(invalid(format!("pandas index, got {value}")));
+    };
+    let object = object.inner();
+    let (class, dict) = match (&*object.name, &object.args[..]) {
+        ("_new_Index", [Value::Global(module, name), dict]) => (format!("{module}.{name}"), dict),
+        _ => return Err(unsupported(object.class_path())),
+    };
+    let name = item(dict, "name").unwrap_or(Value::None);
+    let labels = if class.ends_with(".RangeIndex") {
+        let int = |key| match item(dict, key) {
+            Some(Value::I64(i)) => Ok(i),
+            None if key == "step" => Ok(1),
+            _ => Err(invalid("pandas RangeIndex")),
+        };
+        let (start, stop, step) = (int("start")?, int("stop")?, int("step")?);
+        if step == 0 {
+            return Err(invalid("pandas RangeIndex"));
+        }
+        Labels::Range { start, stop, step }
+    } else {
+        match item(dict, "data") {
+            Some(Value::NdArray(array)) if array.inner().shape.len() == 1 => {
+                Labels::Array(array.inner().elements()?)
+            }
+            _ => return Err(unsupported(class)),
+        }
+    };
+    Ok(Index { name, labels })
+}
+
+// Look up a string key in a dict.
+fn item(dict: &Value, key: &str) -> Option<Value> {
+    let Value::Dict(dict) = dict else {
+        return None;
+    };
+    let dict = dict.inner();
+    dict.get(&HashableValue::String(SharedFrozen::new(key.into())))
+        .or_else(|| dict.get(&HashableValue::Bytes(SharedFrozen::new(key.into()))))
+        .cloned()
+}
+
+fn range_len(start: i64, stop: i64, step: i64) -> usize {
+    if step == 0 {
+        return 0;
+    }
+    let span = match step > 0 {
+        true => i128::from(stop) - i128::from(start),
+        false => i128::from(start) - i128::from(stop),
+    };
+    let step = i128::from(step).abs();
+    usize::try_from((span + step - 1).div_euclid(step)).unwrap_or(0)
+}
+
+fn invalid(what: impl Into<String>) -> Error {
+    Error::Syntax(ErrorCode::InvalidValue(what.into()))
+}
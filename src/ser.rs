@@ -14,12 +14,19 @@ use serde::ser;
 use serde::ser::Serialize;
 use serde::ser::SerializeMap;
 use serde::ser::SerializeSeq;
-use std::collections::BTreeSet;
+use serde::ser::SerializeStruct;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io;
+use std::mem;
+use std::str::FromStr;
 
+use super::compat;
 use super::consts::*;
-use super::error::{Error, Result};
-use super::value::{HashableValue, Value};
+use super::error::{Error, ErrorCode, Result};
+use super::ndarray::{ArrayData, NdArray};
+use super::types;
+use super::types::{EnumValues, PyEnum};
+use super::value::{HashableValue, Object, Shared, SharedFrozen, Value, to_value, wtf8_lossy};
 
 /// Supported pickle protocols for writing.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -39,6 +46,10 @@ impl Default for PickleProto {
 pub struct SerOptions {
     proto: PickleProto,
     compat_enum_repr: bool,
+    structs_as_namespace: bool,
+    enums: BTreeMap<&'static str, PyEnum>,
+    namedtuples: BTreeMap<&'static str, (String, String)>,
+    u8_sequences_as_bytes: bool,
 }
 
 impl SerOptions {
@@ -79,17 +90,68 @@ impl SerOptions {
         self.compat_enum_repr = true;
         self
     }
+
+    /// Serialize structs as `types.SimpleNamespace` instances instead of dicts.
+    ///
+    /// This allows Python code to access the fields as attributes
+    /// (`obj.field`) without having to define a class.  The deserializer
+    /// turns such objects back into maps, so they can be decoded into the
+    /// same structs again.
+    pub fn structs_as_namespace(mut self) -> Self {
+        self.structs_as_namespace = true;
+        self
+    }
+
+    /// Serialize the unit variants of the Rust enum named `rust_name` as
+    /// members of the Python `enum.Enum` subclass `class` (given as
+    /// `"module.Name"`), with the member values given by `values`.
+    ///
+    /// Python pickles enum members as a call of the class with the member
+    /// value, which is what this writes.
+    pub fn python_enum(mut self, rust_name: &'static str, class: &str, values: EnumValues) -> Self {
+        self.enums.insert(rust_name, PyEnum::new(class, values));
+        self
+    }
+
+    /// Serialize sequences whose elements are all `u8` as Python `bytes`,
+    /// instead of as lists of integers.
+    ///
+    /// This covers e.g. `Vec<u8>`, `&[u8]` and `Box<[u8]>`; empty sequences
+    /// stay lists.  Arrays like `[u8; N]` are tuples to serde, and are still
+    /// written as tuples, like Rust tuples of `u8`.
+    pub fn u8_sequences_as_bytes(mut self) -> Self {
+        self.u8_sequences_as_bytes = true;
+        self
+    }
+
+    /// Serialize the Rust struct or tuple struct named `rust_name` as an
+    /// instance of the Python namedtuple `class` (given as `"module.Name"`).
+    ///
+    /// The fields are written positionally, in declaration order, so they
+    /// have to match the order of the namedtuple's fields.
+    pub fn namedtuple(mut self, rust_name: &'static str, class: &str) -> Self {
+        let (module, name) = types::split_class(class);
+        self.namedtuples
+            .insert(rust_name, (module.into(), name.into()));
+        self
+    }
 }
 
 /// A structure for serializing Rust values into a Pickle stream.
 pub struct Serializer<W> {
     writer: W,
     options: SerOptions,
+    // set by `types::PyTuple` for the next sequence
+    seq_as_tuple: bool,
 }
 
 impl<W: io::Write> Serializer<W> {
     pub fn new(writer: W, options: SerOptions) -> Self {
-        Serializer { writer, options }
+        Serializer {
+            writer,
+            options,
+            seq_as_tuple: false,
+        }
     }
 
     /// Unwrap the `Writer` from the `Serializer`.
@@ -102,6 +164,13 @@ impl<W: io::Write> Serializer<W> {
         self.writer.write_all(&[opcode.into()]).map_err(From::from)
     }
 
+    // Write a string from its UTF-8 encoding, which may contain surrogates.
+    fn write_unicode(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_opcode(Opcode::BinUnicode)?;
+        self.writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+        self.writer.write_all(bytes).map_err(From::from)
+    }
+
     fn serialize_hashable_value(&mut self, value: &HashableValue) -> Result<()> {
         use serde::Serializer;
         match *value {
@@ -111,6 +180,7 @@ impl<W: io::Write> Serializer<W> {
             HashableValue::F64(f) => self.serialize_f64(f),
             HashableValue::Bytes(ref b) => self.serialize_bytes(b.inner()),
             HashableValue::String(ref s) => self.serialize_str(s.inner()),
+            HashableValue::WtfString(ref s) => self.write_unicode(s.inner()),
             HashableValue::Int(ref i) => self.serialize_bigint(i),
             HashableValue::FrozenSet(ref s) => self.serialize_set(s.inner(), b"frozenset"),
             HashableValue::Tuple(ref t) => {
@@ -127,7 +197,9 @@ impl<W: io::Write> Serializer<W> {
             Value::I64(i) => self.serialize_i64(i),
             Value::F64(f) => self.serialize_f64(f),
             Value::Bytes(ref b) => self.serialize_bytes(b.inner()),
+            Value::ByteArray(ref b) => types::bytearray::serialize(&*b.inner(), &mut *self),
             Value::String(ref s) => self.serialize_str(s.inner()),
+            Value::WtfString(ref s) => self.write_unicode(s.inner()),
             Value::List(ref l) => {
                 self.write_opcode(Opcode::EmptyList)?;
                 for chunk in l.inner().chunks(1000) {
@@ -159,7 +231,69 @@ impl<W: io::Write> Serializer<W> {
             }
             Value::Set(ref s) => self.serialize_set(&s.inner(), b"set"),
             Value::FrozenSet(ref s) => self.serialize_set(s.inner(), b"frozenset"),
+            Value::Object(ref o) => {
+                let o = o.inner();
+                let header = ObjectHeader {
+                    module: o.module.clone(),
+                    name: o.name.clone(),
+                    new: o.new,
+                    kwargs: (!o.kwargs.is_empty()).then(|| Value::Dict(o.kwargs.clone().into())),
+                    created: false,
+                };
+                self.write_object_header(&header, |slf| slf.write_object_args(&header, &o.args))?;
+                if let Some(ref items) = o.items {
+                    self.write_object_items(items)?;
+                }
+                if let Some(ref state) = o.state {
+                    self.serialize_value(state)?;
+                    self.write_opcode(Opcode::Build)?;
+                }
+                Ok(())
+            }
+            Value::NdArray(ref a) => self.serialize_ndarray(&a.inner()),
+            Value::PersistentId(ref pid) => {
+                self.serialize_value(pid)?;
+                self.write_opcode(Opcode::BinPersId)
+            }
+            Value::Global(ref module, ref name) => self.write_global(module, name),
+        }
+    }
+
+    // Write an array as NumPy pickles it: numpy.core.multiarray._reconstruct(
+    // numpy.ndarray, (0,), b'b'), with the state (1, shape, dtype,
+    // fortran_order, data) set by BUILD.  NumPy 2 still loads it from the old
+    // module name.
+    fn serialize_ndarray(&mut self, array: &NdArray) -> Result<()> {
+        use serde::Serializer;
+        self.write_global("numpy.core.multiarray", "_reconstruct")?;
+        self.write_global("numpy", "ndarray")?;
+        self.serialize_value(&Value::Tuple(SharedFrozen::new(vec![Value::I64(0)])))?;
+        self.serialize_bytes(b"b")?;
+        self.write_opcode(Opcode::Tuple3)?;
+        self.write_opcode(Opcode::Reduce)?;
+        self.write_opcode(Opcode::Mark)?;
+        self.serialize_i64(1)?;
+        let shape = array
+            .shape
+            .iter()
+            .map(|&len| Value::I64(len as i64))
+            .collect();
+        self.serialize_value(&Value::Tuple(SharedFrozen::new(shape)))?;
+        let (args, state) = array.dtype.to_pickle();
+        self.write_global("numpy", "dtype")?;
+        self.serialize_value(&args)?;
+        self.write_opcode(Opcode::Reduce)?;
+        self.serialize_value(&state)?;
+        self.write_opcode(Opcode::Build)?;
+        self.serialize_bool(array.fortran_order)?;
+        match array.data {
+            ArrayData::Raw(ref bytes) => self.serialize_bytes(bytes)?,
+            ArrayData::Objects(ref items) => {
+                self.serialize_value(&Value::List(Shared::new(items.clone())))?
+            }
         }
+        self.write_opcode(Opcode::Tuple)?;
+        self.write_opcode(Opcode::Build)
     }
 
     fn serialize_bigint(&mut self, i: &BigInt) -> Result<()> {
@@ -219,7 +353,197 @@ impl<W: io::Write> Serializer<W> {
         }
     }
 
-    fn serialize_set(&mut self, items: &BTreeSet<HashableValue>, name: &[u8]) -> Result<()> {
+    // Write a GLOBAL opcode, with the Python 2 names for protocol 2.
+    fn write_global(&mut self, module: &str, name: &str) -> Result<()> {
+        let (module, name) = match self.options.proto {
+            PickleProto::V2 => compat::to_py2(module, name),
+            PickleProto::V3 => (module, name),
+        };
+        self.write_opcode(Opcode::Global)?;
+        self.writer.write_all(module.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.write_all(name.as_bytes())?;
+        self.writer.write_all(b"\n").map_err(From::from)
+    }
+
+    // Start writing a tuple; with `bytes`, a tuple of u8 is written as bytes.
+    fn begin_tuple(&mut self, len: usize) -> Result<Compound<'_, W>> {
+        self.seq_as_tuple = false;
+        if len == 0 {
+            self.write_opcode(Opcode::EmptyTuple)?;
+            Ok(Compound {
+                ser: self,
+                state: None,
+                tuple: false,
+                object: None,
+                newobj: false,
+                bytes: None,
+            })
+        } else {
+            self.write_opcode(Opcode::Mark)?;
+            Ok(Compound {
+                ser: self,
+                state: Some(0),
+                tuple: false,
+                object: None,
+                newobj: false,
+                bytes: None,
+            })
+        }
+    }
+
+    // Start writing a struct as namedtuple, created by NEWOBJ from its fields.
+    fn serialize_namedtuple(&mut self, name: &str, len: usize) -> Result<Compound<'_, W>> {
+        let (module, class) = self.options.namedtuples[name].clone();
+        self.write_global(&module, &class)?;
+        let mut compound = self.begin_tuple(len)?;
+        compound.newobj = true;
+        Ok(compound)
+    }
+
+    // Write the instructions that create an object, with the argument tuple
+    // written by `args`.
+    fn write_object_header<F>(&mut self, header: &ObjectHeader, args: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        if header.is_singleton() {
+            // Ellipsis and NotImplemented are referred to by name.
+            return self.write_global(&header.module, &header.name);
+        }
+        match &header.kwargs {
+            Some(kwargs) => {
+                // copyreg.__newobj_ex__(cls, args, kwargs), as Python does for
+                // protocols without NEWOBJ_EX
+                self.write_global("copyreg", "__newobj_ex__")?;
+                self.write_global(&header.module, &header.name)?;
+                args(self)?;
+                self.serialize_value(kwargs)?;
+                self.write_opcode(Opcode::Tuple3)?;
+                self.write_opcode(Opcode::Reduce)
+            }
+            None => {
+                self.write_global(&header.module, &header.name)?;
+                args(self)?;
+                self.write_opcode(if header.new {
+                    Opcode::NewObj
+                } else {
+                    Opcode::Reduce
+                })
+            }
+        }
+    }
+
+    // Write the argument tuple of an object.  The default factory of a
+    // `collections.defaultdict` is given by its class path, and written as a
+    // global.
+    fn write_object_args(&mut self, header: &ObjectHeader, args: &[Value]) -> Result<()> {
+        match args {
+            [Value::String(factory)] if header.is_defaultdict() => {
+                let (module, name) = types::split_class(factory.inner());
+                self.write_global(module, name)?;
+                self.write_opcode(Opcode::Tuple1)
+            }
+            _ => self.serialize_tuplevalue(args, |slf, v| slf.serialize_value(v)),
+        }
+    }
+
+    // Write the contents of an instance of a list, dict or set subclass, like
+    // Python does for the list and dict items returned by `__reduce__`.
+    fn write_object_items(&mut self, items: &Value) -> Result<()> {
+        match *items {
+            Value::List(ref l) => {
+                self.write_batched(l.inner().iter(), Opcode::Appends, |slf, item| {
+                    slf.serialize_value(item)
+                })
+            }
+            Value::Dict(ref d) => {
+                self.write_batched(d.inner().iter(), Opcode::SetItems, |slf, (key, value)| {
+                    slf.serialize_hashable_value(key)?;
+                    slf.serialize_value(value)
+                })
+            }
+            Value::Set(ref s) => {
+                self.write_batched(s.inner().iter(), Opcode::AddItems, |slf, item| {
+                    slf.serialize_hashable_value(item)
+                })
+            }
+            ref other => Err(Error::Syntax(ErrorCode::InvalidValue(other.to_string()))),
+        }
+    }
+
+    // Write items between MARK and the `batch` opcode (APPENDS, SETITEMS or
+    // ADDITEMS), starting a new batch every 1000 items as Python pickle does.
+    fn write_batched<T>(
+        &mut self,
+        items: impl IntoIterator<Item = T>,
+        batch: Opcode,
+        mut write_item: impl FnMut(&mut Self, T) -> Result<()>,
+    ) -> Result<()> {
+        self.write_opcode(Opcode::Mark)?;
+        for (n, item) in items.into_iter().enumerate() {
+            if n > 0 && n % 1000 == 0 {
+                self.write_opcode(batch)?;
+                self.write_opcode(Opcode::Mark)?;
+            }
+            write_item(self, item)?;
+        }
+        self.write_opcode(batch)
+    }
+
+    // Handle a field of the struct that `value::Object` (and the
+    // `PickleClass` derive) serializes objects as.
+    fn serialize_object_field<T: Serialize + ?Sized>(
+        &mut self,
+        header: &mut ObjectHeader,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        match key {
+            "module" | "name" => {
+                let string = match to_value(value)? {
+                    Value::String(s) => s.into_raw_or_cloned(),
+                    other => return Err(Error::Syntax(ErrorCode::InvalidValue(other.to_string()))),
+                };
+                if key == "module" {
+                    header.module = string;
+                } else {
+                    header.name = string;
+                }
+            }
+            "new" => header.new = to_value(value)? == Value::Bool(true),
+            "kwargs" => header.kwargs = Some(to_value(value)?),
+            "args" if header.is_defaultdict() => {
+                let args = match to_value(value)? {
+                    Value::List(args) => args.into_raw_or_cloned(),
+                    Value::Tuple(args) => args.into_raw_or_cloned(),
+                    other => return Err(Error::Syntax(ErrorCode::InvalidValue(other.to_string()))),
+                };
+                self.write_object_header(header, |slf| slf.write_object_args(header, &args))?;
+                header.created = true;
+            }
+            "args" => {
+                self.write_object_header(header, |slf| {
+                    slf.seq_as_tuple = true;
+                    value.serialize(&mut *slf)?;
+                    slf.seq_as_tuple = false;
+                    Ok(())
+                })?;
+                header.created = true;
+            }
+            "items" if header.created => self.write_object_items(&to_value(value)?)?,
+            "state" if header.created => {
+                value.serialize(&mut *self)?;
+                self.write_opcode(Opcode::Build)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Write a GLOBAL opcode for a builtin, with the module name that fits the
+    // protocol.
+    fn write_builtin(&mut self, name: &[u8]) -> Result<()> {
         self.write_opcode(Opcode::Global)?;
         if self.options.proto == PickleProto::V3 {
             self.writer.write_all(b"builtins\n")?;
@@ -227,7 +551,23 @@ impl<W: io::Write> Serializer<W> {
             self.writer.write_all(b"__builtin__\n")?;
         }
         self.writer.write_all(name)?;
-        self.writer.write_all(b"\n")?;
+        self.writer.write_all(b"\n").map_err(From::from)
+    }
+
+    // Write `value` as the single argument of a call to the builtin `name`.
+    fn serialize_builtin_call<T: Serialize + ?Sized>(
+        &mut self,
+        name: &[u8],
+        value: &T,
+    ) -> Result<()> {
+        self.write_builtin(name)?;
+        value.serialize(&mut *self)?;
+        self.write_opcode(Opcode::Tuple1)?;
+        self.write_opcode(Opcode::Reduce)
+    }
+
+    fn serialize_set(&mut self, items: &BTreeSet<HashableValue>, name: &[u8]) -> Result<()> {
+        self.write_builtin(name)?;
         self.write_opcode(Opcode::EmptyList)?;
         self.write_opcode(Opcode::Mark)?;
         for (n, item) in items.iter().enumerate() {
@@ -246,6 +586,35 @@ impl<W: io::Write> Serializer<W> {
 pub struct Compound<'a, W: io::Write + 'a> {
     ser: &'a mut Serializer<W>,
     state: Option<usize>,
+    // whether a sequence is written as a tuple
+    tuple: bool,
+    // set when writing a `value::Object`
+    object: Option<ObjectHeader>,
+    // whether the tuple is the argument tuple of NEWOBJ (for namedtuples)
+    newobj: bool,
+    // elements collected while they are all u8, see `u8_sequences_as_bytes`;
+    // nothing is written until the first other element
+    bytes: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct ObjectHeader {
+    module: String,
+    name: String,
+    new: bool,
+    kwargs: Option<Value>,
+    // whether the object was written, so that BUILD can follow
+    created: bool,
+}
+
+impl ObjectHeader {
+    fn is_defaultdict(&self) -> bool {
+        self.module == "collections" && self.name == "defaultdict"
+    }
+
+    fn is_singleton(&self) -> bool {
+        self.module == "builtins" && matches!(&*self.name, "Ellipsis" | "NotImplemented")
+    }
 }
 
 impl<'a, W: io::Write> ser::SerializeSeq for Compound<'a, W> {
@@ -254,6 +623,22 @@ impl<'a, W: io::Write> ser::SerializeSeq for Compound<'a, W> {
 
     #[inline]
     fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        if self.tuple {
+            return ser::SerializeTuple::serialize_element(self, value);
+        }
+        if let Some(ref mut bytes) = self.bytes {
+            if let Ok(byte) = value.serialize(ByteProbe) {
+                bytes.push(byte);
+                return Ok(());
+            }
+            // Not a byte string after all: write the list so far.
+            let bytes = self.bytes.take().unwrap();
+            self.ser.write_opcode(Opcode::EmptyList)?;
+            self.ser.write_opcode(Opcode::Mark)?;
+            for byte in bytes {
+                ser::SerializeSeq::serialize_element(self, &byte)?;
+            }
+        }
         value.serialize(&mut *self.ser)?;
         // Batch appends as in Python pickle
         *self.state.as_mut().unwrap() += 1;
@@ -267,10 +652,15 @@ impl<'a, W: io::Write> ser::SerializeSeq for Compound<'a, W> {
 
     #[inline]
     fn end(self) -> Result<()> {
-        if self.state.is_some() {
-            self.ser.write_opcode(Opcode::Appends)?;
+        if self.tuple {
+            return ser::SerializeTuple::end(self);
+        }
+        match self.bytes {
+            Some(bytes) if bytes.is_empty() => self.ser.write_opcode(Opcode::EmptyList),
+            Some(bytes) => ser::Serializer::serialize_bytes(self.ser, &bytes),
+            None if self.state.is_some() => self.ser.write_opcode(Opcode::Appends),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
@@ -288,6 +678,9 @@ impl<'a, W: io::Write> ser::SerializeTuple for Compound<'a, W> {
         if self.state.is_some() {
             self.ser.write_opcode(Opcode::Tuple)?;
         }
+        if self.newobj {
+            self.ser.write_opcode(Opcode::NewObj)?;
+        }
         Ok(())
     }
 }
@@ -368,13 +761,31 @@ impl<'a, W: io::Write> ser::SerializeStruct for Compound<'a, W> {
         key: &'static str,
         value: &T,
     ) -> Result<()> {
+        if let Some(ref mut header) = self.object {
+            return self.ser.serialize_object_field(header, key, value);
+        }
+        if self.newobj {
+            return ser::SerializeTuple::serialize_element(self, value);
+        }
         ser::SerializeMap::serialize_key(self, key)?;
         ser::SerializeMap::serialize_value(self, value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        ser::SerializeMap::end(self)
+        if self.object.is_some() {
+            return Ok(());
+        }
+        if self.newobj {
+            return ser::SerializeTuple::end(self);
+        }
+        if self.state.is_some() {
+            self.ser.write_opcode(Opcode::SetItems)?;
+        }
+        if self.ser.options.structs_as_namespace {
+            self.ser.write_opcode(Opcode::Build)?;
+        }
+        Ok(())
     }
 }
 
@@ -525,6 +936,22 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
         }
     }
 
+    #[inline]
+    fn serialize_i128(self, value: i128) -> Result<()> {
+        match i64::try_from(value) {
+            Ok(value) => self.serialize_i64(value),
+            Err(_) => self.serialize_bigint(&value.into()),
+        }
+    }
+
+    #[inline]
+    fn serialize_u128(self, value: u128) -> Result<()> {
+        match u64::try_from(value) {
+            Ok(value) => self.serialize_u64(value),
+            Err(_) => self.serialize_bigint(&value.into()),
+        }
+    }
+
     #[inline]
     fn serialize_f32(self, value: f32) -> Result<()> {
         self.write_opcode(Opcode::BinFloat)?;
@@ -551,9 +978,7 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
 
     #[inline]
     fn serialize_str(self, value: &str) -> Result<()> {
-        self.write_opcode(Opcode::BinUnicode)?;
-        self.writer.write_u32::<LittleEndian>(value.len() as u32)?;
-        self.writer.write_all(value.as_bytes()).map_err(From::from)
+        self.write_unicode(value.as_bytes())
     }
 
     #[inline]
@@ -605,10 +1030,25 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
     #[inline]
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         variant: &'static str,
     ) -> Result<()> {
+        if let Some(py_enum) = self.options.enums.get(name) {
+            let value = py_enum
+                .values
+                .value(variant_index, variant)
+                .ok_or_else(|| {
+                    Error::Syntax(ErrorCode::Structure(format!(
+                        "no Python value for {name}::{variant}"
+                    )))
+                })?;
+            let (module, class) = (py_enum.module.clone(), py_enum.name.clone());
+            self.write_global(&module, &class)?;
+            self.serialize_value(&value)?;
+            self.write_opcode(Opcode::Tuple1)?;
+            return self.write_opcode(Opcode::Reduce);
+        }
         self.serialize_str(variant)?;
         if self.options.compat_enum_repr {
             self.write_opcode(Opcode::Tuple1)
@@ -620,10 +1060,42 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
     #[inline]
     fn serialize_newtype_struct<T: Serialize + ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<()> {
-        value.serialize(self)
+        match name {
+            types::TUPLE => {
+                self.seq_as_tuple = true;
+                value.serialize(&mut *self)?;
+                self.seq_as_tuple = false;
+                Ok(())
+            }
+            types::SET => self.serialize_builtin_call(b"set", value),
+            types::FROZENSET => self.serialize_builtin_call(b"frozenset", value),
+            types::BYTEARRAY => self.serialize_builtin_call(b"bytearray", value),
+            types::BIGINT => {
+                // The integer is passed on as its decimal representation.
+                let digits = match to_value(value)? {
+                    Value::String(digits) => digits.into_raw_or_cloned(),
+                    other => return Err(Error::Syntax(ErrorCode::InvalidValue(other.to_string()))),
+                };
+                match BigInt::from_str(&digits) {
+                    Ok(i) => self.serialize_bigint(&i),
+                    Err(_) => Err(Error::Syntax(ErrorCode::InvalidLiteral(digits.into()))),
+                }
+            }
+            _ => match types::array_typecode(name) {
+                // array.array(typecode, list)
+                Some(typecode) => {
+                    self.write_global("array", "array")?;
+                    self.serialize_str(typecode)?;
+                    value.serialize(&mut *self)?;
+                    self.write_opcode(Opcode::Tuple2)?;
+                    self.write_opcode(Opcode::Reduce)
+                }
+                None => value.serialize(self),
+            },
+        }
     }
 
     #[inline]
@@ -634,6 +1106,7 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
         variant: &'static str,
         value: &T,
     ) -> Result<()> {
+        self.seq_as_tuple = false;
         if self.options.compat_enum_repr {
             self.serialize_str(variant)?;
             value.serialize(&mut *self)?;
@@ -658,17 +1131,46 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
 
     #[inline]
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        if mem::take(&mut self.seq_as_tuple) {
+            self.write_opcode(Opcode::Mark)?;
+            return Ok(Compound {
+                ser: self,
+                state: Some(0),
+                tuple: true,
+                object: None,
+                newobj: false,
+                bytes: None,
+            });
+        }
+        if self.options.u8_sequences_as_bytes && len != Some(0) {
+            return Ok(Compound {
+                ser: self,
+                state: Some(0),
+                tuple: false,
+                object: None,
+                newobj: false,
+                bytes: Some(Vec::new()),
+            });
+        }
         self.write_opcode(Opcode::EmptyList)?;
         match len {
             Some(0) => Ok(Compound {
                 ser: self,
                 state: None,
+                tuple: false,
+                object: None,
+                newobj: false,
+                bytes: None,
             }),
             _ => {
                 self.write_opcode(Opcode::Mark)?;
                 Ok(Compound {
                     ser: self,
                     state: Some(0),
+                    tuple: false,
+                    object: None,
+                    newobj: false,
+                    bytes: None,
                 })
             }
         }
@@ -676,28 +1178,19 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
 
     #[inline]
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
-        if len == 0 {
-            self.write_opcode(Opcode::EmptyTuple)?;
-            Ok(Compound {
-                ser: self,
-                state: None,
-            })
-        } else {
-            self.write_opcode(Opcode::Mark)?;
-            Ok(Compound {
-                ser: self,
-                state: Some(0),
-            })
-        }
+        self.begin_tuple(len)
     }
 
     #[inline]
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        self.serialize_tuple(len)
+        if self.options.namedtuples.contains_key(name) {
+            return self.serialize_namedtuple(name, len);
+        }
+        self.begin_tuple(len)
     }
 
     #[inline]
@@ -708,6 +1201,7 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
+        self.seq_as_tuple = false;
         if !self.options.compat_enum_repr {
             self.write_opcode(Opcode::EmptyDict)?;
         }
@@ -717,29 +1211,63 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
         Ok(Compound {
             ser: self,
             state: None,
+            tuple: false,
+            object: None,
+            newobj: false,
+            bytes: None,
         })
     }
 
     #[inline]
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.seq_as_tuple = false;
         self.write_opcode(Opcode::EmptyDict)?;
         match len {
             Some(0) => Ok(Compound {
                 ser: self,
                 state: None,
+                tuple: false,
+                object: None,
+                newobj: false,
+                bytes: None,
             }),
             _ => {
                 self.write_opcode(Opcode::Mark)?;
                 Ok(Compound {
                     ser: self,
                     state: Some(0),
+                    tuple: false,
+                    object: None,
+                    newobj: false,
+                    bytes: None,
                 })
             }
         }
     }
 
     #[inline]
-    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        if name == types::OBJECT {
+            // Nothing can be written before the class and arguments are known.
+            return Ok(Compound {
+                ser: self,
+                state: None,
+                tuple: false,
+                object: Some(ObjectHeader::default()),
+                newobj: false,
+                bytes: None,
+            });
+        }
+        if self.options.namedtuples.contains_key(name) {
+            return self.serialize_namedtuple(name, len);
+        }
+        if self.options.structs_as_namespace {
+            // types.SimpleNamespace() with the fields set as instance __dict__
+            self.write_opcode(Opcode::Global)?;
+            self.writer.write_all(b"types\nSimpleNamespace\n")?;
+            self.write_opcode(Opcode::EmptyTuple)?;
+            self.write_opcode(Opcode::Reduce)?;
+        }
         self.serialize_map(Some(len))
     }
 
@@ -810,6 +1338,20 @@ pub fn to_vec<T: Serialize>(value: &T, options: SerOptions) -> Result<Vec<u8>> {
     Ok(writer)
 }
 
+// Serialize as a 128-bit integer if possible, otherwise as `types::PyBigInt`.
+fn serialize_bigint<S: ser::Serializer>(
+    value: &BigInt,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    if let Some(i) = value.to_i128() {
+        serializer.serialize_i128(i)
+    } else if let Some(u) = value.to_u128() {
+        serializer.serialize_u128(u)
+    } else {
+        types::bigint::serialize(value, serializer)
+    }
+}
+
 impl Serialize for Value {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -819,12 +1361,14 @@ impl Serialize for Value {
             Value::None => serializer.serialize_none(),
             Value::Bool(b) => serializer.serialize_bool(b),
             Value::I64(i) => serializer.serialize_i64(i),
-            Value::Int(ref big_int) => {
-                serializer.serialize_i128(big_int.to_i128().expect("cannot serialize bigint"))
-            }
+            Value::Int(ref big_int) => serialize_bigint(big_int, serializer),
             Value::F64(f) => serializer.serialize_f64(f),
             Value::Bytes(ref shared) => serializer.serialize_bytes(shared.inner()),
+            Value::ByteArray(ref shared) => {
+                types::bytearray::serialize(&*shared.inner(), serializer)
+            }
             Value::String(ref shared) => serializer.serialize_str(shared.inner()),
+            Value::WtfString(ref shared) => serializer.serialize_str(&wtf8_lossy(shared.inner())),
             Value::Tuple(ref shared) => {
                 let inner = shared.inner();
                 let mut seq = serializer.serialize_seq(Some(inner.len()))?;
@@ -857,6 +1401,15 @@ impl Serialize for Value {
                 }
                 seq.end()
             }
+            Value::Object(ref shared) => shared.inner().serialize(serializer),
+            Value::NdArray(ref shared) => {
+                let list = shared.inner().to_list().map_err(ser::Error::custom)?;
+                list.serialize(serializer)
+            }
+            Value::PersistentId(ref pid) => pid.serialize(serializer),
+            Value::Global(ref module, ref name) => {
+                serializer.serialize_str(&format!("{module}.{name}"))
+            }
             Value::Dict(ref shared) => {
                 let inner = shared.inner();
                 let mut map = serializer.serialize_map(Some(inner.len()))?;
@@ -889,12 +1442,13 @@ impl Serialize for HashableValue {
             HashableValue::None => serializer.serialize_none(),
             HashableValue::Bool(b) => serializer.serialize_bool(b),
             HashableValue::I64(i) => serializer.serialize_i64(i),
-            HashableValue::Int(ref big_int) => {
-                serializer.serialize_i128(big_int.to_i128().expect("cannot serialize bigint"))
-            }
+            HashableValue::Int(ref big_int) => serialize_bigint(big_int, serializer),
             HashableValue::F64(f) => serializer.serialize_f64(f),
             HashableValue::Bytes(ref shared) => serializer.serialize_bytes(shared.inner()),
             HashableValue::String(ref shared) => serializer.serialize_str(shared.inner()),
+            HashableValue::WtfString(ref shared) => {
+                serializer.serialize_str(&wtf8_lossy(shared.inner()))
+            }
             HashableValue::Tuple(ref shared) => {
                 let inner = shared.inner();
                 let mut seq = serializer.serialize_seq(Some(inner.len()))?;
@@ -914,3 +1468,111 @@ impl Serialize for HashableValue {
         }
     }
 }
+
+/// Objects are serialized as a struct with the fields `module`, `name`,
+/// `new`, `kwargs` (if not empty), `args`, `items` and `state` (if any).  The pickle
+/// serializer turns this into the instructions that recreate the object.
+impl Serialize for Object {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let mut object = serializer.serialize_struct(types::OBJECT, 7)?;
+        object.serialize_field("module", &self.module)?;
+        object.serialize_field("name", &self.name)?;
+        object.serialize_field("new", &self.new)?;
+        if self.kwargs.is_empty() {
+            object.skip_field("kwargs")?;
+        } else {
+            object.serialize_field("kwargs", &self.kwargs)?;
+        }
+        object.serialize_field("args", &self.args)?;
+        match self.items {
+            Some(ref items) => object.serialize_field("items", items)?,
+            None => object.skip_field("items")?,
+        }
+        match self.state {
+            Some(ref state) => object.serialize_field("state", state)?,
+            None => object.skip_field("state")?,
+        }
+        object.end()
+    }
+}
+
+// Accepts only `u8` values, to find sequences that can be written as bytes.
+struct ByteProbe;
+
+macro_rules! reject {
+    ($($method:ident($($arg:ty),*) -> $ret:ty;)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ret> {
+                Err(Error::Syntax(ErrorCode::Structure("not a byte".into())))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ByteProbe {
+    type Ok = u8;
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<u8, Error>;
+    type SerializeTuple = ser::Impossible<u8, Error>;
+    type SerializeTupleStruct = ser::Impossible<u8, Error>;
+    type SerializeTupleVariant = ser::Impossible<u8, Error>;
+    type SerializeMap = ser::Impossible<u8, Error>;
+    type SerializeStruct = ser::Impossible<u8, Error>;
+    type SerializeStructVariant = ser::Impossible<u8, Error>;
+
+    fn serialize_u8(self, value: u8) -> Result<u8> {
+        Ok(value)
+    }
+
+    reject! {
+        serialize_bool(bool) -> u8;
+        serialize_i8(i8) -> u8;
+        serialize_i16(i16) -> u8;
+        serialize_i32(i32) -> u8;
+        serialize_i64(i64) -> u8;
+        serialize_u16(u16) -> u8;
+        serialize_u32(u32) -> u8;
+        serialize_u64(u64) -> u8;
+        serialize_f32(f32) -> u8;
+        serialize_f64(f64) -> u8;
+        serialize_char(char) -> u8;
+        serialize_str(&str) -> u8;
+        serialize_bytes(&[u8]) -> u8;
+        serialize_none() -> u8;
+        serialize_unit() -> u8;
+        serialize_unit_struct(&'static str) -> u8;
+        serialize_unit_variant(&'static str, u32, &'static str) -> u8;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<u8> {
+        Err(Error::Syntax(ErrorCode::Structure("not a byte".into())))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<u8> {
+        Err(Error::Syntax(ErrorCode::Structure("not a byte".into())))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<u8> {
+        Err(Error::Syntax(ErrorCode::Structure("not a byte".into())))
+    }
+}
@@ -0,0 +1,378 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! PyTorch checkpoints (`.pt` and `.pth` files).
+//!
+//! Since PyTorch 1.6, `torch.save` writes a zip archive holding the pickled
+//! object in `<name>/data.pkl` and each tensor storage, uncompressed, in
+//! `<name>/data/<key>`.  Tensors are pickled as
+//! `torch._utils._rebuild_tensor_v2(storage, offset, size, stride, ...)`,
+//! where the storage is the persistent ID `('storage', torch.FloatStorage,
+//! key, location, numel)`.
+//!
+//! A [`Checkpoint`] borrows the bytes of the archive, e.g. of a memory-mapped
+//! file, so that tensor data is accessed without copying.  The legacy format
+//! of older PyTorch versions isn't supported.
+//!
+//! ```no_run
+//! # fn main() -> pickled::Result<()> {
+//! let bytes = std::fs::read("model.pt")?;
+//! let checkpoint = pickled::torch::Checkpoint::from_slice(&bytes)?;
+//! for (name, tensor) in checkpoint.state_dict()? {
+//!     println!("{name}: {:?} {:?}", tensor.dtype, tensor.shape);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use zip::result::ZipError;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::de::{DeOptions, value_from_slice};
+use crate::error::{Error, ErrorCode, Result};
+use crate::ndarray::checked_len;
+use crate::value::Value;
+
+/// The type of the items of a tensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dtype {
+    Float64,
+    Float32,
+    Float16,
+    BFloat16,
+    Complex128,
+    Complex64,
+    Int64,
+    Int32,
+    Int16,
+    Int8,
+    UInt8,
+    Bool,
+}
+
+impl Dtype {
+    /// Return the size of an item in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Dtype::Complex128 => 16,
+            Dtype::Float64 | Dtype::Complex64 | Dtype::Int64 => 8,
+            Dtype::Float32 | Dtype::Int32 => 4,
+            Dtype::Float16 | Dtype::BFloat16 | Dtype::Int16 => 2,
+            Dtype::Int8 | Dtype::UInt8 | Dtype::Bool => 1,
+        }
+    }
+
+    // The dtype of a storage class, e.g. `torch.FloatStorage`.
+    fn from_storage(class: &str) -> Option<Dtype> {
+        Some(match class.strip_prefix("torch.")? {
+            "DoubleStorage" => Dtype::Float64,
+            "FloatStorage" => Dtype::Float32,
+            "HalfStorage" => Dtype::Float16,
+            "BFloat16Storage" => Dtype::BFloat16,
+            "ComplexDoubleStorage" => Dtype::Complex128,
+            "ComplexFloatStorage" => Dtype::Complex64,
+            "LongStorage" => Dtype::Int64,
+            "IntStorage" => Dtype::Int32,
+            "ShortStorage" => Dtype::Int16,
+            "CharStorage" => Dtype::Int8,
+            "ByteStorage" | "UntypedStorage" => Dtype::UInt8,
+            "BoolStorage" => Dtype::Bool,
+            _ => return None,
+        })
+    }
+}
+
+/// A tensor in a [`Checkpoint`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor<'a> {
+    /// The type of the items.
+    pub dtype: Dtype,
+    /// The size of each dimension.
+    pub shape: Vec<usize>,
+    /// The distance between items along each dimension, in items.
+    pub stride: Vec<usize>,
+    /// The position of the first item in the storage, in items.
+    pub storage_offset: usize,
+    /// Whether the tensor (or parameter) requires gradients.
+    pub requires_grad: bool,
+    /// The key of the storage within the archive.
+    pub storage_key: String,
+    /// The bytes of the whole storage, which may be shared with other
+    /// tensors.
+    pub storage: &'a [u8],
+}
+
+impl<'a> Tensor<'a> {
+    /// Return the number of items, or `usize::MAX` if the shape has more.
+    pub fn numel(&self) -> usize {
+        checked_len(&self.shape).unwrap_or(usize::MAX)
+    }
+
+    /// Return whether the items are laid out in C order without gaps.
+    pub fn is_contiguous(&self) -> bool {
+        let mut expected = 1;
+        for (&len, &stride) in self.shape.iter().zip(&self.stride).rev() {
+            if len != 1 && stride != expected {
+                return false;
+            }
+            expected = expected.saturating_mul(len);
+        }
+        true
+    }
+
+    /// Return the bytes of the items, if the tensor is contiguous.  They are
+    /// in the byte order of the checkpoint, see [`Checkpoint::is_big_endian`].
+    pub fn data(&self) -> Option<&'a [u8]> {
+        if !self.is_contiguous() {
+            return None;
+        }
+        let size = self.dtype.size();
+        let start = self.storage_offset.checked_mul(size)?;
+        let len = checked_len(&self.shape)?.checked_mul(size)?;
+        self.storage.get(start..start.checked_add(len)?)
+    }
+}
+
+/// A PyTorch checkpoint, as written by `torch.save`.
+#[derive(Clone, Debug)]
+pub struct Checkpoint<'a> {
+    object: Value,
+    storages: BTreeMap<String, &'a [u8]>,
+    big_endian: bool,
+}
+
+impl<'a> Checkpoint<'a> {
+    /// Read the checkpoint from the bytes of its zip archive.
+    pub fn from_slice(bytes: &'a [u8]) -> Result<Checkpoint<'a>> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(zip_error)?;
+        let mut members = BTreeMap::new();
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i).map_err(zip_error)?;
+            if file.compression() != CompressionMethod::Stored {
+                return Err(invalid(format!("compressed member {}", file.name())));
+            }
+            let start = usize::try_from(file.data_start()).unwrap_or(usize::MAX);
+            let len = usize::try_from(file.size()).unwrap_or(usize::MAX);
+            let data = (bytes.get(start..).and_then(|rest| rest.get(..len)))
+                .ok_or_else(|| invalid(format!("truncated member {}", file.name())))?;
+            members.insert(file.name().to_string(), data);
+        }
+        // All members are in a directory named after the file.
+        let pickle_name = (members.keys())
+            .filter(|name| name.rsplit('/').next() == Some("data.pkl"))
+            .min_by_key(|name| name.len())
+            .ok_or_else(|| invalid("checkpoint without data.pkl".into()))?;
+        let prefix = &pickle_name[..pickle_name.len() - "data.pkl".len()];
+        let object = value_from_slice(members[pickle_name], DeOptions::new().keep_objects())?;
+        let big_endian = members.get(&format!("{prefix}byteorder")) == Some(&&b"big"[..]);
+        let storages = (members.iter())
+            .filter_map(|(name, data)| {
+                let key = name.strip_prefix(prefix)?.strip_prefix("data/")?;
+                Some((key.to_string(), *data))
+            })
+            .collect();
+        Ok(Checkpoint {
+            object,
+            storages,
+            big_endian,
+        })
+    }
+
+    /// Return the unpickled object, decoded with [`DeOptions::keep_objects`];
+    /// tensors are `torch._utils._rebuild_tensor_v2` objects.
+    pub fn object(&self) -> &Value {
+        &self.object
+    }
+
+    /// Return whether the tensor data is big-endian.
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Return all tensors, with the dotted path of keys and indices that
+    /// leads to them, e.g. `"layer1.0.weight"` for a state dict, or
+    /// `"model.layer1.0.weight"` for a dict holding one under `"model"`.
+    /// Dict items come in key order, not in the order they were saved.
+    pub fn state_dict(&self) -> Result<Vec<(String, Tensor<'a>)>> {
+        let mut tensors = Vec::new();
+        self.find_tensors(&self.object, String::new(), &mut tensors)?;
+        Ok(tensors)
+    }
+
+    fn find_tensors(
+        &self,
+        value: &Value,
+        path: String,
+        tensors: &mut Vec<(String, Tensor<'a>)>,
+    ) -> Result<()> {
+        let join = |key: &dyn std::fmt::Display| match path.as_str() {
+            "" => key.to_string(),
+            path => format!("{path}.{key}"),
+        };
+        if let Some(tensor) = self.tensor(value)? {
+            tensors.push((path, tensor));
+            return Ok(());
+        }
+        match value {
+            Value::Dict(dict) => {
+                for (key, value) in dict.inner().iter() {
+                    let key = key
+                        .to_string_key()
+                        .unwrap_or_else(|| key.to_string().into());
+                    self.find_tensors(value, join(&key), tensors)?;
+                }
+            }
+            Value::List(items) => {
+                for (i, value) in items.inner().iter().enumerate() {
+                    self.find_tensors(value, join(&i), tensors)?;
+                }
+            }
+            Value::Tuple(items) => {
+                for (i, value) in items.inner().iter().enumerate() {
+                    self.find_tensors(value, join(&i), tensors)?;
+                }
+            }
+            // An OrderedDict (its state holds the `_metadata` of a state
+            // dict), or any other object with a `__dict__`.
+            Value::Object(object) => {
+                let object = object.inner();
+                match (&object.items, &object.state) {
+                    (Some(items), _) => self.find_tensors(items, path, tensors)?,
+                    (None, Some(state)) => self.find_tensors(state, path, tensors)?,
+                    (None, None) => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Return the tensor for a value of [`object`](Checkpoint::object), if it
+    /// is one.
+    pub fn tensor(&self, value: &Value) -> Result<Option<Tensor<'a>>> {
+        let Value::Object(object) = value else {
+            return Ok(None);
+        };
+        let object = object.inner();
+        let args = &object.args;
+        match (object.module.as_str(), object.name.as_str()) {
+            // (storage, storage_offset, size, stride, requires_grad, hooks)
+            ("torch._utils", "_rebuild_tensor_v2" | "_rebuild_tensor") if args.len() >= 4 => {
+                let requires_grad = matches!(args.get(4), Some(Value::Bool(true)));
+                self.rebuild_tensor(&args[0], &args[1], &args[2], &args[3], requires_grad)
+                    .map(Some)
+            }
+            // (data, requires_grad, hooks), for a torch.nn.Parameter
+            ("torch._utils", "_rebuild_parameter" | "_rebuild_parameter_with_state")
+                if args.len() >= 2 =>
+            {
+                let mut tensor = self.tensor(&args[0])?;
+                if let Some(tensor) = &mut tensor {
+                    tensor.requires_grad = args[1] == Value::Bool(true);
+                }
+                Ok(tensor)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn rebuild_tensor(
+        &self,
+        storage: &Value,
+        offset: &Value,
+        size: &Value,
+        stride: &Value,
+        requires_grad: bool,
+    ) -> Result<Tensor<'a>> {
+        // ('storage', storage_type, key, location, numel)
+        let pid = match storage {
+            Value::PersistentId(pid) => match &**pid {
+                Value::Tuple(pid) => pid.inner().clone(),
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        let (class, key, numel) = match &pid[..] {
+            [Value::String(typ), Value::String(class), key, _, numel, ..]
+                if typ.inner() == "storage" =>
+            {
+                let key = match key {
+                    Value::String(key) => key.inner().clone(),
+                    key => key.to_string(),
+                };
+                (class.inner().clone(), key, usize_value(numel)?)
+            }
+            _ => return Err(invalid(format!("tensor storage {storage}"))),
+        };
+        let dtype = Dtype::from_storage(&class).ok_or_else(|| {
+            Error::Syntax(ErrorCode::Structure(format!(
+                "unsupported tensor storage {class}"
+            )))
+        })?;
+        let storage = (self.storages.get(&key).copied())
+            .ok_or_else(|| invalid(format!("checkpoint without storage {key}")))?;
+        if numel
+            .checked_mul(dtype.size())
+            .is_none_or(|len| storage.len() < len)
+        {
+            return Err(invalid(format!("truncated storage {key}")));
+        }
+        let tuple = |value: &Value| match value {
+            Value::Tuple(items) => items.inner().iter().map(usize_value).collect(),
+            Value::List(items) => items.inner().iter().map(usize_value).collect(),
+            _ => Err(invalid(format!("tensor size {value}"))),
+        };
+        let tensor = Tensor {
+            dtype,
+            shape: tuple(size)?,
+            stride: tuple(stride)?,
+            storage_offset: usize_value(offset)?,
+            requires_grad,
+            storage_key: key,
+            storage,
+        };
+        // The last item must be inside the storage.
+        let last = (tensor.shape.iter().zip(&tensor.stride))
+            .try_fold(tensor.storage_offset, |last, (len, stride)| {
+                last.checked_add(len.saturating_sub(1).checked_mul(*stride)?)
+            });
+        let inside = match (checked_len(&tensor.shape), last) {
+            (Some(0), Some(_)) => true,
+            (Some(_), Some(last)) => last < storage.len() / dtype.size(),
+            _ => false,
+        };
+        if tensor.shape.len() != tensor.stride.len() || !inside {
+            return Err(invalid(format!(
+                "tensor of shape {:?} outside storage {}",
+                tensor.shape, tensor.storage_key
+            )));
+        }
+        Ok(tensor)
+    }
+}
+
+fn usize_value(value: &Value) -> Result<usize> {
+    match value {
+        Value::I64(i) => usize::try_from(*i).map_err(|_| invalid(format!("tensor size {i}"))),
+        _ => Err(invalid(format!("tensor size {value}"))),
+    }
+}
+
+fn invalid(what: String) -> Error {
+    Error::Syntax(ErrorCode::InvalidValue(format!(
+        "PyTorch checkpoint: {what}"
+    )))
+}
+
+fn zip_error(error: ZipError) -> Error {
+    match error {
+        ZipError::Io(error) => Error::Io(error),
+        error => invalid(error.to_string()),
+    }
+}
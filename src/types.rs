@@ -0,0 +1,512 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Wrapper types for selecting the Python type a value is pickled as.
+//!
+//! Serde's data model doesn't distinguish between tuples, lists and sets, or
+//! between byte strings and sequences of integers.  The wrappers in this
+//! module let you pick the exact Python type per value:
+//!
+//! * [`PyTuple`] pickles any sequence as a `tuple`
+//! * [`PySet`] and [`PyFrozenSet`] pickle any sequence as a `set`/`frozenset`
+//! * [`PyBytes`] and [`PyByteArray`] pickle a `Vec<u8>` as `bytes`/`bytearray`
+//! * [`PyBigInt`] pickles a `num_bigint::BigInt` as an `int` of any size
+//! * [`PyArray`] pickles a `Vec` of numbers as an `array.array`
+//!
+//! All wrappers deserialize from any Python value that can be converted to the
+//! wrapped type, e.g. a `PyBytes` can also be decoded from a `bytearray`.
+//!
+//! For use with `#[derive(Serialize, Deserialize)]`, the same behavior is
+//! available as modules for `#[serde(with = "...")]`:
+//!
+//! ```
+//! # use serde_derive::{Deserialize, Serialize};
+//! # use std::collections::HashSet;
+//! #[derive(Serialize, Deserialize)]
+//! struct Record {
+//!     #[serde(with = "pickled::types::tuple")]
+//!     point: Vec<f64>,
+//!     #[serde(with = "pickled::types::set")]
+//!     tags: HashSet<String>,
+//!     #[serde(with = "pickled::types::bytes")]
+//!     payload: Vec<u8>,
+//! }
+//! ```
+//!
+//! The wrappers are recognized through special newtype struct names by this
+//! crate's serializers and deserializers.  Other serde formats see the plain
+//! wrapped value (and a decimal string for `PyBigInt`).
+//!
+//! Unit-only Rust enums can be mapped to a Python `enum.Enum` class with
+//! `SerOptions::python_enum` and `DeOptions::python_enum`, using
+//! [`EnumValues`] to give the member values.
+
+use num_bigint::BigInt;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+
+use crate::value::{SharedFrozen, Value};
+
+pub(crate) const TUPLE: &str = "__pickled_PyTuple";
+pub(crate) const SET: &str = "__pickled_PySet";
+pub(crate) const FROZENSET: &str = "__pickled_PyFrozenSet";
+pub(crate) const BYTEARRAY: &str = "__pickled_PyByteArray";
+pub(crate) const BIGINT: &str = "__pickled_PyBigInt";
+pub(crate) const OBJECT: &str = "__pickled_PyObject";
+// followed by the typecode
+pub(crate) const ARRAY: &str = "__pickled_PyArray_";
+
+macro_rules! wrapper_impls {
+    ($name:ident < $t:ident >, $with:ident) => {
+        impl<$t> Deref for $name<$t> {
+            type Target = $t;
+
+            fn deref(&self) -> &$t {
+                &self.0
+            }
+        }
+
+        impl<$t> DerefMut for $name<$t> {
+            fn deref_mut(&mut self) -> &mut $t {
+                &mut self.0
+            }
+        }
+
+        impl<$t> From<$t> for $name<$t> {
+            fn from(value: $t) -> Self {
+                $name(value)
+            }
+        }
+
+        impl<$t: Serialize> Serialize for $name<$t> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $with::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de, $t: Deserialize<'de>> Deserialize<'de> for $name<$t> {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $with::deserialize(deserializer).map($name)
+            }
+        }
+    };
+    ($name:ident($t:ty), $with:ident) => {
+        impl Deref for $name {
+            type Target = $t;
+
+            fn deref(&self) -> &$t {
+                &self.0
+            }
+        }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $t {
+                &mut self.0
+            }
+        }
+
+        impl From<$t> for $name {
+            fn from(value: $t) -> Self {
+                $name(value)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $with::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $with::deserialize(deserializer).map($name)
+            }
+        }
+    };
+}
+
+/// A sequence that is pickled as a Python `tuple`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyTuple<T>(pub T);
+
+wrapper_impls!(PyTuple<T>, tuple);
+
+/// A sequence that is pickled as a Python `set`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PySet<T>(pub T);
+
+wrapper_impls!(PySet<T>, set);
+
+/// A sequence that is pickled as a Python `frozenset`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyFrozenSet<T>(pub T);
+
+wrapper_impls!(PyFrozenSet<T>, frozenset);
+
+/// A byte buffer that is pickled as a Python `bytes` object.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyBytes(pub Vec<u8>);
+
+wrapper_impls!(PyBytes(Vec<u8>), bytes);
+
+/// A byte buffer that is pickled as a Python `bytearray` object.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyByteArray(pub Vec<u8>);
+
+wrapper_impls!(PyByteArray(Vec<u8>), bytearray);
+
+/// An integer of arbitrary size, pickled as a Python `int`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyBigInt(pub BigInt);
+
+wrapper_impls!(PyBigInt(BigInt), bigint);
+
+/// A vector of numbers that is pickled as a Python `array.array`, with the
+/// typecode of the item type, e.g. `'d'` for `f64`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PyArray<T>(pub Vec<T>);
+
+impl<T> Deref for PyArray<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for PyArray<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.0
+    }
+}
+
+impl<T> From<Vec<T>> for PyArray<T> {
+    fn from(value: Vec<T>) -> Self {
+        PyArray(value)
+    }
+}
+
+impl<T: ArrayItem> Serialize for PyArray<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        array::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PyArray<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        array::deserialize(deserializer).map(PyArray)
+    }
+}
+
+/// A number type that can be stored in a Python `array.array`.
+///
+/// `i64` and `u64` use the typecodes `q` and `Q`, which Python 2 lacks.
+pub trait ArrayItem: Serialize {
+    /// The wrapper name, which carries the array typecode.
+    #[doc(hidden)]
+    const ARRAY: &'static str;
+}
+
+macro_rules! array_items {
+    ($($t:ty => $typecode:literal),*) => {
+        $(impl ArrayItem for $t {
+            const ARRAY: &'static str = concat!("__pickled_PyArray_", $typecode);
+        })*
+    };
+}
+
+array_items!(i8 => "b", u8 => "B", i16 => "h", u16 => "H", i32 => "i", u32 => "I",
+             i64 => "q", u64 => "Q", f32 => "f", f64 => "d");
+
+/// Return the array typecode carried by a wrapper name.
+pub(crate) fn array_typecode(name: &str) -> Option<&str> {
+    name.strip_prefix(ARRAY)
+}
+
+/// Serializes a byte slice with `serialize_bytes`.
+struct BytesRef<'a>(&'a [u8]);
+
+impl Serialize for BytesRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(value.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(value)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Vec<u8>, E> {
+        Ok(value.into_bytes())
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+struct BigIntVisitor;
+
+impl<'de> Visitor<'de> for BigIntVisitor {
+    type Value = BigInt;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<BigInt, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<BigInt, E> {
+        Ok(value.into())
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> Result<BigInt, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<BigInt, E> {
+        Ok(value.into())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<BigInt, E> {
+        BigInt::from_str(value).map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<BigInt, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+/// Pickle any sequence as a Python `tuple`; use with `#[serde(with = "...")]`.
+pub mod tuple {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(TUPLE, value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+/// Pickle any sequence as a Python `set`; use with `#[serde(with = "...")]`.
+pub mod set {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(SET, value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+/// Pickle any sequence as a Python `frozenset`; use with `#[serde(with = "...")]`.
+pub mod frozenset {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(FROZENSET, value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+/// Pickle a byte buffer as a Python `bytes`; use with `#[serde(with = "...")]`.
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]> + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_bytes(value.as_ref())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(T::from)
+    }
+}
+
+/// Pickle a byte buffer as a Python `bytearray`; use with `#[serde(with = "...")]`.
+pub mod bytearray {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]> + ?Sized,
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(BYTEARRAY, &BytesRef(value.as_ref()))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(T::from)
+    }
+}
+
+/// Pickle a `Vec` of numbers as a Python `array.array`; use with
+/// `#[serde(with = "...")]`.
+pub mod array {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: ArrayItem,
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(T::ARRAY, value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer)
+    }
+}
+
+/// Pickle a `num_bigint::BigInt` as a Python `int`; use with `#[serde(with = "...")]`.
+pub mod bigint {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(BIGINT, &value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        deserializer.deserialize_newtype_struct(BIGINT, BigIntVisitor)
+    }
+}
+
+/// The values of the members of a Python `enum.Enum` class, in the order of
+/// the variants of the Rust enum it is mapped to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EnumValues {
+    /// Consecutive integers from the given start, like `enum.auto()` (which
+    /// starts at 1).
+    Auto(i64),
+    /// The variant names as strings.
+    Names,
+    /// Explicit values, one per variant.
+    Explicit(Vec<Value>),
+}
+
+impl EnumValues {
+    /// Return the Python value of the given variant.
+    pub(crate) fn value(&self, index: u32, variant: &str) -> Option<Value> {
+        match self {
+            EnumValues::Auto(start) => start.checked_add(index.into()).map(Value::I64),
+            EnumValues::Names => Some(Value::String(SharedFrozen::new(variant.into()))),
+            EnumValues::Explicit(values) => values.get(index as usize).cloned(),
+        }
+    }
+
+    /// Return the index of the variant with the given Python value.
+    pub(crate) fn index(&self, value: &Value, variants: &[&str]) -> Option<usize> {
+        match (self, value) {
+            (EnumValues::Auto(start), Value::I64(i)) => i
+                .checked_sub(*start)
+                .and_then(|i| usize::try_from(i).ok())
+                .filter(|&i| i < variants.len()),
+            (EnumValues::Names, Value::String(s)) => variants.iter().position(|v| v == s.inner()),
+            (EnumValues::Explicit(values), _) => values.iter().position(|v| v == value),
+            _ => None,
+        }
+    }
+}
+
+/// A Python enum class registered in the serializer or deserializer options.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PyEnum {
+    pub module: String,
+    pub name: String,
+    pub values: EnumValues,
+}
+
+impl PyEnum {
+    pub(crate) fn new(class: &str, values: EnumValues) -> Self {
+        let (module, name) = split_class(class);
+        PyEnum {
+            module: module.into(),
+            name: name.into(),
+            values,
+        }
+    }
+}
+
+/// Split a `"module.Name"` class path into module and name; a class without a
+/// module is assumed to be defined in `__main__`.
+pub(crate) fn split_class(class: &str) -> (&str, &str) {
+    class.rsplit_once('.').unwrap_or(("__main__", class))
+}
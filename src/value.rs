@@ -8,16 +8,19 @@
 
 use num_bigint::BigInt;
 use num_traits::{Signed, ToPrimitive};
+use std::any::Any;
 use std::borrow::Cow;
 use std::cell::{Ref, RefCell, RefMut};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
 pub use crate::value_impls::{from_value, to_value};
 
 use crate::error::{Error, ErrorCode};
+use crate::ndarray::NdArray;
+use crate::types::{EnumValues, PyEnum};
 
 #[derive(Debug, Eq, PartialOrd, Ord, Clone)]
 pub struct Shared<T>(Rc<RefCell<T>>);
@@ -38,6 +41,13 @@ impl<T> Shared<T> {
     pub fn provenance(&self) -> usize {
         Rc::as_ptr(&self.0).expose_provenance()
     }
+
+    pub(crate) fn downgrade(&self) -> Weak<dyn Any>
+    where
+        T: 'static,
+    {
+        Rc::downgrade(&self.0) as Weak<dyn Any>
+    }
 }
 
 impl<T> From<T> for Shared<T> {
@@ -103,6 +113,13 @@ impl<T> SharedFrozen<T> {
     pub fn provenance(&self) -> usize {
         Rc::as_ptr(&self.0).expose_provenance()
     }
+
+    pub(crate) fn downgrade(&self) -> Weak<dyn Any>
+    where
+        T: 'static,
+    {
+        Rc::downgrade(&self.0) as Weak<dyn Any>
+    }
 }
 
 impl<T> From<T> for SharedFrozen<T> {
@@ -176,8 +193,15 @@ pub enum Value {
     F64(f64),
     /// Bytestring
     Bytes(SharedFrozen<Vec<u8>>),
+    /// Mutable byte array (`bytearray`)
+    ByteArray(Shared<Vec<u8>>),
     /// Unicode string
     String(SharedFrozen<String>),
+    /// Unicode string with lone surrogates, in the generalized UTF-8 that
+    /// Python writes with `surrogatepass` (only produced when asked to keep
+    /// them while unpickling, see `DeOptions::surrogates`).  Serde sees it as
+    /// a string with the surrogates replaced by U+FFFD.
+    WtfString(SharedFrozen<Vec<u8>>),
     /// List
     List(Shared<Vec<Value>>),
     /// Tuple
@@ -188,6 +212,275 @@ pub enum Value {
     FrozenSet(SharedFrozen<BTreeSet<HashableValue>>),
     /// Dictionary (map)
     Dict(Shared<BTreeMap<HashableValue, Value>>),
+    /// Class instance (only produced when objects are kept while unpickling)
+    Object(Shared<Object>),
+    /// NumPy array.  Serde sees it as nested lists, like `tolist()` gives.
+    NdArray(Shared<NdArray>),
+    /// Reference to an object outside the pickle, by the ID that the pickler's
+    /// `persistent_id` gave.  Serde sees the ID.
+    PersistentId(Box<Value>),
+    /// Reference to a class or function, by module and name, e.g. a class
+    /// passed as an argument (only produced when objects are kept while
+    /// unpickling).  Serde sees the dotted path.
+    Global(String, String),
+}
+
+/// A Python object together with the information needed to recreate it:
+/// its class, the arguments it was created with, and its state.
+///
+/// The deserializer only produces these when asked to keep objects (see
+/// `DeOptions::keep_objects`); by default, objects are replaced by their
+/// state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    /// Module of the class, e.g. `"collections"`.
+    pub module: String,
+    /// Name of the class within the module, e.g. `"OrderedDict"`.
+    pub name: String,
+    /// Whether the object is created by `cls.__new__(cls, *args, **kwargs)`
+    /// (NEWOBJ) instead of by calling `cls(*args)` (REDUCE).
+    pub new: bool,
+    /// Positional arguments for creating the object.
+    pub args: Vec<Value>,
+    /// Keyword arguments for `cls.__new__`.
+    pub kwargs: BTreeMap<HashableValue, Value>,
+    /// The contents of instances of `list`, `dict` or `set` subclasses, as
+    /// added by APPENDS, SETITEMS or ADDITEMS (or passed to
+    /// `copyreg._reconstructor`).
+    pub items: Option<Value>,
+    /// The object's state, as set by BUILD: either the instance `__dict__`,
+    /// or the argument to `__setstate__`.
+    pub state: Option<Value>,
+}
+
+impl Object {
+    /// Create an object of the given class, without arguments and state.
+    pub fn new(module: impl Into<String>, name: impl Into<String>) -> Self {
+        Object {
+            module: module.into(),
+            name: name.into(),
+            new: true,
+            args: Vec::new(),
+            kwargs: BTreeMap::new(),
+            items: None,
+            state: None,
+        }
+    }
+
+    /// Return the class path, e.g. `"collections.OrderedDict"`.
+    pub fn class_path(&self) -> String {
+        format!("{}.{}", self.module, self.name)
+    }
+
+    /// Look up an attribute in the object's state.
+    ///
+    /// This handles both a plain `__dict__` and the `(__dict__, slots)` pair
+    /// pickled for classes with `__slots__`.  Attribute names may be bytes
+    /// when pickled by Python 2.
+    pub fn attr(&self, name: &str) -> Option<Value> {
+        fn lookup(dict: &Value, name: &str) -> Option<Value> {
+            let Value::Dict(dict) = dict else {
+                return None;
+            };
+            let dict = dict.inner();
+            dict.get(&HashableValue::String(SharedFrozen::new(name.into())))
+                .or_else(|| dict.get(&HashableValue::Bytes(SharedFrozen::new(name.into()))))
+                .cloned()
+        }
+        // The pair can arrive as a list when the object went through serde.
+        match self.state.as_ref()? {
+            Value::Tuple(pair) if pair.inner().len() == 2 => {
+                let pair = pair.inner();
+                lookup(&pair[0], name).or_else(|| lookup(&pair[1], name))
+            }
+            Value::List(pair) if pair.inner().len() == 2 => {
+                let pair = pair.inner();
+                lookup(&pair[0], name).or_else(|| lookup(&pair[1], name))
+            }
+            dict => lookup(dict, name),
+        }
+    }
+
+    // Find the enum variant named like the object's class, either with the
+    // full "module.Name" path or just the name.
+    pub(crate) fn class_variant(&self, variants: &[&'static str]) -> Option<&'static str> {
+        class_variant(&self.module, &self.name, variants)
+    }
+
+    // Turn the object into the `(variant, content)` enum representation, with
+    // the state as the content, or the arguments if there is no state.
+    pub(crate) fn into_variant(self, variant: &str) -> Value {
+        let mut items = vec![Value::String(SharedFrozen::new(variant.into()))];
+        match self.state {
+            Some(state) => items.push(state),
+            None if !self.args.is_empty() => items.push(Value::Tuple(SharedFrozen::new(self.args))),
+            None => {}
+        }
+        Value::Tuple(SharedFrozen::new(items))
+    }
+
+    // Find the variant of the Rust enum `name` for a Python enum member,
+    // pickled as `Class(value)`: by the values of the enum registered for it,
+    // if any, or else by the member names.
+    pub(crate) fn enum_member(
+        &self,
+        name: &str,
+        variants: &'static [&'static str],
+        py_enum: Option<&PyEnum>,
+    ) -> Result<&'static str, Error> {
+        let class = self.class_path();
+        let value = match &self.args[..] {
+            [value] if !self.new => value,
+            _ => {
+                return Err(Error::Syntax(ErrorCode::Structure(format!(
+                    "{class} object is not an enum member"
+                ))));
+            }
+        };
+        let index = match py_enum {
+            Some(py_enum) if py_enum.module == self.module && py_enum.name == self.name => {
+                py_enum.values.index(value, variants)
+            }
+            Some(py_enum) => {
+                return Err(Error::Syntax(ErrorCode::Structure(format!(
+                    "expected a member of {}.{}, got {class}",
+                    py_enum.module, py_enum.name
+                ))));
+            }
+            None => EnumValues::Names.index(value, variants),
+        };
+        index.map(|i| variants[i]).ok_or_else(|| {
+            Error::Syntax(ErrorCode::Structure(format!(
+                "no variant of {name} for {class}({value})"
+            )))
+        })
+    }
+}
+
+// Find the enum variant named like the class `module.name`, either with the
+// full path or just the name.
+pub(crate) fn class_variant(
+    module: &str,
+    name: &str,
+    variants: &[&'static str],
+) -> Option<&'static str> {
+    let path = format!("{module}.{name}");
+    (variants.iter().find(|&&v| v == path))
+        .or_else(|| variants.iter().find(|&&v| v == name))
+        .copied()
+}
+
+// The integer value of an `int` that fits into a u128.
+fn to_u128(value: &Value) -> Option<u128> {
+    match value {
+        Value::I64(i) => u128::try_from(*i).ok(),
+        Value::Int(i) => u128::try_from(i).ok(),
+        _ => None,
+    }
+}
+
+fn invalid_value(value: &Value) -> Error {
+    Error::Syntax(ErrorCode::InvalidValue(value.to_string()))
+}
+
+/// An `ipaddress.IPv4Address`, pickled with its integer value.
+impl From<std::net::Ipv4Addr> for Value {
+    fn from(addr: std::net::Ipv4Addr) -> Self {
+        let mut object = Object::new("ipaddress", "IPv4Address");
+        object.new = false;
+        object.args = vec![Value::I64(u32::from(addr).into())];
+        Value::Object(Shared::new(object))
+    }
+}
+
+/// An `ipaddress.IPv6Address`, pickled with its string form.
+impl From<std::net::Ipv6Addr> for Value {
+    fn from(addr: std::net::Ipv6Addr) -> Self {
+        let mut object = Object::new("ipaddress", "IPv6Address");
+        object.new = false;
+        object.args = vec![Value::String(SharedFrozen::new(addr.to_string()))];
+        Value::Object(Shared::new(object))
+    }
+}
+
+impl From<std::net::IpAddr> for Value {
+    fn from(addr: std::net::IpAddr) -> Self {
+        match addr {
+            std::net::IpAddr::V4(addr) => addr.into(),
+            std::net::IpAddr::V6(addr) => addr.into(),
+        }
+    }
+}
+
+/// Get an IP address from an `ipaddress.IPv4Address` or `IPv6Address`
+/// object, or from a string as they are decoded to by default.
+impl TryFrom<&Value> for std::net::IpAddr {
+    type Error = Error;
+
+    fn try_from(value: &Value) -> Result<Self, Error> {
+        let addr = match value {
+            Value::String(s) => s.inner().parse().ok(),
+            Value::Object(object) => {
+                let object = object.inner();
+                match (&*object.module, &*object.name, &object.args[..]) {
+                    ("ipaddress", _, [Value::String(s)]) => s.inner().parse().ok(),
+                    ("ipaddress", "IPv4Address", [int]) => to_u128(int)
+                        .and_then(|int| u32::try_from(int).ok())
+                        .map(|int| std::net::Ipv4Addr::from(int).into()),
+                    ("ipaddress", "IPv6Address", [int]) => {
+                        to_u128(int).map(|int| std::net::Ipv6Addr::from(int).into())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        addr.ok_or_else(|| invalid_value(value))
+    }
+}
+
+/// A `uuid.UUID`, pickled with its integer value as state.
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for Value {
+    fn from(uuid: uuid::Uuid) -> Self {
+        let int = uuid.as_u128();
+        let int = match i64::try_from(int) {
+            Ok(i) => Value::I64(i),
+            Err(_) => Value::Int(BigInt::from(int)),
+        };
+        let mut state = BTreeMap::new();
+        state.insert(HashableValue::String(SharedFrozen::new("int".into())), int);
+        let mut object = Object::new("uuid", "UUID");
+        object.state = Some(Value::Dict(Shared::new(state)));
+        Value::Object(Shared::new(object))
+    }
+}
+
+/// Get a UUID from a `uuid.UUID` object, or from a string as UUIDs are
+/// decoded to by default.
+#[cfg(feature = "uuid")]
+impl TryFrom<&Value> for uuid::Uuid {
+    type Error = Error;
+
+    fn try_from(value: &Value) -> Result<Self, Error> {
+        let uuid = match value {
+            Value::String(s) => s.inner().parse().ok(),
+            Value::Object(object) => {
+                let object = object.inner();
+                if object.module == "uuid" && object.name == "UUID" {
+                    object
+                        .attr("int")
+                        .as_ref()
+                        .and_then(to_u128)
+                        .map(uuid::Uuid::from_u128)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        uuid.ok_or_else(|| invalid_value(value))
+    }
 }
 
 /// Represents all primitive builtin Python values that can be contained
@@ -214,6 +507,8 @@ pub enum HashableValue {
     Bytes(SharedFrozen<Vec<u8>>),
     /// Unicode string
     String(SharedFrozen<String>),
+    /// Unicode string with lone surrogates, see `Value::WtfString`
+    WtfString(SharedFrozen<Vec<u8>>),
     /// Tuple
     Tuple(SharedFrozen<Vec<HashableValue>>),
     /// Frozen (immutable) set
@@ -266,6 +561,7 @@ impl Value {
             Value::F64(f) => Ok(HashableValue::F64(f)),
             Value::Bytes(b) => Ok(HashableValue::Bytes(b)),
             Value::String(s) => Ok(HashableValue::String(s)),
+            Value::WtfString(s) => Ok(HashableValue::WtfString(s)),
             Value::FrozenSet(v) => Ok(HashableValue::FrozenSet(v)),
             Value::Tuple(v) => values_to_hashable(v).map(HashableValue::Tuple),
             _ => Err(Error::Syntax(ErrorCode::ValueNotHashable)),
@@ -281,6 +577,7 @@ impl Value {
             Value::F64(f) => Ok(RawHashableValue::F64(f)),
             Value::Bytes(b) => Ok(RawHashableValue::Bytes(b)),
             Value::String(s) => Ok(RawHashableValue::String(s)),
+            Value::WtfString(s) => Ok(RawHashableValue::WtfString(s)),
             Value::FrozenSet(v) => {
                 let v = v.inner();
                 let new = BTreeSet::from_iter(v.iter().cloned().map(|v| {
@@ -308,6 +605,7 @@ impl HashableValue {
             HashableValue::F64(f) => Value::F64(f),
             HashableValue::Bytes(b) => Value::Bytes(b),
             HashableValue::String(s) => Value::String(s),
+            HashableValue::WtfString(s) => Value::WtfString(s),
             HashableValue::FrozenSet(v) => Value::FrozenSet(v),
             HashableValue::Tuple(v) => Value::Tuple(hashable_to_values(v)),
         }
@@ -317,6 +615,7 @@ impl HashableValue {
     pub fn to_string_key(&self) -> Option<Cow<'static, str>> {
         let result = match *self {
             HashableValue::String(ref s) => Cow::Owned(s.inner().to_owned()),
+            HashableValue::WtfString(ref s) => Cow::Owned(wtf8_lossy(s.inner())),
             HashableValue::None => Cow::Borrowed("null"),
             HashableValue::Bool(b) => Cow::Owned(b.to_string()),
             HashableValue::I64(i) => Cow::Owned(i.to_string()),
@@ -371,7 +670,9 @@ impl fmt::Display for Value {
             Value::Int(ref i) => write!(f, "{i}"),
             Value::F64(v) => write!(f, "{v}"),
             Value::Bytes(ref b) => write!(f, "b{b:?}"),
+            Value::ByteArray(ref b) => write!(f, "bytearray(b\"{}\")", b.inner().escape_ascii()),
             Value::String(ref s) => write!(f, "{s:?}"),
+            Value::WtfString(ref s) => write!(f, "{:?}", wtf8_lossy(s.inner())),
             Value::List(ref v) => {
                 let v = v.inner();
                 write_elements(f, v.iter(), "[", "]", v.len(), false)
@@ -404,6 +705,21 @@ impl fmt::Display for Value {
                 }
                 write!(f, "}}")
             }
+            Value::Object(ref o) => {
+                let o = o.inner();
+                write!(f, "{}.{}", o.module, o.name)?;
+                write_elements(f, o.args.iter(), "(", ")", o.args.len(), false)?;
+                if let Some(ref items) = o.items {
+                    write!(f, " with items {items}")?;
+                }
+                if let Some(ref state) = o.state {
+                    write!(f, " with state {state}")?;
+                }
+                Ok(())
+            }
+            Value::NdArray(ref a) => write!(f, "{}", a.inner()),
+            Value::PersistentId(ref pid) => write!(f, "persistent_id({pid})"),
+            Value::Global(ref module, ref name) => write!(f, "{module}.{name}"),
         }
     }
 }
@@ -424,6 +740,7 @@ impl fmt::Display for HashableValue {
                 let s = s.inner();
                 write!(f, "{s:?}")
             }
+            HashableValue::WtfString(ref s) => write!(f, "{:?}", wtf8_lossy(s.inner())),
             HashableValue::Tuple(ref v) => {
                 let v = v.inner();
                 write_elements(f, v.iter(), "(", ")", v.len(), v.len() == 1)
@@ -500,13 +817,21 @@ impl Ord for HashableValue {
                 _ => Ordering::Less,
             },
             Bytes(ref bs) => match *other {
-                String(_) | FrozenSet(_) | Tuple(_) => Ordering::Less,
+                String(_) | WtfString(_) | FrozenSet(_) | Tuple(_) => Ordering::Less,
                 Bytes(ref bs2) => bs.cmp(bs2),
                 _ => Ordering::Greater,
             },
+            // Strings with and without surrogates are ordered by their encoding.
             String(ref s) => match *other {
                 FrozenSet(_) | Tuple(_) => Ordering::Less,
                 String(ref s2) => s.cmp(s2),
+                WtfString(ref s2) => s.inner().as_bytes().cmp(s2.inner()),
+                _ => Ordering::Greater,
+            },
+            WtfString(ref s) => match *other {
+                FrozenSet(_) | Tuple(_) => Ordering::Less,
+                String(ref s2) => s.inner()[..].cmp(s2.inner().as_bytes()),
+                WtfString(ref s2) => s.cmp(s2),
                 _ => Ordering::Greater,
             },
             FrozenSet(ref s) => match *other {
@@ -549,6 +874,31 @@ fn float_bigint_ord(bi: &BigInt, g: f64) -> Ordering {
     }
 }
 
+/// Decode generalized UTF-8, replacing each encoded surrogate (and any other
+/// invalid sequence) with U+FFFD.
+pub(crate) fn wtf8_lossy(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                result.push_str(valid);
+                return result;
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                result.push_str(std::str::from_utf8(valid).expect("checked above"));
+                result.push('\u{fffd}');
+                let len = match invalid {
+                    [0xed, 0xa0..=0xbf, 0x80..=0xbf, ..] => 3,
+                    _ => err.error_len().unwrap_or(invalid.len()),
+                };
+                rest = &invalid[len..];
+            }
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "variantly", derive(variantly::Variantly))]
 pub(crate) enum RawHashableValue {
@@ -566,6 +916,8 @@ pub(crate) enum RawHashableValue {
     Bytes(SharedFrozen<Vec<u8>>),
     /// Unicode string
     String(SharedFrozen<String>),
+    /// Unicode string with lone surrogates
+    WtfString(SharedFrozen<Vec<u8>>),
     /// Tuple
     Tuple(SharedFrozen<Vec<RawHashableValue>>),
     /// Frozen (immutable) set
@@ -591,8 +943,9 @@ impl std::cmp::Ord for RawHashableValue {
             RawHashableValue::F64(_) => 4,
             RawHashableValue::Bytes(_) => 5,
             RawHashableValue::String(_) => 6,
-            RawHashableValue::Tuple(_) => 7,
-            RawHashableValue::FrozenSet(_) => 8,
+            RawHashableValue::WtfString(_) => 7,
+            RawHashableValue::Tuple(_) => 8,
+            RawHashableValue::FrozenSet(_) => 9,
         };
         let __arg1_discr = match other {
             RawHashableValue::None => 0,
@@ -602,8 +955,9 @@ impl std::cmp::Ord for RawHashableValue {
             RawHashableValue::F64(_) => 4,
             RawHashableValue::Bytes(_) => 5,
             RawHashableValue::String(_) => 6,
-            RawHashableValue::Tuple(_) => 7,
-            RawHashableValue::FrozenSet(_) => 8,
+            RawHashableValue::WtfString(_) => 7,
+            RawHashableValue::Tuple(_) => 8,
+            RawHashableValue::FrozenSet(_) => 9,
         };
 
         match ::core::cmp::Ord::cmp(&__self_discr, &__arg1_discr) {
@@ -623,6 +977,9 @@ impl std::cmp::Ord for RawHashableValue {
                 (RawHashableValue::String(__self_0), RawHashableValue::String(__arg1_0)) => {
                     ::core::cmp::Ord::cmp(__self_0, __arg1_0)
                 }
+                (RawHashableValue::WtfString(__self_0), RawHashableValue::WtfString(__arg1_0)) => {
+                    ::core::cmp::Ord::cmp(__self_0, __arg1_0)
+                }
                 (RawHashableValue::Tuple(__self_0), RawHashableValue::Tuple(__arg1_0)) => {
                     ::core::cmp::Ord::cmp(__self_0, __arg1_0)
                 }
@@ -11,13 +11,15 @@ use num_traits::ToPrimitive;
 use serde::de::Visitor;
 use serde::ser::Serialize;
 use serde::{de, forward_to_deserialize_any, ser};
-use std::collections::{BTreeMap, btree_map};
+use std::collections::{BTreeMap, BTreeSet, btree_map};
 use std::fmt;
 use std::result::Result as StdResult;
+use std::str::FromStr;
 use std::vec;
 
 use crate::error::{Error, ErrorCode, Result};
-use crate::value::{HashableValue, Shared, SharedFrozen, Value};
+use crate::types;
+use crate::value::{HashableValue, Object, Shared, SharedFrozen, Value, wtf8_lossy};
 
 impl<'de> de::Deserialize<'de> for Value {
     #[inline]
@@ -51,6 +53,22 @@ impl<'de> de::Deserialize<'de> for Value {
                 }
             }
 
+            #[inline]
+            fn visit_i128<E>(self, value: i128) -> StdResult<Value, E> {
+                match i64::try_from(value) {
+                    Ok(value) => Ok(Value::I64(value)),
+                    Err(_) => Ok(Value::Int(BigInt::from(value))),
+                }
+            }
+
+            #[inline]
+            fn visit_u128<E>(self, value: u128) -> StdResult<Value, E> {
+                match i64::try_from(value) {
+                    Ok(value) => Ok(Value::I64(value)),
+                    Err(_) => Ok(Value::Int(BigInt::from(value))),
+                }
+            }
+
             #[inline]
             fn visit_f64<E>(self, value: f64) -> StdResult<Value, E> {
                 Ok(Value::F64(value))
@@ -109,6 +127,14 @@ impl<'de> de::Deserialize<'de> for Value {
                 mut visitor: V,
             ) -> StdResult<Value, V::Error> {
                 let mut values = BTreeMap::new();
+                if let Some(key) = visitor.next_key::<HashableValue>()? {
+                    // Objects nested in an object are marked by a special key.
+                    if matches!(key, HashableValue::String(ref s) if s.inner() == types::OBJECT) {
+                        visitor.next_value::<de::IgnoredAny>()?;
+                        return Ok(Value::Object(Shared::new(object_from_map(visitor)?)));
+                    }
+                    values.insert(key, visitor.next_value()?);
+                }
                 while let Some((key, value)) = visitor.next_entry()? {
                     values.insert(key, value);
                 }
@@ -152,6 +178,22 @@ impl<'de> de::Deserialize<'de> for HashableValue {
                 }
             }
 
+            #[inline]
+            fn visit_i128<E>(self, value: i128) -> StdResult<HashableValue, E> {
+                match i64::try_from(value) {
+                    Ok(value) => Ok(HashableValue::I64(value)),
+                    Err(_) => Ok(HashableValue::Int(BigInt::from(value))),
+                }
+            }
+
+            #[inline]
+            fn visit_u128<E>(self, value: u128) -> StdResult<HashableValue, E> {
+                match i64::try_from(value) {
+                    Ok(value) => Ok(HashableValue::I64(value)),
+                    Err(_) => Ok(HashableValue::Int(BigInt::from(value))),
+                }
+            }
+
             #[inline]
             fn visit_f64<E>(self, value: f64) -> StdResult<HashableValue, E> {
                 Ok(HashableValue::F64(value))
@@ -212,15 +254,115 @@ impl<'de> de::Deserialize<'de> for HashableValue {
     }
 }
 
+const OBJECT_FIELDS: &[&str] = &["module", "name", "new", "kwargs", "args", "items", "state"];
+
+impl<'de> de::Deserialize<'de> for Object {
+    fn deserialize<D: de::Deserializer<'de>>(deser: D) -> StdResult<Object, D::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> Visitor<'de> for ObjectVisitor {
+            type Value = Object;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a Python object")
+            }
+
+            fn visit_map<V: de::MapAccess<'de>>(self, visitor: V) -> StdResult<Object, V::Error> {
+                object_from_map(visitor)
+            }
+        }
+
+        deser.deserialize_struct(types::OBJECT, OBJECT_FIELDS, ObjectVisitor)
+    }
+}
+
+fn object_from_map<'de, V: de::MapAccess<'de>>(mut visitor: V) -> StdResult<Object, V::Error> {
+    let (mut module, mut name) = (None, None);
+    let mut object = Object::new("", "");
+    while let Some(key) = visitor.next_key::<String>()? {
+        match &*key {
+            "module" => module = Some(visitor.next_value()?),
+            "name" => name = Some(visitor.next_value()?),
+            "new" => object.new = visitor.next_value()?,
+            "kwargs" => object.kwargs = visitor.next_value()?,
+            "args" => object.args = visitor.next_value()?,
+            "items" => object.items = Some(visitor.next_value()?),
+            "state" => object.state = Some(visitor.next_value()?),
+            _ => {
+                visitor.next_value::<de::IgnoredAny>()?;
+            }
+        }
+    }
+    object.module = module.ok_or_else(|| de::Error::missing_field("module"))?;
+    object.name = name.ok_or_else(|| de::Error::missing_field("name"))?;
+    Ok(object)
+}
+
+// The fields of an object, as expected by its `Deserialize` impl.  The
+// leading special key lets `Value`'s `Deserialize` impl recognize objects
+// nested in other objects.
+fn object_fields(object: Object) -> Value {
+    let key = |name: &str| HashableValue::String(SharedFrozen::new(name.into()));
+    let mut fields = BTreeMap::new();
+    fields.insert(key(types::OBJECT), Value::None);
+    fields.insert(key("module"), Value::String(object.module.into()));
+    fields.insert(key("name"), Value::String(object.name.into()));
+    fields.insert(key("new"), Value::Bool(object.new));
+    fields.insert(key("kwargs"), Value::Dict(object.kwargs.into()));
+    fields.insert(key("args"), Value::Tuple(object.args.into()));
+    if let Some(items) = object.items {
+        fields.insert(key("items"), items);
+    }
+    if let Some(state) = object.state {
+        fields.insert(key("state"), state);
+    }
+    Value::Dict(Shared::new(fields))
+}
+
+// The reverse of `object_fields`, for the output of `Object`'s `Serialize`
+// impl.
+fn object_from_fields(mut fields: BTreeMap<HashableValue, Value>) -> Result<Object> {
+    let mut field = |name: &str| fields.remove(&HashableValue::String(name.to_owned().into()));
+    let mut object = Object::new("", "");
+    match (field("module"), field("name")) {
+        (Some(Value::String(module)), Some(Value::String(name))) => {
+            object.module = module.into_raw_or_cloned();
+            object.name = name.into_raw_or_cloned();
+        }
+        _ => {
+            return Err(Error::Syntax(ErrorCode::Structure(
+                "object without class name".into(),
+            )));
+        }
+    }
+    object.new = field("new") == Some(Value::Bool(true));
+    if let Some(Value::Dict(kwargs)) = field("kwargs") {
+        object.kwargs = kwargs.into_raw_or_cloned();
+    }
+    object.args = match field("args") {
+        Some(Value::List(args)) => args.into_raw_or_cloned(),
+        Some(Value::Tuple(args)) => args.into_raw_or_cloned(),
+        _ => Vec::new(),
+    };
+    object.items = field("items");
+    object.state = field("state");
+    Ok(object)
+}
+
 /// Deserializes a decoded value into any serde supported value.
 pub struct Deserializer {
     value: Option<Value>,
+    // set while deserializing the fields of an object
+    in_object: bool,
 }
 
 impl Deserializer {
     /// Creates a new deserializer instance for deserializing the specified JSON value.
     pub fn new(value: Value) -> Deserializer {
-        Deserializer { value: Some(value) }
+        Deserializer {
+            value: Some(value),
+            in_object: false,
+        }
     }
 }
 
@@ -239,18 +381,12 @@ impl<'de: 'a, 'a> de::Deserializer<'de> for &'a mut Deserializer {
             Value::None => visitor.visit_unit(),
             Value::Bool(v) => visitor.visit_bool(v),
             Value::I64(v) => visitor.visit_i64(v),
-            Value::Int(v) => {
-                if let Some(i) = v.to_i64() {
-                    visitor.visit_i64(i)
-                } else {
-                    Err(Error::Syntax(ErrorCode::InvalidValue(
-                        "integer too large".into(),
-                    )))
-                }
-            }
+            Value::Int(v) => visit_bigint(v, visitor),
             Value::F64(v) => visitor.visit_f64(v),
             Value::Bytes(v) => visitor.visit_byte_buf(v.into_raw_or_cloned()),
+            Value::ByteArray(v) => visitor.visit_byte_buf(v.into_raw_or_cloned()),
             Value::String(v) => visitor.visit_string(v.into_raw_or_cloned()),
+            Value::WtfString(v) => visitor.visit_string(wtf8_lossy(v.inner())),
             Value::List(v) => {
                 let v = v.into_raw_or_cloned();
                 let len = v.len();
@@ -304,6 +440,33 @@ impl<'de: 'a, 'a> de::Deserializer<'de> for &'a mut Deserializer {
                     len,
                 })
             }
+            Value::Object(v) => {
+                let object = v.into_raw_or_cloned();
+                self.value = Some(if self.in_object {
+                    object_fields(object)
+                } else {
+                    // Without the class, an object is represented by its state,
+                    // or by its `__new__` arguments if it has no state (e.g.
+                    // namedtuples).
+                    match object.state {
+                        Some(state) => state,
+                        None if object.new && !object.args.is_empty() => {
+                            Value::Tuple(SharedFrozen::new(object.args))
+                        }
+                        None => Value::Dict(Shared::new(BTreeMap::new())),
+                    }
+                });
+                self.deserialize_any(visitor)
+            }
+            Value::NdArray(v) => {
+                self.value = Some(v.inner().to_list()?);
+                self.deserialize_any(visitor)
+            }
+            Value::PersistentId(pid) => {
+                self.value = Some(*pid);
+                self.deserialize_any(visitor)
+            }
+            Value::Global(module, name) => visitor.visit_string(format!("{module}.{name}")),
         }
     }
 
@@ -319,26 +482,94 @@ impl<'de: 'a, 'a> de::Deserializer<'de> for &'a mut Deserializer {
     #[inline]
     fn deserialize_newtype_struct<V: Visitor<'de>>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value> {
+        if name == types::BIGINT
+            && let Some(Value::Int(v)) = self.value.take_if(|v| matches!(v, Value::Int(_)))
+        {
+            return visitor.visit_string(v.to_string());
+        }
         visitor.visit_newtype_struct(self)
     }
 
+    #[inline]
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name == types::OBJECT {
+            // Objects are presented with their class, see `value::Object`.
+            match self.value.take() {
+                Some(Value::Object(v)) => self.value = Some(object_fields(v.into_raw_or_cloned())),
+                Some(other) => {
+                    return Err(Error::Syntax(ErrorCode::Structure(format!(
+                        "expected a Python object, got {other}"
+                    ))));
+                }
+                None => return Err(Error::Syntax(ErrorCode::EOFWhileParsing)),
+            }
+            let in_object = std::mem::replace(&mut self.in_object, true);
+            let result = de::Deserializer::deserialize_any(&mut *self, visitor);
+            self.in_object = in_object;
+            return result;
+        }
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
     #[inline]
     fn deserialize_enum<V: Visitor<'de>>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value> {
+        // Objects select the variant named like their class.  Otherwise,
+        // they can be Python enum members, pickled as `Class(value)`.
+        if let Some(Value::Object(object)) = &self.value {
+            let object = object.inner().clone();
+            self.value = Some(match object.class_variant(variants) {
+                Some(variant) => object.into_variant(variant),
+                None => {
+                    let variant = object.enum_member(name, variants, None)?;
+                    Value::String(SharedFrozen::new(variant.into()))
+                }
+            });
+        }
         visitor.visit_enum(self)
     }
 
+    #[inline]
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        // Bytes can be decoded as a sequence of integers, e.g. into `Vec<u8>`.
+        let bytes = match &self.value {
+            Some(Value::Bytes(bytes)) => Some(bytes.inner().clone()),
+            Some(Value::ByteArray(bytes)) => Some(bytes.inner().clone()),
+            _ => None,
+        };
+        if let Some(bytes) = bytes {
+            let items: Vec<_> = bytes.iter().map(|&b| Value::I64(b.into())).collect();
+            self.value = None;
+            return visitor.visit_seq(SeqDeserializer {
+                de: self,
+                len: items.len(),
+                iter: items.into_iter(),
+            });
+        }
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    #[inline]
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
     forward_to_deserialize_any! {
-        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char str string unit seq
-        bytes byte_buf map unit_struct tuple_struct struct identifier
-        tuple ignored_any
+        bool u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 char str string unit
+        bytes byte_buf map unit_struct tuple_struct identifier
+        ignored_any
     }
 }
 
@@ -399,6 +630,7 @@ impl<'de: 'a, 'a> de::VariantAccess<'de> for &'a mut Deserializer {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
+        self.value = None;
         Ok(())
     }
 
@@ -572,6 +804,8 @@ pub struct SerializeMap<'a> {
     variant: &'a str,
     key: Option<HashableValue>,
     state: BTreeMap<HashableValue, Value>,
+    // set when serializing a `value::Object`
+    object: bool,
 }
 
 impl<'a> ser::SerializeMap for SerializeMap<'a> {
@@ -618,6 +852,9 @@ impl<'a> ser::SerializeStruct for SerializeMap<'a> {
 
     #[inline]
     fn end(self) -> Result<Value> {
+        if self.object {
+            return Ok(Value::Object(Shared::new(object_from_fields(self.state)?)));
+        }
         Ok(Value::Dict(Shared::new(self.state)))
     }
 }
@@ -707,6 +944,22 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         })
     }
 
+    #[inline]
+    fn serialize_i128(self, value: i128) -> Result<Value> {
+        Ok(match i64::try_from(value) {
+            Ok(value) => Value::I64(value),
+            Err(_) => Value::Int(BigInt::from(value)),
+        })
+    }
+
+    #[inline]
+    fn serialize_u128(self, value: u128) -> Result<Value> {
+        Ok(match i64::try_from(value) {
+            Ok(value) => Value::I64(value),
+            Err(_) => Value::Int(BigInt::from(value)),
+        })
+    }
+
     #[inline]
     fn serialize_f32(self, value: f32) -> Result<Value> {
         self.serialize_f64(value.into())
@@ -757,10 +1010,59 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     #[inline]
     fn serialize_newtype_struct<T: Serialize + ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Value> {
-        value.serialize(self)
+        let value = value.serialize(&mut *self)?;
+        match name {
+            types::TUPLE => Ok(match value {
+                Value::List(items) => Value::Tuple(items.into()),
+                other => other,
+            }),
+            types::SET => Ok(match value {
+                Value::List(items) => {
+                    Value::Set(Shared::new(hashable_items(items.inner().clone())?))
+                }
+                Value::Tuple(items) => {
+                    Value::Set(Shared::new(hashable_items(items.inner().clone())?))
+                }
+                other => other,
+            }),
+            types::FROZENSET => Ok(match value {
+                Value::List(items) => {
+                    Value::FrozenSet(SharedFrozen::new(hashable_items(items.inner().clone())?))
+                }
+                Value::Tuple(items) => {
+                    Value::FrozenSet(SharedFrozen::new(hashable_items(items.inner().clone())?))
+                }
+                other => other,
+            }),
+            types::BYTEARRAY => Ok(match value {
+                Value::Bytes(bytes) => Value::ByteArray(Shared::new(bytes.into_raw_or_cloned())),
+                other => other,
+            }),
+            types::BIGINT => match value {
+                Value::String(digits) => match BigInt::from_str(digits.inner()) {
+                    Ok(i) => Ok(match i.to_i64() {
+                        Some(i) => Value::I64(i),
+                        None => Value::Int(i),
+                    }),
+                    Err(_) => Err(Error::Syntax(ErrorCode::InvalidLiteral(
+                        digits.inner().as_bytes().to_vec(),
+                    ))),
+                },
+                other => Ok(other),
+            },
+            _ => match types::array_typecode(name) {
+                Some(typecode) => {
+                    let mut object = Object::new("array", "array");
+                    object.new = false;
+                    object.args = vec![Value::String(SharedFrozen::new(typecode.into())), value];
+                    Ok(Value::Object(Shared::new(object)))
+                }
+                None => Ok(value),
+            },
+        }
     }
 
     #[inline]
@@ -836,16 +1138,18 @@ impl<'a> ser::Serializer for &'a mut Serializer {
             variant: "",
             key: None,
             state: BTreeMap::new(),
+            object: false,
         })
     }
 
     #[inline]
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
         Ok(SerializeMap {
             ser: self,
             variant: "",
             key: None,
             state: BTreeMap::new(),
+            object: name == types::OBJECT,
         })
     }
 
@@ -862,10 +1166,15 @@ impl<'a> ser::Serializer for &'a mut Serializer {
             variant,
             key: None,
             state: BTreeMap::new(),
+            object: false,
         })
     }
 }
 
+fn hashable_items(items: Vec<Value>) -> Result<BTreeSet<HashableValue>> {
+    items.into_iter().map(Value::into_hashable).collect()
+}
+
 /// Serialize any serde serializable object into a `value::Value`.
 pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value> {
     value.serialize(&mut Serializer)
@@ -876,3 +1185,20 @@ pub fn from_value<'de, T: de::Deserialize<'de>>(value: Value) -> Result<T> {
     let mut de = Deserializer::new(value);
     de::Deserialize::deserialize(&mut de)
 }
+
+/// Visit a big integer with the smallest of serde's integer types that fits.
+pub(crate) fn visit_bigint<'de, V: Visitor<'de>>(v: BigInt, visitor: V) -> Result<V::Value> {
+    if let Some(i) = v.to_i64() {
+        visitor.visit_i64(i)
+    } else if let Some(u) = v.to_u64() {
+        visitor.visit_u64(u)
+    } else if let Some(i) = v.to_i128() {
+        visitor.visit_i128(i)
+    } else if let Some(u) = v.to_u128() {
+        visitor.visit_u128(u)
+    } else {
+        Err(Error::Syntax(ErrorCode::InvalidValue(
+            "integer too large".into(),
+        )))
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright (c) 2015-2021 Georg Brandl.  Licensed under the Apache License,
+// Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at
+// your option. This file may not be copied, modified, or distributed except
+// according to those terms.
+
+//! ZODB FileStorage databases (`Data.fs` files).
+//!
+//! A FileStorage is a log of transactions, each holding a data record for
+//! every object it changed.  A record stores the object as two pickles, one
+//! after the other with a shared memo: the class of the object, and its
+//! state.  References to other persistent objects in the state are pickled
+//! as persistent IDs, which are decoded to [`Value::PersistentId`] and can be
+//! interpreted with [`Reference::from_pid`].
+//!
+//! A [`FileStorage`] borrows the bytes of the file, e.g. of a memory-mapped
+//! one.  Index files (`Data.fs.index`) aren't read; transactions are always
+//! iterated from the start.
+//!
+//! ```no_run
+//! # fn main() -> pickled::Result<()> {
+//! let bytes = std::fs::read("Data.fs")?;
+//! let storage = pickled::zodb::FileStorage::from_slice(&bytes, Default::default())?;
+//! for transaction in storage.transactions() {
+//!     for record in transaction?.records {
+//!         println!("{:#x}: {}", record.oid, record.class);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use byteorder::{BigEndian, ByteOrder};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read};
+
+use crate::de::{DeOptions, Deserializer, value_from_slice};
+use crate::error::{Error, ErrorCode, Result};
+use crate::value::{Shared, Value};
+
+const TRANS_HDR_LEN: usize = 23;
+const DATA_HDR_LEN: usize = 42;
+
+/// A FileStorage database.
+pub struct FileStorage<'a> {
+    data: &'a [u8],
+    options: DeOptions,
+}
+
+/// A committed transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction {
+    /// The transaction id, which encodes the time of the commit.
+    pub tid: u64,
+    /// `' '`, or `'p'` if the storage has been packed since.
+    pub status: char,
+    /// The user, usually given as `"<path> <name>"`.
+    pub user: String,
+    /// The description, e.g. the note of `transaction.note()`.
+    pub description: String,
+    /// The extension dict with further metadata (empty if there is none).
+    pub extension: Value,
+    /// The data records, one per changed object.
+    pub records: Vec<Record>,
+}
+
+/// A data record: the new revision of an object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// The object id.
+    pub oid: u64,
+    /// The id of the transaction that wrote the record.
+    pub tid: u64,
+    /// The class of the object, given by its dotted name, or a tuple of the
+    /// class and the arguments to `__new__`.  `None` if the record deletes
+    /// the object, e.g. by undoing its creation.
+    pub class: Value,
+    /// The state of the object, `None` if the record deletes it.
+    pub state: Value,
+}
+
+/// A reference from one persistent object to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reference {
+    /// `oid` or `(oid, class)`: an object in the same database.
+    Object { oid: u64, class: Option<String> },
+    /// `['w', (oid,)]` or `['w', (oid, database)]`: a weak reference.
+    Weak { oid: u64, database: Option<String> },
+    /// `['m', (database, oid, class)]` or `['n', (database, oid)]`: an object
+    /// in another database.
+    Database {
+        database: String,
+        oid: u64,
+        class: Option<String>,
+    },
+}
+
+impl<'a> FileStorage<'a> {
+    /// Open a FileStorage from the bytes of its `Data.fs` file.
+    ///
+    /// The options are used to decode the pickles.
+    pub fn from_slice(data: &'a [u8], options: DeOptions) -> Result<FileStorage<'a>> {
+        // FS21 files have Python 2 pickles, FS30 files Python 3 ones.
+        if !data.starts_with(b"FS21") && !data.starts_with(b"FS30") {
+            return Err(invalid("bad magic string".into()));
+        }
+        Ok(FileStorage { data, options })
+    }
+
+    /// Iterate over the committed transactions, oldest first.
+    pub fn transactions(&self) -> Transactions<'a, '_> {
+        Transactions {
+            storage: self,
+            pos: 4,
+        }
+    }
+
+    // Read the transaction at `pos`, and return it with the position of the
+    // next one, or `None` if it hasn't been committed.
+    fn transaction(&self, pos: usize) -> Result<Option<(Transaction, usize)>> {
+        let header = self.get(pos, TRANS_HDR_LEN)?;
+        let tid = BigEndian::read_u64(&header[..8]);
+        let len = self.len(&header[8..16])?;
+        let status = char::from(header[16]);
+        let user_len = BigEndian::read_u16(&header[17..19]).into();
+        let description_len = BigEndian::read_u16(&header[19..21]).into();
+        let extension_len = BigEndian::read_u16(&header[21..23]).into();
+        if status == 'c' {
+            return Ok(None);
+        }
+        let end = pos.saturating_add(len);
+        if self.len(self.get(end, 8)?)? != len {
+            return Err(invalid(format!("transaction length mismatch at {pos}")));
+        }
+
+        let mut start = pos + TRANS_HDR_LEN;
+        let user = self.get(start, user_len)?;
+        start += user_len;
+        let description = self.get(start, description_len)?;
+        start += description_len;
+        let extension = match self.get(start, extension_len)? {
+            [] => Value::Dict(Shared::new(BTreeMap::new())),
+            extension => value_from_slice(extension, self.options.clone())?,
+        };
+        start += extension_len;
+
+        let mut headers = Vec::new();
+        let mut pickles = VecDeque::new();
+        while start < end {
+            let (oid, tid, data, next) = self.record(start)?;
+            headers.push((oid, tid, data.is_some()));
+            pickles.extend(data);
+            start = next;
+        }
+        let mut de = Deserializer::new(Pickles(pickles), self.options.clone());
+        let mut records = Vec::with_capacity(headers.len());
+        for (oid, tid, has_data) in headers {
+            let (class, state) = if has_data {
+                de.reset_memo();
+                (de.deserialize_class()?, de.deserialize_value()?)
+            } else {
+                (Value::None, Value::None)
+            };
+            records.push(Record {
+                oid,
+                tid,
+                class,
+                state,
+            });
+        }
+        de.end()?;
+
+        let transaction = Transaction {
+            tid,
+            status,
+            user: String::from_utf8_lossy(user).into_owned(),
+            description: String::from_utf8_lossy(description).into_owned(),
+            extension,
+            records,
+        };
+        Ok(Some((transaction, end + 8)))
+    }
+
+    // Read the data record at `pos`, and return its oid and tid, its pickles
+    // (following back pointers to earlier records, as undo and copying write
+    // them) and the position of the next record.
+    fn record(&self, pos: usize) -> Result<(u64, u64, Option<&'a [u8]>, usize)> {
+        let (oid, tid, len, start) = self.data_header(pos)?;
+        if len > 0 {
+            return Ok((oid, tid, Some(self.get(start, len)?), start + len));
+        }
+        let next = start + 8;
+        let (mut current, mut back) = (pos, self.len(self.get(start, 8)?)?);
+        while back != 0 {
+            if back >= current {
+                return Err(invalid(format!("bad back pointer at {pos}")));
+            }
+            let (_, _, len, start) = self.data_header(back)?;
+            if len > 0 {
+                return Ok((oid, tid, Some(self.get(start, len)?), next));
+            }
+            (current, back) = (back, self.len(self.get(start, 8)?)?);
+        }
+        Ok((oid, tid, None, next))
+    }
+
+    // Read the header of the data record at `pos`: the oid, the tid, the
+    // length of the pickles (0 for a back pointer) and where they start.
+    fn data_header(&self, pos: usize) -> Result<(u64, u64, usize, usize)> {
+        let header = self.get(pos, DATA_HDR_LEN)?;
+        let oid = BigEndian::read_u64(&header[..8]);
+        let tid = BigEndian::read_u64(&header[8..16]);
+        let version_len = usize::from(BigEndian::read_u16(&header[32..34]));
+        let len = self.len(&header[34..42])?;
+        let mut start = pos + DATA_HDR_LEN;
+        // Versions were removed in ZODB 3.9, but old files may have them.
+        if version_len > 0 {
+            start += 16 + version_len;
+        }
+        Ok((oid, tid, len, start))
+    }
+
+    fn get(&self, pos: usize, len: usize) -> Result<&'a [u8]> {
+        (pos.checked_add(len))
+            .and_then(|end| self.data.get(pos..end))
+            .ok_or_else(|| invalid(format!("truncated file at {pos}")))
+    }
+
+    fn len(&self, bytes: &[u8]) -> Result<usize> {
+        usize::try_from(BigEndian::read_u64(bytes)).map_err(|_| invalid("bad length".into()))
+    }
+}
+
+/// An iterator over the transactions of a [`FileStorage`].
+pub struct Transactions<'a, 's> {
+    storage: &'s FileStorage<'a>,
+    pos: usize,
+}
+
+impl Iterator for Transactions<'_, '_> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Result<Transaction>> {
+        if self.pos >= self.storage.data.len() {
+            return None;
+        }
+        match self.storage.transaction(self.pos) {
+            Ok(Some((transaction, next))) => {
+                self.pos = next;
+                Some(Ok(transaction))
+            }
+            Ok(None) => {
+                self.pos = self.storage.data.len();
+                None
+            }
+            Err(error) => {
+                self.pos = self.storage.data.len();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl Record {
+    /// Return the references to other objects in the state.
+    pub fn references(&self) -> Result<Vec<Reference>> {
+        fn visit(value: &Value, refs: &mut Vec<Result<Reference>>) {
+            match value {
+                Value::PersistentId(pid) => refs.push(Reference::from_pid(pid)),
+                Value::List(items) => items.inner().iter().for_each(|item| visit(item, refs)),
+                Value::Tuple(items) => items.inner().iter().for_each(|item| visit(item, refs)),
+                Value::Dict(dict) => dict.inner().values().for_each(|item| visit(item, refs)),
+                Value::Object(object) => {
+                    let object = object.inner();
+                    object.args.iter().for_each(|item| visit(item, refs));
+                    object.kwargs.values().for_each(|item| visit(item, refs));
+                    object.items.iter().for_each(|item| visit(item, refs));
+                    object.state.iter().for_each(|item| visit(item, refs));
+                }
+                _ => {}
+            }
+        }
+        let mut refs = Vec::new();
+        visit(&self.state, &mut refs);
+        refs.into_iter().collect()
+    }
+}
+
+impl Reference {
+    /// Interpret a persistent ID as ZODB pickles it.
+    pub fn from_pid(pid: &Value) -> Result<Reference> {
+        let error = || invalid(format!("persistent reference {pid}"));
+        let items = |value: &Value| match value {
+            Value::Tuple(items) => Some(items.inner().clone()),
+            _ => None,
+        };
+        if let Some(oid) = oid(pid) {
+            return Ok(Reference::Object { oid, class: None });
+        }
+        let reference = match pid {
+            Value::Tuple(items) => match &items.inner()[..] {
+                [oid_value, class_value] => Reference::Object {
+                    oid: oid(oid_value).ok_or_else(error)?,
+                    class: class(class_value),
+                },
+                _ => return Err(error()),
+            },
+            Value::List(list) => {
+                let list = list.inner();
+                let [Value::String(kind), args] = &list[..] else {
+                    return Err(error());
+                };
+                let args = items(args).ok_or_else(error)?;
+                match (kind.inner().as_str(), &args[..]) {
+                    ("w", [oid_value]) => Reference::Weak {
+                        oid: oid(oid_value).ok_or_else(error)?,
+                        database: None,
+                    },
+                    ("w", [oid_value, Value::String(database)]) => Reference::Weak {
+                        oid: oid(oid_value).ok_or_else(error)?,
+                        database: Some(database.inner().clone()),
+                    },
+                    ("m", [Value::String(database), oid_value, class_value]) => {
+                        Reference::Database {
+                            database: database.inner().clone(),
+                            oid: oid(oid_value).ok_or_else(error)?,
+                            class: class(class_value),
+                        }
+                    }
+                    ("n", [Value::String(database), oid_value]) => Reference::Database {
+                        database: database.inner().clone(),
+                        oid: oid(oid_value).ok_or_else(error)?,
+                        class: None,
+                    },
+                    _ => return Err(error()),
+                }
+            }
+            _ => return Err(error()),
+        };
+        Ok(reference)
+    }
+}
+
+// An oid is pickled as 8 bytes, big-endian; in Python 2 pickles, as a str,
+// whose characters are the bytes if it was decoded, e.g. as Latin-1.
+fn oid(value: &Value) -> Option<u64> {
+    let bytes = match value {
+        Value::Bytes(bytes) => bytes.inner().clone(),
+        Value::String(string) => (string.inner().chars())
+            .map(|c| u8::try_from(c).ok())
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    (bytes.len() == 8).then(|| BigEndian::read_u64(&bytes))
+}
+
+// The class of a reference, by dotted name, or as `(module, name)` in
+// databases written by old ZODB versions.
+fn class(value: &Value) -> Option<String> {
+    match value {
+        Value::String(name) => Some(name.inner().clone()),
+        Value::Tuple(items) => match &items.inner()[..] {
+            [Value::String(module), Value::String(name)] => {
+                Some(format!("{}.{}", module.inner(), name.inner()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The pickles of the records of a transaction, read one after another.
+struct Pickles<'a>(VecDeque<&'a [u8]>);
+
+impl Read for Pickles<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(front) = self.0.front_mut() {
+            if !front.is_empty() {
+                return front.read(buf);
+            }
+            self.0.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+fn invalid(what: String) -> Error {
+    Error::Syntax(ErrorCode::InvalidValue(format!("FileStorage: {what}")))
+}
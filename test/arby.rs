@@ -114,6 +114,9 @@ impl Arbitrary for Value {
             Value::Bytes(ref v) => {
                 Box::new(Arbitrary::shrink(v.inner()).map(|x| Value::Bytes(SharedFrozen::new(x))))
             }
+            Value::ByteArray(ref v) => {
+                Box::new(Arbitrary::shrink(&*v.inner()).map(|x| Value::ByteArray(Shared::new(x))))
+            }
             Value::String(ref v) => {
                 Box::new(Arbitrary::shrink(v.inner()).map(|x| Value::String(SharedFrozen::new(x))))
             }
@@ -132,6 +135,11 @@ impl Arbitrary for Value {
             Value::Dict(ref v) => {
                 Box::new(Arbitrary::shrink(&*v.inner()).map(|x| Value::Dict(Shared::new(x))))
             }
+            Value::WtfString(_)
+            | Value::Object(_)
+            | Value::NdArray(_)
+            | Value::PersistentId(_)
+            | Value::Global(..) => empty_shrinker(),
         }
     }
 }
@@ -146,7 +154,7 @@ impl Arbitrary for HashableValue {
             HashableValue::None => empty_shrinker(),
             HashableValue::Bool(v) => Box::new(Arbitrary::shrink(&v).map(HashableValue::Bool)),
             HashableValue::I64(v) => Box::new(Arbitrary::shrink(&v).map(HashableValue::I64)),
-            HashableValue::Int(_) => empty_shrinker(),
+            HashableValue::Int(_) | HashableValue::WtfString(_) => empty_shrinker(),
             HashableValue::F64(v) => Box::new(Arbitrary::shrink(&v).map(HashableValue::F64)),
             HashableValue::Bytes(ref v) => Box::new(
                 Arbitrary::shrink(v.inner()).map(|x| HashableValue::Bytes(SharedFrozen::new(x))),
@@ -14,6 +14,7 @@ macro_rules! pyobj {
     (ii=$i:expr) => { Value::Int($i.clone()) };
     (f=$f:expr)  => { Value::F64($f) };
     (bb=$b:expr) => { Value::Bytes(crate::value::SharedFrozen::new($b.to_vec())) };
+    (ba=$b:expr) => { Value::ByteArray(crate::value::Shared::new($b.to_vec())) };
     (s=$s:expr)  => { Value::String(crate::value::SharedFrozen::new($s.to_string())) };
     (t=($($m:ident=$v:tt),*))  => { Value::Tuple(crate::value::SharedFrozen::new(vec![$(pyobj!($m=$v)),*])) };
     (l=[$($m:ident=$v:tt),*])  => { Value::List(crate::value::Shared::new(vec![$(pyobj!($m=$v)),*])) };
@@ -38,13 +39,19 @@ macro_rules! hpyobj {
 }
 
 mod struct_tests {
+    use crate::error::{Error, ErrorCode};
+    use crate::types::{
+        EnumValues, PyArray, PyBigInt, PyByteArray, PyBytes, PyFrozenSet, PySet, PyTuple,
+    };
     use crate::{
-        HashableValue, SerOptions, Value, from_slice, from_value, to_value, to_vec,
+        DeOptions, HashableValue, SerOptions, Value, from_slice, from_value, to_value, to_vec,
         value_from_slice, value_to_vec,
     };
+    use num_bigint::BigInt;
+    use pickled_derive::PickleClass;
     use serde::{de, ser};
     use serde_derive::{Deserialize, Serialize};
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
     use std::fmt;
     use std::iter::FromIterator;
 
@@ -199,6 +206,249 @@ mod struct_tests {
         );
     }
 
+    #[test]
+    fn encode_struct_as_namespace() {
+        let value = Outer {
+            inner: vec![Inner {
+                a: (),
+                b: 32,
+                c: vec!["doc".into()],
+            }],
+        };
+        let vec = to_vec(&value, SerOptions::new().structs_as_namespace()).unwrap();
+        assert!(vec.starts_with(b"\x80\x03ctypes\nSimpleNamespace\n)R"));
+        let py_val: Value = value_from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(
+            py_val,
+            pyobj!(d={s="inner" => l=[d={s="a" => n=None, s="b" => i=32,
+                                          s="c" => l=[s="doc"]}]})
+        );
+        let decoded: Outer = from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Typed {
+        #[serde(with = "crate::types::tuple")]
+        point: Vec<i32>,
+        #[serde(with = "crate::types::set")]
+        tags: HashSet<String>,
+        #[serde(with = "crate::types::bytes")]
+        payload: Vec<u8>,
+        #[serde(with = "crate::types::bytearray")]
+        buffer: Vec<u8>,
+        #[serde(with = "crate::types::bigint")]
+        id: BigInt,
+    }
+
+    #[test]
+    fn encode_python_types() {
+        let big = BigInt::from(10000000000u64) * BigInt::from(10000000000u64);
+        test_encode_ok(PyTuple(vec![1, 2]), pyobj!(t = (i = 1, i = 2)));
+        test_encode_ok(PyTuple(Vec::<i32>::new()), pyobj!(t = ()));
+        test_encode_ok(PyTuple(vec![PyTuple(vec![1])]), pyobj!(t = (t = (i = 1))));
+        test_encode_ok(PySet(vec![1, 2]), pyobj!(ss = (i = 1, i = 2)));
+        test_encode_ok(PyFrozenSet((1, 2)), pyobj!(fs = (i = 1, i = 2)));
+        test_encode_ok(PyBytes(b"ab".to_vec()), pyobj!(bb = b"ab"));
+        test_encode_ok(PyByteArray(b"ab".to_vec()), pyobj!(ba = b"ab"));
+        test_encode_ok(PyBigInt(big.clone()), pyobj!(ii = big));
+        test_encode_ok(PyBigInt(BigInt::from(-5)), pyobj!(i = -5));
+        test_encode_ok_with_opt(
+            PyByteArray(b"\xff".to_vec()),
+            pyobj!(ba = b"\xff"),
+            SerOptions::new().proto_v2(),
+        );
+    }
+
+    #[test]
+    fn python_types_roundtrip() {
+        let big = BigInt::from(10000000000u64) * BigInt::from(10000000000u64);
+        let value = Typed {
+            point: vec![1, 2],
+            tags: HashSet::from_iter(vec!["a".to_string()]),
+            payload: b"\x00\x01".to_vec(),
+            buffer: b"\x02".to_vec(),
+            id: big.clone(),
+        };
+        test_encode_ok(
+            value.clone(),
+            pyobj!(d={s="point" => t=(i=1, i=2), s="tags" => ss=(s="a"),
+                      s="payload" => bb=b"\x00\x01", s="buffer" => ba=b"\x02",
+                      s="id" => ii=big}),
+        );
+        for options in [SerOptions::new(), SerOptions::new().proto_v2()] {
+            let vec = to_vec(&value, options).unwrap();
+            let decoded: Typed = from_slice(&vec, Default::default()).unwrap();
+            assert_eq!(decoded, value);
+        }
+        let decoded: Typed = from_value(to_value(&value).unwrap()).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn arrays() {
+        type Arrays = (Vec<f64>, Vec<i32>, String);
+        let expected = (vec![1.5, -2.0], vec![1, -2], "hi".to_string());
+        // pickle.dumps([array('d', [1.5, -2.0]), array('i', [1, -2]),
+        //               array('u', 'hi')], 3)
+        let data =
+            b"\x80\x03]q\x00(carray\n_array_reconstructor\nq\x01(carray\narray\nq\x02X\x01\x00\
+                     \x00\x00dq\x03K\x10C\x10\x00\x00\x00\x00\x00\x00\xf8?\x00\x00\x00\x00\x00\x00\
+                     \x00\xc0q\x04tq\x05Rq\x06h\x01(h\x02X\x01\x00\x00\x00iq\x07K\x08C\x08\x01\x00\
+                     \x00\x00\xfe\xff\xff\xffq\x08tq\tRq\nh\x01(h\x02X\x01\x00\x00\x00uq\x0bK\x14C\
+                     \x08h\x00\x00\x00i\x00\x00\x00q\x0ctq\rRq\x0ee.";
+        let decoded: Arrays = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, expected);
+        // The same in Python 2, with protocol 2
+        let data = b"\x80\x02]q\x00(carray\narray\nq\x01U\x01dq\x02]q\x03(G?\xf8\x00\x00\x00\x00\x00\
+                     \x00G\xc0\x00\x00\x00\x00\x00\x00\x00e\x86q\x04Rq\x05h\x01U\x01iq\x06]q\x07(K\
+                     \x01J\xfe\xff\xff\xffe\x86q\x08Rq\th\x01U\x01uq\n]q\x0b(X\x01\x00\x00\x00hq\x0c\
+                     X\x01\x00\x00\x00iq\re\x86q\x0eRq\x0fe.";
+        let decoded: Arrays = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, expected);
+        // Big-endian int16 (machine format 5)
+        let data = b"\x80\x03carray\n_array_reconstructor\n(carray\narray\nX\x01\x00\x00\x00h\
+                     K\x05C\x04\xff\xfe\x00\x01tR.";
+        let decoded: Vec<i16> = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, vec![-2, 1]);
+
+        // Written as array.array(typecode, list)
+        let value = PyArray(vec![1.5f64, -2.0]);
+        let vec = to_vec(&value, Default::default()).unwrap();
+        let object = value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap();
+        assert_eq!(object, to_value(&value).unwrap());
+        let Value::Object(ref object) = object else {
+            panic!("expected an object, got {object}");
+        };
+        assert_eq!(object.inner().class_path(), "array.array");
+        assert_eq!(
+            object.inner().args,
+            vec![pyobj!(s = "d"), pyobj!(l = [f = 1.5, f = (-2.0)])]
+        );
+        let decoded: PyArray<f64> = from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[derive(Clone, Debug, PartialEq, PickleClass)]
+    #[pickle(class = "shapes.Point")]
+    struct Point {
+        #[pickle(arg)]
+        x: i64,
+        #[pickle(arg)]
+        y: i64,
+        #[pickle(rename = "name")]
+        label: String,
+        #[pickle(default)]
+        tags: Vec<String>,
+        #[pickle(skip)]
+        cached: Option<u32>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, PickleClass)]
+    #[pickle(class = "shapes.Slotted")]
+    struct Slotted {
+        #[pickle(slot)]
+        a: i64,
+        #[pickle(slot)]
+        b: String,
+    }
+
+    #[test]
+    fn decode_class() {
+        // pickle.dumps(Point(1, 2, "origin"), 3) with __getnewargs__ -> (x, y)
+        let data = b"\x80\x03cshapes\nPoint\nq\x00K\x01K\x02\x86q\x01\x81q\x02}q\x03(X\x01\x00\x00\x00\
+                     xq\x04K\x01X\x01\x00\x00\x00yq\x05K\x02X\x04\x00\x00\x00nameq\x06X\x06\x00\x00\x00\
+                     originq\x07ub.";
+        let point: Point = from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            point,
+            Point {
+                x: 1,
+                y: 2,
+                label: "origin".into(),
+                tags: vec![],
+                cached: None,
+            }
+        );
+        // pickle.dumps(Slotted(5, "x"), 3) with __slots__ = ("a", "b")
+        let data = b"\x80\x03cshapes\nSlotted\nq\x00)\x81q\x01N}q\x02(X\x01\x00\x00\x00aq\x03K\x05\
+                     X\x01\x00\x00\x00bq\x04X\x01\x00\x00\x00xq\x05u\x86q\x06b.";
+        let slotted: Slotted = from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            slotted,
+            Slotted {
+                a: 5,
+                b: "x".into()
+            }
+        );
+        // The class has to match.
+        assert!(from_slice::<Point>(data, Default::default()).is_err());
+    }
+
+    #[test]
+    fn slot_state() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Labeled {
+            label: String,
+            size: i32,
+        }
+        // o = Labeled(); o.label = 'l'; o.size = 3; pickle.dumps(o, 2)
+        // with __slots__ = ("label", "__dict__")
+        let data =
+            b"\x80\x02cshapes\nLabeled\nq\x00)\x81q\x01}q\x02X\x04\x00\x00\x00sizeq\x03K\x03s\
+                     }q\x04X\x05\x00\x00\x00labelq\x05X\x01\x00\x00\x00lq\x06s\x86q\x07b.";
+        let labeled: Labeled = from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            labeled,
+            Labeled {
+                label: "l".into(),
+                size: 3
+            }
+        );
+        assert_eq!(
+            value_from_slice(data, Default::default()).unwrap(),
+            pyobj!(d={s="size" => i=3, s="label" => s="l"})
+        );
+        // Kept objects have the original state.
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(
+            object.inner().state,
+            Some(pyobj!(t=(d={s="size" => i=3}, d={s="label" => s="l"})))
+        );
+    }
+
+    #[test]
+    fn class_roundtrip() {
+        let point = Point {
+            x: -1,
+            y: 7,
+            label: "p".into(),
+            tags: vec!["a".into()],
+            cached: Some(3),
+        };
+        let vec = to_vec(&point, Default::default()).unwrap();
+        assert!(vec.starts_with(b"\x80\x03cshapes\nPoint\n"));
+        let decoded: Point = from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(
+            decoded,
+            Point {
+                cached: None,
+                ..point
+            }
+        );
+
+        let slotted = Slotted {
+            a: 1,
+            b: "b".into(),
+        };
+        let vec = to_vec(&slotted, SerOptions::new().proto_v2()).unwrap();
+        let decoded: Slotted = from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(decoded, slotted);
+    }
+
     #[test]
     fn decode_types() {
         test_decode_ok(pyobj!(n = None), ());
@@ -297,12 +547,346 @@ mod struct_tests {
             ],
         );
     }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    enum Status {
+        Ok,
+        NotFound,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    #[test]
+    fn python_enum() {
+        let status_values = || EnumValues::Explicit(vec![pyobj!(i = 200), pyobj!(i = 404)]);
+        let options =
+            DeOptions::new().python_enum("Status", "shapes.status.Status", status_values());
+        // pickle.dumps([Status.NOT_FOUND, Status.OK, Status.NOT_FOUND], 3)
+        let data =
+            b"\x80\x03]q\x00(cshapes.status\nStatus\nq\x01M\x94\x01\x85q\x02Rq\x03h\x01K\xc8\
+                     \x85q\x04Rq\x05h\x03e.";
+        let decoded: Vec<Status> = from_slice(data, options.clone()).unwrap();
+        assert_eq!(
+            decoded,
+            vec![Status::NotFound, Status::Ok, Status::NotFound]
+        );
+        // Without the registration, the integer values can't be matched.
+        assert!(from_slice::<Vec<Status>>(data, Default::default()).is_err());
+        // Neither can members of a different class.
+        assert!(
+            from_slice::<Vec<Color>>(
+                data,
+                options.python_enum("Color", "shapes.Color", EnumValues::Names)
+            )
+            .is_err()
+        );
+
+        // pickle.dumps(Color.Green, 2) for a `str` enum with names as values
+        let data =
+            b"\x80\x02cshapes.status\nColor\nq\x00X\x05\x00\x00\x00Greenq\x01\x85q\x02Rq\x03.";
+        let decoded: Color = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, Color::Green);
+        // The same member kept as an object in a `Value`.
+        let value = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        assert_eq!(from_value::<Color>(value).unwrap(), Color::Green);
+
+        let options =
+            SerOptions::new().python_enum("Status", "shapes.status.Status", status_values());
+        let vec = to_vec(&(Status::Ok, Color::Red), options).unwrap();
+        assert_eq!(
+            vec,
+            b"\x80\x03(cshapes.status\nStatus\nJ\xc8\x00\x00\x00\x85RX\x03\x00\x00\x00Redt."
+        );
+        let decoded: (Status, Color) = from_slice(
+            &vec,
+            DeOptions::new().python_enum("Status", "shapes.status.Status", status_values()),
+        )
+        .unwrap();
+        assert_eq!(decoded, (Status::Ok, Color::Red));
+    }
+
+    #[derive(Clone, Debug, PartialEq, Deserialize)]
+    struct KeyEvent {
+        key: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Deserialize)]
+    enum Event {
+        Click {
+            x: i64,
+            y: i64,
+        },
+        #[serde(rename = "shapes.events.Scroll")]
+        Scroll {
+            delta: i64,
+        },
+        Key(KeyEvent),
+        Quit,
+    }
+
+    #[test]
+    fn decode_enum_by_class() {
+        // pickle.dumps([Click(1, 2), Scroll(-3), Key("a"), Quit(), Click(5, 6)], 3)
+        let data = b"\x80\x03]q\x00(cshapes.events\nClick\nq\x01)\x81q\x02}q\x03(X\x01\x00\x00\x00xq\x04\
+                     K\x01X\x01\x00\x00\x00yq\x05K\x02ubcshapes.events\nScroll\nq\x06)\x81q\x07}q\x08\
+                     X\x05\x00\x00\x00deltaq\tJ\xfd\xff\xff\xffsbcshapes.events\nKey\nq\n)\x81q\x0b}q\x0c\
+                     X\x03\x00\x00\x00keyq\rX\x01\x00\x00\x00aq\x0esbcshapes.events\nQuit\nq\x0f)\x81q\x10\
+                     h\x01)\x81q\x11}q\x12(h\x04K\x05h\x05K\x06ube.";
+        let expected = vec![
+            Event::Click { x: 1, y: 2 },
+            Event::Scroll { delta: -3 },
+            Event::Key(KeyEvent { key: "a".into() }),
+            Event::Quit,
+            Event::Click { x: 5, y: 6 },
+        ];
+        let decoded: Vec<Event> = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, expected);
+        // The same works from objects kept in a `Value`.
+        let value = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let decoded: Vec<Event> = from_value(value).unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct NamedPoint {
+        x: i64,
+        y: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct TuplePoint(i64, String);
+
+    #[test]
+    fn namedtuple() {
+        // Point = namedtuple("Point", "x y"); pickle.dumps(Point(1, "a"), 3)
+        let data =
+            b"\x80\x03cshapes.nt\nPoint\nq\x00K\x01X\x01\x00\x00\x00aq\x01\x86q\x02\x81q\x03.";
+        let value = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(value, pyobj!(t = (i = 1, s = "a")));
+        let decoded: NamedPoint = from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            decoded,
+            NamedPoint {
+                x: 1,
+                y: "a".into()
+            }
+        );
+        let decoded: TuplePoint = from_slice(data, Default::default()).unwrap();
+        assert_eq!(decoded, TuplePoint(1, "a".into()));
+
+        let options = SerOptions::new()
+            .namedtuple("NamedPoint", "shapes.nt.Point")
+            .namedtuple("TuplePoint", "shapes.nt.Point");
+        let vec = to_vec(&TuplePoint(1, "a".into()), options.clone()).unwrap();
+        assert_eq!(
+            vec,
+            b"\x80\x03cshapes.nt\nPoint\n(J\x01\x00\x00\x00X\x01\x00\x00\x00at\x81."
+        );
+        let point = NamedPoint {
+            x: 2,
+            y: "b".into(),
+        };
+        let vec = to_vec(&point, options).unwrap();
+        let decoded: NamedPoint = from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(decoded, point);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Ids {
+        hash: u64,
+        small: i128,
+        uuid: u128,
+        signed: i128,
+    }
+
+    #[test]
+    fn integers_128() {
+        let ids = Ids {
+            hash: u64::MAX,
+            small: -5,
+            uuid: u128::MAX - 1,
+            signed: i128::MIN,
+        };
+        let (hash, uuid, signed) = (
+            BigInt::from(u64::MAX),
+            BigInt::from(u128::MAX - 1),
+            BigInt::from(i128::MIN),
+        );
+        let target = pyobj!(d={s="hash" => ii=hash, s="small" => i=(-5),
+                               s="uuid" => ii=uuid, s="signed" => ii=signed});
+        test_encode_ok(ids.clone(), target.clone());
+        test_decode_ok(target, ids);
+
+        // Too large for any Rust integer type.
+        let huge: BigInt = BigInt::from(u128::MAX) * 2;
+        let huge = pyobj!(ii = huge);
+        assert!(from_value::<u128>(huge.clone()).is_err());
+        let decoded: PyBigInt = from_value(huge).unwrap();
+        assert_eq!(decoded.0, BigInt::from(u128::MAX) * 2);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Blobs {
+        data: Vec<u8>,
+        key: [u8; 4],
+        boxed: Box<[u8]>,
+        numbers: Vec<u16>,
+    }
+
+    #[test]
+    fn bytes_as_u8_sequences() {
+        let blobs = Blobs {
+            data: b"abc".to_vec(),
+            key: *b"\x00\x01\xfe\xff",
+            boxed: Box::new(*b"xy"),
+            numbers: vec![1, 2],
+        };
+        let target = pyobj!(d={s="data" => bb=b"abc", s="key" => bb=b"\x00\x01\xfe\xff",
+                               s="boxed" => bb=b"xy", s="numbers" => l=[i=1, i=2]});
+        test_decode_ok(target, blobs.clone());
+        // Arrays are tuples to serde, and stay tuples.
+        let target = pyobj!(d={s="data" => bb=b"abc", s="key" => t=(i=0, i=1, i=254, i=255),
+                               s="boxed" => bb=b"xy", s="numbers" => l=[i=1, i=2]});
+        test_encode_ok_with_opt(
+            blobs.clone(),
+            target.clone(),
+            SerOptions::new().u8_sequences_as_bytes(),
+        );
+        test_encode_ok_with_opt(
+            blobs,
+            target,
+            SerOptions::new().u8_sequences_as_bytes().proto_v2(),
+        );
+        test_encode_ok_with_opt(
+            (1u8, 2u8),
+            pyobj!(t = (i = 1, i = 2)),
+            SerOptions::new().u8_sequences_as_bytes(),
+        );
+        // Lists of integers are still accepted.
+        test_decode_ok(pyobj!(l = [i = 1, i = 2]), vec![1u8, 2]);
+        // Mixed and empty sequences are not written as bytes.
+        let options = SerOptions::new().u8_sequences_as_bytes();
+        let vec = to_vec(&(vec![Some(1u8), None], Vec::<u8>::new()), options).unwrap();
+        let value = value_from_slice(&vec, Default::default()).unwrap();
+        assert_eq!(value, pyobj!(t = (l = [i = 1, n = None], l = [])));
+    }
+
+    #[test]
+    fn error_path() {
+        #[derive(Debug, Deserialize)]
+        struct Address {
+            #[allow(dead_code)]
+            zip: i32,
+        }
+        #[derive(Debug, Deserialize)]
+        struct User {
+            #[allow(dead_code)]
+            address: Address,
+        }
+        #[derive(Debug, Deserialize)]
+        struct Users {
+            #[allow(dead_code)]
+            users: Vec<User>,
+        }
+        // {'users': [{'address': {'zip': 1}}, {'address': {'zip': 'x'}}]}
+        let data = b"\x80\x02}q\x00X\x05\x00\x00\x00usersq\x01]q\x02(}q\x03X\x07\x00\x00\
+                     \x00addressq\x04}q\x05X\x03\x00\x00\x00zipq\x06K\x01ss}q\x07h\x04}q\x08\
+                     h\x06X\x01\x00\x00\x00xq\tsses.";
+        match from_slice::<Users>(data, Default::default()) {
+            Err(Error::Path {
+                path,
+                offset,
+                error,
+            }) => {
+                assert_eq!(path, "users[1].address.zip");
+                // The EMPTY_DICT opcode of the second address.
+                assert_eq!(offset, Some(60));
+                assert!(matches!(*error, Error::Syntax(ErrorCode::Structure(_))));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        // Keys that aren't identifiers are written like in Python.
+        let value = pyobj!(d={i=1 => l=[s="x"]});
+        let vec = value_to_vec(&value, Default::default()).unwrap();
+        let err = from_slice::<BTreeMap<i32, Vec<i32>>>(&vec, Default::default()).unwrap_err();
+        assert!(
+            err.to_string().ends_with("at [1][0] (opcode at offset 9)"),
+            "{err}"
+        );
+        // The wrapped error is available as the source and the innermost error.
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(matches!(
+            source.downcast_ref::<Error>(),
+            Some(Error::Syntax(ErrorCode::Structure(_)))
+        ));
+        assert!(matches!(err.innermost(), Error::Syntax(_)));
+        assert!(matches!(err.code(), Some(ErrorCode::Structure(_))));
+    }
+
+    #[test]
+    fn diagnose() {
+        use crate::{StrEncoding, StrErrors};
+
+        // [1, 'abc', <NEXT_BUFFER>]
+        let data = b"\x80\x03]q\x00(K\x01X\x03\x00\x00\x00abc\x97e.";
+        let err = value_from_slice(data, Default::default()).unwrap_err();
+        let report = crate::diagnose(data, &Default::default(), &err);
+        assert!(report.contains("unsupported opcode 0x97 (NEXT_BUFFER)"));
+        assert!(report.contains("opcode: NEXT_BUFFER (0x97) at offset 16"));
+        assert!(report.contains("00000010 >97<65 2e"));
+        assert!(report.contains("stack (2 items, top last):\n  1\n  \"abc\"\n"));
+        assert!(report.contains("mark stack (1 mark):\n  0: 1 item\n"));
+        assert!(report.contains("memo (1 entry, newest first):\n  #0: list (0 items)\n"));
+        // Errors with a path stop at the opcode creating the container.
+        let data = b"\x80\x03}q\x00X\x01\x00\x00\x00aq\x01]q\x02X\x01\x00\x00\x00xq\x03as.";
+        let err = from_slice::<BTreeMap<String, Vec<i32>>>(data, Default::default()).unwrap_err();
+        let report = crate::diagnose(data, &Default::default(), &err);
+        assert!(report.contains("opcode: EMPTY_LIST (0x5d) at offset 13"));
+        assert!(report.contains("stack (2 items, top last):\n  memo #0\n  memo #1\n"));
+        // The pickle is replayed with the options that failed.
+        let data = b"\x80\x02]q\x00(K\x01U\x04caf\xe9e.";
+        let options = DeOptions::new().str_encoding(StrEncoding::Ascii, StrErrors::Strict);
+        let err = value_from_slice(data, options.clone()).unwrap_err();
+        let report = crate::diagnose(data, &options, &err);
+        assert!(
+            report.contains("opcode: SHORT_BINSTRING (0x55) at offset 8"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn stringify_keys() {
+        let options = DeOptions::new().stringify_keys();
+        // {1: 'a', None: 'b', (1, 2): 'c'}
+        let data = b"\x80\x02}q\x00(K\x01X\x01\x00\x00\x00aq\x01NX\x01\x00\x00\x00bq\x02\
+                     K\x01K\x02\x86q\x03X\x01\x00\x00\x00cq\x04u.";
+        let map: BTreeMap<String, String> = from_slice(data, options.clone()).unwrap();
+        let expected = BTreeMap::from_iter(
+            [("1", "a"), ("null", "b"), ("(1, 2)", "c")]
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        assert_eq!(map, expected);
+        assert!(from_slice::<BTreeMap<String, String>>(data, Default::default()).is_err());
+        // {'1': 'a', '-20': 'b'}
+        let data = b"\x80\x02}q\x00(X\x01\x00\x00\x001q\x01X\x01\x00\x00\x00aq\x02\
+                     X\x03\x00\x00\x00-20q\x03X\x01\x00\x00\x00bq\x04u.";
+        let map: BTreeMap<i64, String> = from_slice(data, options).unwrap();
+        let expected = BTreeMap::from_iter([(1, "a".to_string()), (-20, "b".to_string())]);
+        assert_eq!(map, expected);
+    }
 }
 
 mod value_tests {
     use crate::Deserializer;
     use crate::error::{Error, ErrorCode};
-    use crate::{DeOptions, HashableValue, SerOptions, Value};
+    use crate::ndarray::{ArrayData, Dtype, Elements, NdArray};
+    use crate::pandas::{self, Labels};
+    use crate::value::Shared;
+    use crate::{DeOptions, HashableValue, Object, SerOptions, Value};
     use crate::{from_slice, to_vec, value_from_reader, value_from_slice, value_to_vec};
     use num_bigint::BigInt;
     use quickcheck::{Gen, QuickCheck};
@@ -341,7 +925,7 @@ mod value_tests {
                 l=[i=1, i=2, i=3],
                 ss=(i=0, i=42),
                 d={},
-                bb=b"\x00\x55\xaa\xff"
+                ba=b"\x00\x55\xaa\xff"
             ]
         });
         // Unfortunately, __dict__ keys are strings and so are pickled
@@ -360,6 +944,19 @@ mod value_tests {
         obj
     }
 
+    // The objects in a list decoded with `DeOptions::keep_objects`.
+    fn kept_objects(val: &Value) -> Vec<Object> {
+        let Value::List(list) = val else {
+            panic!("expected a list, got {val}");
+        };
+        (list.inner().iter())
+            .map(|item| match item {
+                Value::Object(object) => object.inner().clone(),
+                other => panic!("expected an object, got {other}"),
+            })
+            .collect()
+    }
+
     #[test]
     fn unpickle_all() {
         for &(major, proto) in TEST_CASES {
@@ -484,6 +1081,1023 @@ mod value_tests {
         de.end().unwrap();
     }
 
+    #[test]
+    fn py2_str_modes() {
+        use crate::{StrEncoding, StrErrors};
+        // Python 2: pickle.dumps({'a': 'caf\xe9', 'b': 'x'}, 2)
+        let data = b"\x80\x02}q\x00(U\x01aq\x01U\x04caf\xe9q\x02U\x01bq\x03U\x01xq\x04u.";
+        let decode = |options: DeOptions| value_from_slice(data, options);
+        assert_eq!(
+            decode(Default::default()).unwrap(),
+            pyobj!(d={bb=b"a" => bb=b"caf\xe9", bb=b"b" => bb=b"x"})
+        );
+        let options = DeOptions::new().str_encoding(StrEncoding::Latin1, StrErrors::Strict);
+        assert_eq!(
+            decode(options).unwrap(),
+            pyobj!(d={s="a" => s="café", s="b" => s="x"})
+        );
+        let options = DeOptions::new().str_encoding(StrEncoding::Ascii, StrErrors::Strict);
+        assert!(decode(options).is_err());
+        let options = DeOptions::new().str_encoding(StrEncoding::Ascii, StrErrors::Replace);
+        assert_eq!(
+            decode(options).unwrap(),
+            pyobj!(d={s="a" => s="caf\u{fffd}", s="b" => s="x"})
+        );
+        let options = DeOptions::new().str_encoding(StrEncoding::Utf8, StrErrors::Bytes);
+        assert_eq!(
+            decode(options).unwrap(),
+            pyobj!(d={s="a" => bb=b"caf\xe9", s="b" => s="x"})
+        );
+        // Keys as text, values as bytes, also through serde.
+        let options = DeOptions::new().str_key_encoding(StrEncoding::Ascii, StrErrors::Strict);
+        assert_eq!(
+            decode(options.clone()).unwrap(),
+            pyobj!(d={s="a" => bb=b"caf\xe9", s="b" => bb=b"x"})
+        );
+        let map: BTreeMap<String, Vec<u8>> = from_slice(data, options).unwrap();
+        let expected = BTreeMap::from_iter([
+            ("a".to_string(), b"caf\xe9".to_vec()),
+            ("b".to_string(), b"x".to_vec()),
+        ]);
+        assert_eq!(map, expected);
+        // Keys that can't be decoded are reported with the stream position.
+        // Python 2: pickle.dumps({'caf\xe9': 1}, 2)
+        let data = b"\x80\x02}q\x00U\x04caf\xe9q\x01K\x01s.";
+        let options = DeOptions::new().str_key_encoding(StrEncoding::Ascii, StrErrors::Strict);
+        match value_from_slice(data, options) {
+            Err(Error::Eval(ErrorCode::InvalidValue(msg), pos)) => {
+                assert_eq!(msg, r#"str is not ASCII: b"caf\xe9""#);
+                assert_eq!(pos, 17);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn simple_namespace() {
+        // pickle.dumps(types.SimpleNamespace(a=1), 3)
+        let data =
+            b"\x80\x03ctypes\nSimpleNamespace\nq\x00)Rq\x01}q\x02X\x01\x00\x00\x00aq\x03K\x01sb.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val, pyobj!(d={s="a" => i=1}));
+    }
+
+    #[test]
+    fn fix_imports() {
+        // Python 2: pickle.dumps(ValueError('x'), 2)
+        let data = b"\x80\x02cexceptions\nValueError\nq\x00U\x01xq\x01\x85q\x02Rq\x03.";
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(object.inner().class_path(), "builtins.ValueError");
+        // Protocol 2 gets the Python 2 names back.
+        let contains = |vec: Vec<u8>, part: &[u8]| vec.windows(part.len()).any(|w| w == part);
+        let vec = value_to_vec(&val, SerOptions::new().proto_v2()).unwrap();
+        assert!(contains(vec, b"cexceptions\nValueError\n"));
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert!(contains(vec, b"cbuiltins\nValueError\n"));
+
+        // pickle.dumps(Point(1, 2, 'o'), 1, fix_imports=False) uses the
+        // Python 3 name of copy_reg.
+        let data = b"ccopyreg\n_reconstructor\nq\x00(cshapes\nPoint\nq\x01cbuiltins\nobject\n\
+                     q\x02Ntq\x03Rq\x04}q\x05(X\x01\x00\x00\x00xq\x06K\x01X\x01\x00\x00\x00yq\x07\
+                     K\x02X\x04\x00\x00\x00nameq\x08X\x01\x00\x00\x00oq\tub.";
+        let options = DeOptions::new().replace_reconstructor_objects_structures();
+        assert_eq!(
+            value_from_slice(data, options).unwrap(),
+            pyobj!(d={s="x" => i=1, s="y" => i=2, s="name" => s="o"})
+        );
+
+        // Python 3 modules named like Python 2 ones are only mapped in
+        // pickles of protocols below 3.
+        let class_path = |data: &[u8]| {
+            let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+            let Value::Object(ref object) = val else {
+                panic!("expected an object, got {val}");
+            };
+            object.inner().class_path()
+        };
+        // pickle.dumps(dbm.Thing(), 4)
+        let data = b"\x80\x04\x8c\x03dbm\x94\x8c\x05Thing\x94\x93\x94)\x81\x94.";
+        assert_eq!(class_path(data), "dbm.Thing");
+        // Python 2: pickle.dumps(dbm.Thing(), 2)
+        let data = b"\x80\x02cdbm\nThing\nq\x00)\x81q\x01.";
+        assert_eq!(class_path(data), "dbm.ndbm.Thing");
+    }
+
+    #[test]
+    fn lone_surrogates() {
+        use crate::Surrogates;
+        // pickle.dumps(['a\udcff', {'k\ud800': 1}], 3)
+        let data = b"\x80\x03]q\x00(X\x04\x00\x00\x00a\xed\xb3\xbfq\x01}q\x02X\x04\x00\x00\x00\
+                     k\xed\xa0\x80q\x03K\x01se.";
+        assert!(value_from_slice(data, Default::default()).is_err());
+        let options = DeOptions::new().surrogates(Surrogates::Replace);
+        assert_eq!(
+            value_from_slice(data, options.clone()).unwrap(),
+            pyobj!(l=[s="a\u{fffd}", d={s="k\u{fffd}" => i=1}])
+        );
+        let (string, dict): (String, BTreeMap<String, i32>) = from_slice(data, options).unwrap();
+        assert_eq!(string, "a\u{fffd}");
+        assert_eq!(dict.get("k\u{fffd}"), Some(&1));
+
+        // Kept strings are written back unchanged.
+        let wtf = |b: &[u8]| Value::WtfString(b.to_vec().into());
+        let val = value_from_slice(data, DeOptions::new().surrogates(Surrogates::Keep)).unwrap();
+        let key = wtf(b"k\xed\xa0\x80").into_hashable().unwrap();
+        assert_eq!(
+            val,
+            Value::List(
+                vec![
+                    wtf(b"a\xed\xb3\xbf"),
+                    Value::Dict(BTreeMap::from([(key, pyobj!(i = 1))]).into())
+                ]
+                .into()
+            )
+        );
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert!(
+            vec.windows(9)
+                .any(|w| w == b"X\x04\x00\x00\x00a\xed\xb3\xbf")
+        );
+        let options = DeOptions::new().surrogates(Surrogates::Keep);
+        assert_eq!(value_from_slice(&vec, options).unwrap(), val);
+
+        // pickle.dumps('a\udcff\U0001f600', 0)
+        let data = b"Va\\udcff\\U0001f600\np0\n.";
+        assert!(value_from_slice(data, Default::default()).is_err());
+        let options = DeOptions::new().surrogates(Surrogates::Replace);
+        assert_eq!(
+            value_from_slice(data, options).unwrap(),
+            pyobj!(s = "a\u{fffd}\u{1f600}")
+        );
+        let options = DeOptions::new().surrogates(Surrogates::Keep);
+        assert_eq!(
+            value_from_slice(data, options).unwrap(),
+            wtf(b"a\xed\xb3\xbf\xf0\x9f\x98\x80")
+        );
+    }
+
+    #[test]
+    fn bytearrays() {
+        // pickle.dumps([bytearray(b'ab'), b'ab'], 5)
+        let data = b"\x80\x05\x95\x16\x00\x00\x00\x00\x00\x00\x00]\x94(\x96\x02\x00\x00\x00\x00\
+                     \x00\x00\x00ab\x94C\x02ab\x94e.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val, pyobj!(l = [ba = b"ab", bb = b"ab"]));
+        // pickle.dumps(bytearray(b'ab'), 3)
+        let data = b"\x80\x03cbuiltins\nbytearray\nq\x00C\x02abq\x01\x85q\x02Rq\x03.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val, pyobj!(ba = b"ab"));
+        assert!(val.clone().into_hashable().is_err());
+
+        for options in [SerOptions::new(), SerOptions::new().proto_v2()] {
+            let vec = value_to_vec(&val, options).unwrap();
+            assert_eq!(value_from_slice(&vec, Default::default()).unwrap(), val);
+        }
+    }
+
+    #[test]
+    fn container_subclasses() {
+        // t = Tags([1, 2]); t.owner = 'me'  (class Tags(list))
+        // pickle.dumps([t, Registry(a=1)], 2)  (class Registry(dict))
+        let data = b"\x80\x02]q\x00(cshapes\nTags\nq\x01)\x81q\x02(K\x01K\x02e}q\x03X\x05\x00\x00\x00\
+                     ownerq\x04X\x02\x00\x00\x00meq\x05sbcshapes\nRegistry\nq\x06)\x81q\x07X\x01\x00\
+                     \x00\x00aq\x08K\x01se.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val, pyobj!(l=[l=[i=1, i=2], d={s="a" => i=1}]));
+
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let objects = kept_objects(&val);
+        assert_eq!(objects[0].class_path(), "shapes.Tags");
+        assert_eq!(objects[0].items, Some(pyobj!(l = [i = 1, i = 2])));
+        assert_eq!(objects[0].attr("owner"), Some(pyobj!(s = "me")));
+        assert_eq!(objects[1].items, Some(pyobj!(d={s="a" => i=1})));
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+        let copy: Value = from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        assert_eq!(copy, val);
+
+        // pickle.dumps(t, 0) uses copy_reg._reconstructor(Tags, list, [1, 2]).
+        let data = b"ccopy_reg\n_reconstructor\np0\n(cshapes\nTags\np1\nc__builtin__\nlist\np2\n\
+                     (lp3\nI1\naI2\natp4\nRp5\n(dp6\nVowner\np7\nVme\np8\nsb.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val, pyobj!(l = [i = 1, i = 2]));
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(object.inner().items, Some(pyobj!(l = [i = 1, i = 2])));
+        assert_eq!(object.inner().attr("owner"), Some(pyobj!(s = "me")));
+    }
+
+    #[test]
+    fn collections_types() {
+        // pickle.dumps([OrderedDict(a=1), defaultdict(list, a=[1]),
+        //               deque([1, 2], maxlen=5), Counter(a=2)], 3)
+        let data = b"\x80\x03]q\x00(ccollections\nOrderedDict\nq\x01)Rq\x02X\x01\x00\x00\x00aq\x03K\x01\
+                     sccollections\ndefaultdict\nq\x04cbuiltins\nlist\nq\x05\x85q\x06Rq\x07h\x03]q\x08\
+                     K\x01asccollections\ndeque\nq\t)K\x05\x86q\nRq\x0b(K\x01K\x02eccollections\n\
+                     Counter\nq\x0c}q\rh\x03K\x02s\x85q\x0eRq\x0fe.";
+        assert_eq!(
+            value_from_slice(data, Default::default()).unwrap(),
+            pyobj!(l=[d={s="a" => i=1}, d={s="a" => l=[i=1]}, l=[i=1, i=2], d={s="a" => i=2}])
+        );
+
+        // The arguments are kept with the objects, and written back.
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let objects = kept_objects(&val);
+        assert_eq!(objects[1].class_path(), "collections.defaultdict");
+        assert_eq!(objects[1].args, vec![pyobj!(s = "builtins.list")]);
+        assert_eq!(objects[2].args, vec![pyobj!(t = ()), pyobj!(i = 5)]);
+        assert_eq!(objects[2].items, Some(pyobj!(l = [i = 1, i = 2])));
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert!(vec.windows(15).any(|w| w == b"cbuiltins\nlist\n"));
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+
+        // Python 2: pickle.dumps([OrderedDict([('a', 1)]), deque([1, 2], 5)], 2)
+        let data =
+            b"\x80\x02]q\x00(ccollections\nOrderedDict\nq\x01]q\x02]q\x03(U\x01aq\x04K\x01ea\
+                     \x85q\x05Rq\x06ccollections\ndeque\nq\x07]q\x08(K\x01K\x02eK\x05\x86q\tRq\ne.";
+        assert_eq!(
+            value_from_slice(data, Default::default()).unwrap(),
+            pyobj!(l=[d={bb=b"a" => i=1}, l=[i=1, i=2]])
+        );
+    }
+
+    #[test]
+    fn stdlib_types() {
+        // pickle.dumps([UUID(int=0x12345678123456781234567812345678),
+        //               PurePosixPath('/a/b'), PureWindowsPath('c:/x/y'),
+        //               IPv4Address('1.2.3.4'), IPv6Address('::1'),
+        //               IPv4Network('10.0.0.0/8'), range(1, 10, 2),
+        //               slice(1, None, 2), Ellipsis, NotImplemented], 3)
+        let data = b"\x80\x03]q\x00(cuuid\nUUID\nq\x01)\x81q\x02}q\x03X\x03\x00\x00\x00intq\x04\
+                     \x8a\x10xV4\x12xV4\x12xV4\x12xV4\x12sbcpathlib\nPurePosixPath\nq\x05X\x01\x00\
+                     \x00\x00/q\x06X\x01\x00\x00\x00aq\x07X\x01\x00\x00\x00bq\x08\x87q\tRq\n\
+                     cpathlib\nPureWindowsPath\nq\x0bX\x03\x00\x00\x00c:\\q\x0cX\x01\x00\x00\x00xq\r\
+                     X\x01\x00\x00\x00yq\x0e\x87q\x0fRq\x10cipaddress\nIPv4Address\nq\x11J\x04\x03\
+                     \x02\x01\x85q\x12Rq\x13cipaddress\nIPv6Address\nq\x14X\x03\x00\x00\x00::1q\x15\
+                     \x85q\x16Rq\x17cipaddress\nIPv4Network\nq\x18X\n\x00\x00\x0010.0.0.0/8q\x19\
+                     \x85q\x1aRq\x1bcbuiltins\nrange\nq\x1cK\x01K\nK\x02\x87q\x1dRq\x1ecbuiltins\n\
+                     slice\nq\x1fK\x01NK\x02\x87q Rq!cbuiltins\nEllipsis\nq\"cbuiltins\n\
+                     NotImplemented\nq#e.";
+        assert_eq!(
+            value_from_slice(data, Default::default()).unwrap(),
+            pyobj!(l=[
+                s="12345678-1234-5678-1234-567812345678", s="/a/b", s="c:\\x\\y",
+                s="1.2.3.4", s="::1", s="10.0.0.0/8",
+                d={s="start" => i=1, s="stop" => i=10, s="step" => i=2},
+                d={s="start" => i=1, s="stop" => n=None, s="step" => i=2},
+                s="Ellipsis", s="NotImplemented"
+            ])
+        );
+
+        // Kept as objects, they are written back as Python pickles them.
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let objects = kept_objects(&val);
+        assert_eq!(objects[8].class_path(), "builtins.Ellipsis");
+        let object = Value::Object(Shared::new(objects[3].clone()));
+        let addr = std::net::IpAddr::try_from(&object).unwrap();
+        assert_eq!(addr, std::net::Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(Value::from(addr), object);
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert!(vec.windows(18).any(|w| w == b"cbuiltins\nEllipsis"));
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+
+        // Nested classes are referred to with getattr(A, 'B'): A.B()
+        let data = b"\x80\x03cbuiltins\ngetattr\nc__main__\nA\nX\x01\x00\x00\x00B\x86R)R.";
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(object.inner().class_path(), "__main__.A.B");
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuids() {
+        for (int, string) in [
+            (
+                0x12345678123456781234567812345678,
+                "12345678-1234-5678-1234-567812345678",
+            ),
+            (1, "00000000-0000-0000-0000-000000000001"),
+        ] {
+            let uuid = uuid::Uuid::from_u128(int);
+            let vec = to_vec(&Value::from(uuid), Default::default()).unwrap();
+            assert!(vec.windows(10).any(|w| w == b"cuuid\nUUID"));
+            let val = value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap();
+            assert_eq!(val, Value::from(uuid));
+            assert_eq!(uuid::Uuid::try_from(&val).unwrap(), uuid);
+            // By default, the string form.
+            let val = value_from_slice(&vec, Default::default()).unwrap();
+            assert_eq!(val, pyobj!(s = string));
+            assert_eq!(uuid::Uuid::try_from(&val).unwrap(), uuid);
+        }
+        assert!(uuid::Uuid::try_from(&pyobj!(s = "not a uuid")).is_err());
+    }
+
+    #[test]
+    fn ndarrays() {
+        // pickle.dumps(np.array([[1, 2], [3, 4]], '<i4', order='F'), 3)
+        let data =
+            b"\x80\x03cnumpy.core.multiarray\n_reconstruct\nq\x00cnumpy\nndarray\nq\x01K\x00\
+                    \x85q\x02C\x01bq\x03\x87q\x04Rq\x05(K\x01K\x02K\x02\x86q\x06cnumpy\ndtype\nq\
+                    \x07X\x02\x00\x00\x00i4q\x08\x89\x88\x87q\tRq\n(K\x03X\x01\x00\x00\x00<q\x0bNNN\
+                    J\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00tq\x0cb\x88C\x10\x01\x00\x00\x00\x03\x00\
+                    \x00\x00\x02\x00\x00\x00\x04\x00\x00\x00q\rtq\x0eb.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let Value::NdArray(ref array) = val else {
+            panic!("expected an array, got {val}");
+        };
+        let array = array.inner().clone();
+        assert_eq!(array.dtype, Dtype::parse("<i4").unwrap());
+        assert_eq!(array.shape, vec![2, 2]);
+        assert!(array.fortran_order);
+        assert_eq!(array.elements().unwrap(), Elements::Int(vec![1, 2, 3, 4]));
+        assert_eq!(
+            array.to_list().unwrap(),
+            pyobj!(l = [l = [i = 1, i = 2], l = [i = 3, i = 4]])
+        );
+        // Serde sees nested lists.
+        let nested: Vec<Vec<i32>> = from_slice(data, Default::default()).unwrap();
+        assert_eq!(nested, vec![vec![1, 2], vec![3, 4]]);
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(value_from_slice(&vec, Default::default()).unwrap(), val);
+
+        // pickle.dumps(np.array([1, 2, 65535], '<u2'), 5)
+        let data = b"\x80\x05\x95r\x00\x00\x00\x00\x00\x00\x00\x8c\x12numpy.core.numeric\x94\x8c\
+                    \x0b_frombuffer\x94\x93\x94(C\x06\x01\x00\x02\x00\xff\xff\x94\x8c\x05numpy\x94\
+                    \x8c\x05dtype\x94\x93\x94\x8c\x02u2\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01<\x94\
+                    NNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\x94bK\x03\x85\x94\x8c\x01C\x94t\x94\
+                    R\x94.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let Value::NdArray(ref array) = val else {
+            panic!("expected an array, got {val}");
+        };
+        assert_eq!(
+            array.inner().to_list().unwrap(),
+            pyobj!(l = [i = 1, i = 2, i = 65535])
+        );
+
+        // pickle.dumps(np.array(['2001-09-09T01:46:40'], 'M8[ns]'), 4)
+        let data = b"\x80\x04\x95\xa1\x00\x00\x00\x00\x00\x00\x00\x8c\x15numpy.core.multiarray\x94\
+                    \x8c\x0c_reconstruct\x94\x93\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94K\
+                    \x00\x85\x94C\x01b\x94\x87\x94R\x94(K\x01K\x01\x85\x94h\x03\x8c\x05dtype\x94\
+                    \x93\x94\x8c\x02M8\x94\x89\x88\x87\x94R\x94(K\x04\x8c\x01<\x94NNNJ\xff\xff\xff\
+                    \xffJ\xff\xff\xff\xffK\x00N(C\x02ns\x94K\x01K\x01K\x01t\x94\x86\x94t\x94b\x89C\
+                    \x08\x00\x00d\xa7\xb3\xb6\xe0\r\x94t\x94b.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let Value::NdArray(ref array) = val else {
+            panic!("expected an array, got {val}");
+        };
+        assert_eq!(array.inner().dtype.descr(), "<M8[ns]");
+        assert_eq!(
+            array.inner().elements().unwrap(),
+            Elements::Int(vec![1_000_000_000_000_000_000])
+        );
+        let vec = value_to_vec(&val, SerOptions::new().proto_v2()).unwrap();
+        assert_eq!(value_from_slice(&vec, Default::default()).unwrap(), val);
+
+        // Crafted item sizes and shapes are refused instead of overflowing.
+        assert_eq!(Dtype::parse("<U5000000000000000000"), None);
+        // pickle.dumps(np.array([1, 2], '<i4'), 3)
+        let data = b"\x80\x03cnumpy.core.multiarray\n_reconstruct\nq\x00cnumpy\nndarray\nq\x01K\x00\
+                     \x85q\x02C\x01bq\x03\x87q\x04Rq\x05(K\x01K\x02\x85q\x06cnumpy\ndtype\nq\x07X\
+                     \x02\x00\x00\x00i4q\x08\x89\x88\x87q\tRq\n(K\x03X\x01\x00\x00\x00<q\x0bNNNJ\xff\
+                     \xff\xff\xffJ\xff\xff\xff\xffK\x00tq\x0cb\x89C\x08\x01\x00\x00\x00\x02\x00\x00\
+                     \x00q\rtq\x0eb.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            val,
+            Value::NdArray(Shared::new(NdArray {
+                dtype: Dtype::parse("<i4").unwrap(),
+                shape: vec![2],
+                fortran_order: false,
+                data: ArrayData::Raw(b"\x01\x00\x00\x00\x02\x00\x00\x00".to_vec()),
+            }))
+        );
+        let crafted = |edits: &[(&[u8], &[u8])]| {
+            let mut data = data.to_vec();
+            for (from, to) in edits {
+                let at = data.windows(from.len()).position(|w| w == *from).unwrap();
+                data.splice(at..at + from.len(), to.iter().copied());
+            }
+            data
+        };
+        let elsize: &[u8] = b"NNNJ\xff\xff\xff\xff";
+        // An elsize of 16 for `i4`
+        assert!(matches!(
+            value_from_slice(
+                &crafted(&[(elsize, b"NNNJ\x10\x00\x00\x00")]),
+                Default::default()
+            ),
+            Err(Error::Syntax(ErrorCode::InvalidValue(_)))
+        ));
+        // An elsize of 6 for `U1`
+        assert!(matches!(
+            value_from_slice(
+                &crafted(&[(b"i4", b"U1"), (elsize, b"NNNJ\x06\x00\x00\x00")]),
+                Default::default()
+            ),
+            Err(Error::Syntax(ErrorCode::InvalidValue(_)))
+        ));
+        // A shape of 2**93 items
+        let dim = b"J\xff\xff\xff\x7f";
+        let shape = [&dim[..], dim, dim, b"\x87"].concat();
+        assert!(matches!(
+            value_from_slice(&crafted(&[(b"K\x02\x85", &shape)]), Default::default()),
+            Err(Error::Syntax(ErrorCode::InvalidValue(_)))
+        ));
+
+        // Arrays of dtypes that can't be represented, like `<f16`, are
+        // replaced by their state or kept as objects, like other objects.
+        let data = crafted(&[(b"X\x02\x00\x00\x00i4", b"X\x03\x00\x00\x00f16")]);
+        let val = value_from_slice(&data, Default::default()).unwrap();
+        assert!(matches!(val, Value::Tuple(_)), "{val}");
+        let val = value_from_slice(&data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(
+            object.inner().class_path(),
+            "numpy.core.multiarray._reconstruct"
+        );
+    }
+
+    #[test]
+    fn pandas_frames() {
+        // pd.DataFrame({'a': [1.5, 2.5], 'b': [10, 20]}).to_pickle(), with
+        // the float block placed by an array and the int block by a slice
+        let data =
+            b"\x80\x04\x95-\x03\x00\x00\x00\x00\x00\x00\x8c\x11pandas.core.frame\x94\x8c\tDa\
+                    taFrame\x94\x93\x94)\x81\x94}\x94(\x8c\x04_mgr\x94\x8c\x1epandas.core.internals\
+                    .managers\x94\x8c\x0cBlockManager\x94\x93\x94]\x94(\x8c\x16pandas._libs.interna\
+                    ls\x94\x8c\x0f_unpickle_block\x94\x93\x94\x8c\x15numpy.core.multiarray\x94\x8c\
+                    \x0c_reconstruct\x94\x93\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94K\x00\
+                    \x85\x94C\x01b\x94\x87\x94R\x94(K\x01K\x01K\x02\x86\x94h\x10\x8c\x05dtype\x94\
+                    \x93\x94\x8c\x02f8\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01<\x94NNNJ\xff\xff\xff\
+                    \xffJ\xff\xff\xff\xffK\x00t\x94b\x89C\x10\x00\x00\x00\x00\x00\x00\xf8?\x00\x00\
+                    \x00\x00\x00\x00\x04@\x94t\x94bh\x0fh\x12h\x13h\x14\x87\x94R\x94(K\x01K\x01\x85\
+                    \x94h\x19\x8c\x02i8\x94\x89\x88\x87\x94R\x94(K\x03h\x1dNNNJ\xff\xff\xff\xffJ\
+                    \xff\xff\xff\xffK\x00t\x94b\x89C\x08\x00\x00\x00\x00\x00\x00\x00\x00\x94t\x94bK\
+                    \x02\x87\x94R\x94h\x0ch\x0fh\x12h\x13h\x14\x87\x94R\x94(K\x01h\x17h\x19h$\x89\
+                    \x88\x87\x94R\x94(K\x03h\x1dNNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\x94b\
+                    \x89C\x10\n\x00\x00\x00\x00\x00\x00\x00\x14\x00\x00\x00\x00\x00\x00\x00\x94t\
+                    \x94b\x8c\x08builtins\x94\x8c\x05slice\x94\x93\x94K\x01K\x02K\x01\x87\x94R\x94K\
+                    \x02\x87\x94R\x94e]\x94(\x8c\x18pandas.core.indexes.base\x94\x8c\n_new_Index\
+                    \x94\x93\x94h;\x8c\x05Index\x94\x93\x94}\x94(\x8c\x04data\x94h\x0fh\x12h\x13h\
+                    \x14\x87\x94R\x94(K\x01K\x02\x85\x94h\x19\x8c\x01O\x94\x89\x88\x87\x94R\x94(K\
+                    \x03\x8c\x01|\x94NNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\x94b\x89]\x94(\x8c\
+                    \x01a\x94\x8c\x01b\x94et\x94b\x8c\x04name\x94Nu\x86\x94R\x94h=\x8c\x19pandas.co\
+                    re.indexes.range\x94\x8c\nRangeIndex\x94\x93\x94}\x94(hNN\x8c\x05start\x94K\x00\
+                    \x8c\x04stop\x94K\x02\x8c\x04step\x94K\x01u\x86\x94R\x94e\x86\x94R\x94\x8c\x04_\
+                    typ\x94\x8c\tdataframe\x94\x8c\t_metadata\x94]\x94\x8c\x05attrs\x94}\x94\x8c\
+                    \x06_flags\x94}\x94\x8c\x17allows_duplicate_labels\x94\x88sub.";
+        let df = pandas::read_dataframe(&data[..], DeOptions::new()).unwrap();
+        assert_eq!(
+            df.index.labels,
+            Labels::Range {
+                start: 0,
+                stop: 2,
+                step: 1
+            }
+        );
+        assert_eq!(df.columns.len(), 2);
+        assert_eq!(df.columns[0].name, pyobj!(s = "a"));
+        assert_eq!(df.columns[0].values, Elements::Float(vec![1.5, 2.5]));
+        let b = df.column("b").unwrap();
+        assert_eq!(b.dtype, Dtype::parse("<i8").unwrap());
+        assert_eq!(b.values, Elements::Int(vec![10, 20]));
+
+        // The index classes are kept as globals, and written back as such.
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+
+        // Extension arrays are refused.
+        let Value::Object(ref frame) = val else {
+            panic!("expected an object, got {val}");
+        };
+        let Some(Value::Object(mgr)) = frame.inner().attr("_mgr") else {
+            panic!("expected a block manager");
+        };
+        let Value::List(ref blocks) = mgr.inner().args[0] else {
+            panic!("expected a list of blocks");
+        };
+        let Value::Object(ref block) = blocks.inner()[0] else {
+            panic!("expected a block");
+        };
+        let categorical = Object::new("pandas.core.arrays.categorical", "Categorical");
+        block.inner_mut().args[0] = Value::Object(Shared::new(categorical));
+        match pandas::DataFrame::from_value(&val) {
+            Err(Error::Syntax(ErrorCode::Structure(msg))) => assert_eq!(
+                msg,
+                "unsupported pandas extension array pandas.core.arrays.categorical.Categorical"
+            ),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // pd.DataFrame({'a': []}).to_pickle(): a column without rows
+        let data =
+            b"\x80\x04\x95z\x02\x00\x00\x00\x00\x00\x00\x8c\x11pandas.core.frame\x94\x8c\tDa\
+                    taFrame\x94\x93\x94)\x81\x94}\x94(\x8c\x04_mgr\x94\x8c\x1epandas.core.internals\
+                    .managers\x94\x8c\x0cBlockManager\x94\x93\x94]\x94\x8c\x16pandas._libs.internal\
+                    s\x94\x8c\x0f_unpickle_block\x94\x93\x94\x8c\x15numpy.core.multiarray\x94\x8c\
+                    \x0c_reconstruct\x94\x93\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94K\x00\
+                    \x85\x94C\x01b\x94\x87\x94R\x94(K\x01K\x01K\x00\x86\x94h\x10\x8c\x05dtype\x94\
+                    \x93\x94\x8c\x01O\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01|\x94NNNJ\xff\xff\xff\
+                    \xffJ\xff\xff\xff\xffK\x00t\x94b\x89]\x94t\x94b\x8c\x08builtins\x94\x8c\x05slic\
+                    e\x94\x93\x94K\x00K\x01K\x01\x87\x94R\x94K\x02\x87\x94R\x94a]\x94(\x8c\x18panda\
+                    s.core.indexes.base\x94\x8c\n_new_Index\x94\x93\x94h)\x8c\x05Index\x94\x93\x94}\
+                    \x94(\x8c\x04data\x94h\x0fh\x12h\x13h\x14\x87\x94R\x94(K\x01K\x01\x85\x94h\x19h\
+                    \x1a\x89\x88\x87\x94R\x94(K\x03h\x1dNNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\
+                    \x94b\x89]\x94\x8c\x01a\x94at\x94b\x8c\x04name\x94Nu\x86\x94R\x94h+\x8c\x19pand\
+                    as.core.indexes.range\x94\x8c\nRangeIndex\x94\x93\x94}\x94(h9N\x8c\x05start\x94\
+                    K\x00\x8c\x04stop\x94K\x00\x8c\x04step\x94K\x01u\x86\x94R\x94e\x86\x94R\x94\x8c\
+                    \x04_typ\x94\x8c\tdataframe\x94\x8c\t_metadata\x94]\x94\x8c\x05attrs\x94}\x94\
+                    \x8c\x06_flags\x94}\x94\x8c\x17allows_duplicate_labels\x94\x88sub.";
+        let df = pandas::read_dataframe(&data[..], DeOptions::new()).unwrap();
+        assert!(df.index.is_empty());
+        assert_eq!(df.columns.len(), 1);
+        assert_eq!(df.columns[0].name, pyobj!(s = "a"));
+        assert_eq!(df.columns[0].values, Elements::Object(vec![]));
+
+        // pd.Series(['p', None], pd.Index(['x', 'y'], name='key'),
+        //           name='vals').to_pickle()
+        let data = b"\x80\x04\x95?\x02\x00\x00\x00\x00\x00\x00\x8c\x12pandas.core.series\x94\x8c\
+                    \x06Series\x94\x93\x94)\x81\x94}\x94(\x8c\x04_mgr\x94\x8c\x1epandas.core.intern\
+                    als.managers\x94\x8c\x12SingleBlockManager\x94\x93\x94\x8c\x16pandas._libs.inte\
+                    rnals\x94\x8c\x0f_unpickle_block\x94\x93\x94\x8c\x15numpy.core.multiarray\x94\
+                    \x8c\x0c_reconstruct\x94\x93\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94K\
+                    \x00\x85\x94C\x01b\x94\x87\x94R\x94(K\x01K\x02\x85\x94h\x0f\x8c\x05dtype\x94\
+                    \x93\x94\x8c\x01O\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01|\x94NNNJ\xff\xff\xff\
+                    \xffJ\xff\xff\xff\xffK\x00t\x94b\x89]\x94(\x8c\x01p\x94Net\x94b\x8c\x08builtins\
+                    \x94\x8c\x05slice\x94\x93\x94K\x00K\x02K\x01\x87\x94R\x94K\x01\x87\x94R\x94\x8c\
+                    \x18pandas.core.indexes.base\x94\x8c\n_new_Index\x94\x93\x94h(\x8c\x05Index\x94\
+                    \x93\x94}\x94(\x8c\x04data\x94h\x0eh\x11h\x12h\x13\x87\x94R\x94(K\x01h\x16h\x18\
+                    h\x19\x89\x88\x87\x94R\x94(K\x03h\x1cNNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00\
+                    t\x94b\x89]\x94(\x8c\x01x\x94\x8c\x01y\x94et\x94b\x8c\x04name\x94\x8c\x03key\
+                    \x94u\x86\x94R\x94\x86\x94R\x94\x8c\x04_typ\x94\x8c\x06series\x94\x8c\t_metadat\
+                    a\x94]\x94\x8c\x05_name\x94a\x8c\x05attrs\x94}\x94\x8c\x06_flags\x94}\x94\x8c\
+                    \x17allows_duplicate_labels\x94\x88shB\x8c\x04vals\x94ub.";
+        let series = pandas::read_series(&data[..], DeOptions::new()).unwrap();
+        assert_eq!(series.name, pyobj!(s = "vals"));
+        assert_eq!(series.index.name, pyobj!(s = "key"));
+        assert_eq!(
+            series.index.to_values(),
+            vec![pyobj!(s = "x"), pyobj!(s = "y")]
+        );
+        assert_eq!(
+            series.values,
+            Elements::Object(vec![pyobj!(s = "p"), Value::None])
+        );
+        assert!(pandas::read_dataframe(&data[..], DeOptions::new()).is_err());
+    }
+
+    #[test]
+    fn persistent_ids() {
+        // Pickler.persistent_id returns 'queue' for a deque and
+        // (FloatStorage, '0') for a storage: pickle.dumps([deque(), storage], 3)
+        let data =
+            b"\x80\x03]q\x00(X\x05\x00\x00\x00queueq\x01Qctorch\nFloatStorage\nq\x02X\x01\x00\
+                     \x00\x000q\x03\x86q\x04Qe.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let pid = |value| Value::PersistentId(Box::new(value));
+        let ids = vec![
+            pid(pyobj!(s = "queue")),
+            pid(pyobj!(t = (s = "torch.FloatStorage", s = "0"))),
+        ];
+        assert_eq!(val, Value::List(Shared::new(ids)));
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(value_from_slice(&vec, Default::default()).unwrap(), val);
+        // Serde sees the IDs.
+        let ids: (String, (String, String)) = from_slice(data, Default::default()).unwrap();
+        assert_eq!(ids.0, "queue");
+        assert_eq!(ids.1.0, "torch.FloatStorage");
+
+        // PERSID, in protocol 0: pickle.dumps([deque()], 0)
+        let data = b"(lp0\nPqueue\na.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let ids = vec![pid(pyobj!(s = "queue"))];
+        assert_eq!(val, Value::List(Shared::new(ids)));
+    }
+
+    #[cfg(feature = "torch")]
+    #[test]
+    fn torch_checkpoint() {
+        use crate::torch::{Checkpoint, Dtype};
+        use std::io::Write;
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        // torch.save(OrderedDict([('fc.weight', Parameter(w)), ('fc.bias', b),
+        //                         ('fc.weight_t', w.t())])), with w and b
+        // views of one storage of 6 floats
+        let data =
+            b"\x80\x02ccollections\nOrderedDict\nq\x00)Rq\x01(X\t\x00\x00\x00fc.weightq\x02c\
+                    torch._utils\n_rebuild_parameter\nq\x03ctorch._utils\n_rebuild_tensor_v2\nq\x04\
+                    ((X\x07\x00\x00\x00storageq\x05ctorch\nFloatStorage\nq\x06X\x01\x00\x00\x000q\
+                    \x07X\x03\x00\x00\x00cpuq\x08K\x06tq\tQK\x00K\x02K\x02\x86q\nK\x02K\x01\x86q\
+                    \x0b\x89h\x00)Rq\x0ctq\rRq\x0e\x88h\x00)Rq\x0f\x87q\x10Rq\x11X\x07\x00\x00\x00f\
+                    c.biasq\x12h\x04((h\x05h\x06h\x07h\x08K\x06tq\x13QK\x04K\x02\x85q\x14K\x01\x85q\
+                    \x15\x89h\x00)Rq\x16tq\x17Rq\x18X\x0b\x00\x00\x00fc.weight_tq\x19h\x04((h\x05h\
+                    \x06h\x07h\x08K\x06tq\x1aQK\x00h\nK\x01K\x02\x86q\x1b\x89h\x00)Rq\x1ctq\x1dRq\
+                    \x1eu.";
+        let floats: Vec<u8> = (1..=6).flat_map(|i| (i as f32).to_le_bytes()).collect();
+        let zip_archive = |data: &[u8]| {
+            let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            let options =
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            for (name, bytes) in [
+                ("ckpt/data.pkl", data),
+                ("ckpt/byteorder", b"little"),
+                ("ckpt/data/0", &floats),
+            ] {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(bytes).unwrap();
+            }
+            zip.finish().unwrap().into_inner()
+        };
+        let archive = zip_archive(data);
+
+        let checkpoint = Checkpoint::from_slice(&archive).unwrap();
+        assert!(!checkpoint.is_big_endian());
+        let tensors = checkpoint.state_dict().unwrap();
+        let names: Vec<_> = tensors.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["fc.bias", "fc.weight", "fc.weight_t"]);
+        let (bias, weight, weight_t) = (&tensors[0].1, &tensors[1].1, &tensors[2].1);
+        assert_eq!(weight.dtype, Dtype::Float32);
+        assert_eq!(weight.shape, [2, 2]);
+        assert!(weight.requires_grad);
+        assert_eq!(weight.data(), Some(&floats[..16]));
+        assert_eq!(bias.storage_offset, 4);
+        assert_eq!(bias.data(), Some(&floats[16..]));
+        // Tensor data is borrowed from the archive.
+        let range = archive.as_ptr_range();
+        assert!(range.contains(&bias.data().unwrap().as_ptr()));
+        assert_eq!(weight_t.stride, [1, 2]);
+        assert!(!weight_t.is_contiguous());
+        assert_eq!(weight_t.data(), None);
+
+        // A bias of shape (3,) with a stride of 2**63 - 1, whose last item
+        // is out of range of a usize
+        let from = b"K\x04K\x02\x85q\x14K\x01\x85";
+        let at = data.windows(from.len()).position(|w| w == from).unwrap();
+        let to = b"K\x04K\x03\x85q\x14\x8a\x08\xff\xff\xff\xff\xff\xff\xff\x7f\x85";
+        let crafted = [&data[..at], to, &data[at + from.len()..]].concat();
+        let archive = zip_archive(&crafted);
+        let checkpoint = Checkpoint::from_slice(&archive).unwrap();
+        assert!(matches!(
+            checkpoint.state_dict(),
+            Err(Error::Syntax(ErrorCode::InvalidValue(_)))
+        ));
+    }
+
+    #[test]
+    fn joblib_arrays() {
+        // joblib.dump({'ints': np.array([[1, 2], [3, 4]], '<i4', order='F'),
+        //              'names': np.array(['a', None])}, f): the raw data of
+        // each array follows its NumpyArrayWrapper, after padding to align
+        // it, except for object arrays, which are pickled separately
+        let data =
+            b"\x80\x04\x95\xd7\x00\x00\x00\x00\x00\x00\x00}\x94(\x8c\x04ints\x94\x8c\x13jobl\
+                    ib.numpy_pickle\x94\x8c\x11NumpyArrayWrapper\x94\x93\x94)\x81\x94}\x94(\x8c\x08\
+                    subclass\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94\x8c\x05shape\x94K\x02K\
+                    \x02\x86\x94\x8c\x05order\x94\x8c\x01F\x94\x8c\x05dtype\x94h\x08h\x0f\x93\x94\
+                    \x8c\x02i4\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01<\x94NNNJ\xff\xff\xff\xffJ\xff\
+                    \xff\xff\xffK\x00t\x94b\x8c\nallow_mmap\x94\x88\x8c\x1bnumpy_array_alignment_by\
+                    tes\x94K\x10ub\r\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01\x00\
+                    \x00\x00\x03\x00\x00\x00\x02\x00\x00\x00\x04\x00\x00\x00\x95P\x00\x00\x00\x00\
+                    \x00\x00\x00\x8c\x05names\x94h\x04)\x81\x94}\x94(h\x07h\nh\x0bK\x02\x85\x94h\r\
+                    \x8c\x01C\x94h\x0fh\x10\x8c\x01O\x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01|\x94NNN\
+                    J\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\x94bh\x16\x88h\x17K\x10ub\x80\x02cnump\
+                    y.core.multiarray\n_reconstruct\nq\x00cnumpy\nndarray\nq\x01K\x00\x85q\x02c_cod\
+                    ecs\nencode\nq\x03X\x01\x00\x00\x00bq\x04X\x06\x00\x00\x00latin1q\x05\x86q\x06R\
+                    q\x07\x87q\x08Rq\t(K\x01K\x02\x85q\ncnumpy\ndtype\nq\x0bX\x01\x00\x00\x00Oq\x0c\
+                    \x89\x88\x87q\rRq\x0e(K\x03X\x01\x00\x00\x00|q\x0fNNNJ\xff\xff\xff\xffJ\xff\xff\
+                    \xff\xffK\x00tq\x10b\x89]q\x11(X\x01\x00\x00\x00aq\x12Netq\x13b.u.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        let Value::Dict(ref dict) = val else {
+            panic!("expected a dict, got {val}");
+        };
+        let array = |key: &str| match &dict.inner()[&hpyobj!(s = key)] {
+            Value::NdArray(array) => array.inner().clone(),
+            other => panic!("expected an array, got {other}"),
+        };
+        let ints = array("ints");
+        assert_eq!(ints.shape, vec![2, 2]);
+        assert!(ints.fortran_order);
+        assert_eq!(
+            ints.to_list().unwrap(),
+            pyobj!(l = [l = [i = 1, i = 2], l = [i = 3, i = 4]])
+        );
+        let names = array("names");
+        assert_eq!(names.dtype, Dtype::parse("|O").unwrap());
+        assert_eq!(names.to_list().unwrap(), pyobj!(l = [s = "a", n = None]));
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(value_from_slice(&vec, Default::default()).unwrap(), val);
+
+        // joblib.dump([np.array(['a'], object), Thing], f) for a class of a
+        // module named `__builtin__`: the protocol 2 pickle of the object
+        // array doesn't make the outer protocol 4 one map Python 2 names
+        let data = b"\x80\x04\x95\xcd\x00\x00\x00\x00\x00\x00\x00]\x94(\x8c\x13joblib.numpy_pickle\
+                    \x94\x8c\x11NumpyArrayWrapper\x94\x93\x94)\x81\x94}\x94(\x8c\x08subclass\x94\
+                    \x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94\x8c\x05shape\x94K\x01\x85\x94\x8c\
+                    \x05order\x94\x8c\x01C\x94\x8c\x05dtype\x94h\x07h\x0e\x93\x94\x8c\x01O\x94\x89\
+                    \x88\x87\x94R\x94(K\x03\x8c\x01|\x94NNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK\x00t\
+                    \x94b\x8c\nallow_mmap\x94\x88\x8c\x1bnumpy_array_alignment_bytes\x94K\x10ub\x80\
+                    \x02cnumpy.core.multiarray\n_reconstruct\nq\x00cnumpy\nndarray\nq\x01K\x00\x85q\
+                    \x02c_codecs\nencode\nq\x03X\x01\x00\x00\x00bq\x04X\x06\x00\x00\x00latin1q\x05\
+                    \x86q\x06Rq\x07\x87q\x08Rq\t(K\x01K\x01\x85q\ncnumpy\ndtype\nq\x0bX\x01\x00\x00\
+                    \x00Oq\x0c\x89\x88\x87q\rRq\x0e(K\x03X\x01\x00\x00\x00|q\x0fNNNJ\xff\xff\xff\
+                    \xffJ\xff\xff\xff\xffK\x00tq\x10b\x89]q\x11X\x01\x00\x00\x00aq\x12atq\x13b.\x95\
+                    \x1a\x00\x00\x00\x00\x00\x00\x00\x8c\x0b__builtin__\x94\x8c\x05Thing\x94\x93\
+                    \x94e.";
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::List(ref list) = val else {
+            panic!("expected a list, got {val}");
+        };
+        assert_eq!(
+            list.inner()[1],
+            Value::Global("__builtin__".into(), "Thing".into())
+        );
+    }
+
+    #[cfg(feature = "joblib")]
+    #[test]
+    fn joblib_compression() {
+        use crate::joblib;
+        use flate2::Compression;
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use std::io::Write;
+
+        // joblib.dump({'w': np.array([1, 2, 65535], '<u2')}, f, compress=...)
+        // compresses this pickle, without aligning the array data
+        let data =
+            b"\x80\x04\x95\xd0\x00\x00\x00\x00\x00\x00\x00}\x94\x8c\x01w\x94\x8c\x13joblib.n\
+                    umpy_pickle\x94\x8c\x11NumpyArrayWrapper\x94\x93\x94)\x81\x94}\x94(\x8c\x08subc\
+                    lass\x94\x8c\x05numpy\x94\x8c\x07ndarray\x94\x93\x94\x8c\x05shape\x94K\x03\x85\
+                    \x94\x8c\x05order\x94\x8c\x01C\x94\x8c\x05dtype\x94h\x08h\x0f\x93\x94\x8c\x02u2\
+                    \x94\x89\x88\x87\x94R\x94(K\x03\x8c\x01<\x94NNNJ\xff\xff\xff\xffJ\xff\xff\xff\
+                    \xffK\x00t\x94b\x8c\nallow_mmap\x94\x89\x8c\x1bnumpy_array_alignment_bytes\x94N\
+                    ub\x01\x00\x02\x00\xff\xffs.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(val.to_string(), "{\"w\": ndarray(shape=(3,), dtype='<u2')}");
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(data).unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(data).unwrap();
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut xz).unwrap();
+        let mut lzma = Vec::new();
+        lzma_rs::lzma_compress(&mut &data[..], &mut lzma).unwrap();
+        for file in [
+            data.to_vec(),
+            zlib.finish().unwrap(),
+            gzip.finish().unwrap(),
+            xz,
+            lzma,
+        ] {
+            assert_eq!(
+                joblib::value_from_slice(&file, Default::default()).unwrap(),
+                val
+            );
+        }
+        match joblib::value_from_slice(b"BZh91AY&SY", Default::default()) {
+            Err(Error::Syntax(ErrorCode::Structure(msg))) => {
+                assert_eq!(msg, "unsupported joblib bz2 compression")
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn npy_files() {
+        use crate::npy;
+
+        // np.save(f, np.array([[1, 2], [3, 4]], '<i4', order='F'))
+        let data =
+            b"\x93NUMPY\x01\x00v\x00{'descr': '<i4', 'fortran_order': True, 'shape': (2, 2),\
+                    \x20}                                                           \n\x01\x00\x00\
+                    \x00\x03\x00\x00\x00\x02\x00\x00\x00\x04\x00\x00\x00";
+        let array = npy::read(&data[..], Default::default()).unwrap();
+        assert_eq!(array.dtype, Dtype::parse("<i4").unwrap());
+        assert_eq!(array.shape, vec![2, 2]);
+        assert!(array.fortran_order);
+        assert_eq!(
+            array.to_list().unwrap(),
+            pyobj!(l = [l = [i = 1, i = 2], l = [i = 3, i = 4]])
+        );
+        let mut vec = Vec::new();
+        npy::write(&mut vec, &array, Default::default()).unwrap();
+        assert_eq!((vec.len() - 16) % 64, 0);
+        assert_eq!(npy::read(&vec[..], Default::default()).unwrap(), array);
+
+        // np.save(f, np.array(['a', None])): the array is pickled
+        let data =
+            b"\x93NUMPY\x01\x00v\x00{'descr': '|O', 'fortran_order': False, 'shape': (2,), }\
+                    \x20                                                            \n\x80\x03cnump\
+                    y.core.multiarray\n_reconstruct\nq\x00cnumpy\nndarray\nq\x01K\x00\x85q\x02C\x01\
+                    bq\x03\x87q\x04Rq\x05(K\x01K\x02\x85q\x06cnumpy\ndtype\nq\x07X\x01\x00\x00\x00O\
+                    q\x08\x89\x88\x87q\tRq\n(K\x03X\x01\x00\x00\x00|q\x0bNNNJ\xff\xff\xff\xffJ\xff\
+                    \xff\xff\xffK\x00tq\x0cb\x89]q\r(X\x01\x00\x00\x00aq\x0eNetq\x0fb.";
+        let array = npy::read(&data[..], Default::default()).unwrap();
+        assert_eq!(array.dtype, Dtype::parse("|O").unwrap());
+        assert_eq!(array.to_list().unwrap(), pyobj!(l = [s = "a", n = None]));
+        let mut vec = Vec::new();
+        npy::write(&mut vec, &array, Default::default()).unwrap();
+        assert_eq!(npy::read(&vec[..], Default::default()).unwrap(), array);
+
+        // A structured dtype, in a header as written by Python 2.
+        let header = b"{'descr': [('x', '<f8')], 'fortran_order': False, 'shape': (1L,), }\n";
+        let mut data = b"\x93NUMPY\x01\x00".to_vec();
+        data.extend((header.len() as u16).to_le_bytes());
+        data.extend(header);
+        match npy::read(&data[..], Default::default()) {
+            Err(Error::Syntax(ErrorCode::Structure(msg))) => {
+                assert_eq!(msg, "unsupported structured numpy dtype")
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        // Deeply nested headers are refused instead of overflowing the stack.
+        let header = "[".repeat(60000);
+        let mut data = b"\x93NUMPY\x01\x00".to_vec();
+        data.extend((header.len() as u16).to_le_bytes());
+        data.extend(header.as_bytes());
+        assert!(matches!(
+            npy::read(&data[..], Default::default()),
+            Err(Error::Syntax(ErrorCode::InvalidValue(_)))
+        ));
+    }
+
+    #[test]
+    fn zodb_storage() {
+        use crate::zodb::{FileStorage, Reference};
+        use crate::{StrEncoding, StrErrors};
+
+        // A FileStorage with three transactions: the creation of a root
+        // PersistentMapping and an app.Folder with a weak reference and one
+        // to another database, a change to the folder with a new object, and
+        // the undo of that change, by back pointers
+        let data = b"FS30\x03\xe0\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x01\x80 \x00\x00\
+                    \x00\x19\x00\x00initial database creation\x00\x00\x00\x00\x00\x00\x00\x00\x03\
+                    \xe0\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                    \x00\x00\x00\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00j\x80\x03cpersistent.mappin\
+                    g\nPersistentMapping\nq\x00.\x80\x03}q\x01X\x04\x00\x00\x00dataq\x02}q\x03X\x04\
+                    \x00\x00\x00homeq\x04C\x08\x00\x00\x00\x00\x00\x00\x00\x01q\x05capp\nFolder\nq\
+                    \x06\x86q\x07Qss.\x00\x00\x00\x00\x00\x00\x00\x01\x03\xe0\x00\x00\x00\x00\x00\
+                    \x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x04\x00\x00\
+                    \x00\x00\x00\x00\x00\x00\x00\x92\x80\x03capp\nFolder\nq\x00.\x80\x03}q\x01(X\
+                    \x05\x00\x00\x00titleq\x02X\x04\x00\x00\x00Homeq\x03X\x05\x00\x00\x00ownerq\x04\
+                    ]q\x05(X\x01\x00\x00\x00wq\x06C\x08\x00\x00\x00\x00\x00\x00\x00\x00q\x07\x85q\
+                    \x08eQX\x05\x00\x00\x00indexq\t]q\n(X\x01\x00\x00\x00mq\x0bX\x07\x00\x00\x00cat\
+                    alogq\x0cC\x08\x00\x00\x00\x00\x00\x00\x00\x05q\rh\x00\x87q\x0eeQu.\x00\x00\x00\
+                    \x00\x00\x00\x01\x80\x03\xe0\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\
+                    \x00\xd8 \x00\x06\x00\x00\x00\x1f admin\x80\x03}q\x00X\x04\x00\x00\x00noteq\x01\
+                    X\x06\x00\x00\x00renameq\x02s.\x00\x00\x00\x00\x00\x00\x00\x01\x03\xe0\x00\x00\
+                    \x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\xc8\x00\x00\x00\x00\x00\x00\x01\
+                    \x8c\x00\x00\x00\x00\x00\x00\x00\x00\x001\x80\x03capp\nFolder\nq\x00.\x80\x03}q\
+                    \x01X\x05\x00\x00\x00titleq\x02X\x06\x00\x00\x00Home 2q\x03s.\x00\x00\x00\x00\
+                    \x00\x00\x00\x02\x03\xe0\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\
+                    \x00\x00\x00\x00\x00\x00\x00\x01\x8c\x00\x00\x00\x00\x00\x00\x00\x00\x00\x17\
+                    \x80\x03capp\nFolder\nq\x00.\x80\x03}q\x01.\x00\x00\x00\x00\x00\x00\x00\xd8\x03\
+                    \xe0\x00\x00\x00\x00\x00\x03\x00\x00\x00\x00\x00\x00\x00\x85 \x00\x06\x00\x04\
+                    \x00\x00 adminundo\x00\x00\x00\x00\x00\x00\x00\x01\x03\xe0\x00\x00\x00\x00\x00\
+                    \x03\x00\x00\x00\x00\x00\x00\x01\xc8\x00\x00\x00\x00\x00\x00\x02l\x00\x00\x00\
+                    \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xc8\x00\x00\x00\x00\
+                    \x00\x00\x00\x02\x03\xe0\x00\x00\x00\x00\x00\x03\x00\x00\x00\x00\x00\x00\x02#\
+                    \x00\x00\x00\x00\x00\x00\x02l\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                    \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x85";
+        let storage = FileStorage::from_slice(data, Default::default()).unwrap();
+        let transactions = (storage.transactions())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let [create, change, undo] = &transactions[..] else {
+            panic!("expected 3 transactions, got {}", transactions.len());
+        };
+        assert_eq!(create.description, "initial database creation");
+        let [root, folder] = &create.records[..] else {
+            panic!("expected 2 records, got {}", create.records.len());
+        };
+        assert_eq!((root.oid, folder.oid), (0, 1));
+        assert_eq!(
+            root.class,
+            pyobj!(s = "persistent.mapping.PersistentMapping")
+        );
+        assert_eq!(
+            root.references().unwrap(),
+            vec![Reference::Object {
+                oid: 1,
+                class: Some("app.Folder".into())
+            }]
+        );
+        assert_eq!(
+            folder.references().unwrap(),
+            vec![
+                Reference::Database {
+                    database: "catalog".into(),
+                    oid: 5,
+                    class: Some("app.Folder".into())
+                },
+                Reference::Weak {
+                    oid: 0,
+                    database: None
+                },
+            ]
+        );
+
+        assert_eq!(change.user, " admin");
+        assert_eq!(change.extension, pyobj!(d = {s = "note" => s = "rename"}));
+        assert_eq!(
+            change.records[0].state,
+            pyobj!(d = {s = "title" => s = "Home 2"})
+        );
+        assert_eq!(change.records[1].state, pyobj!(d = {}));
+
+        assert_eq!(undo.records[0].state, folder.state);
+        assert_eq!(undo.records[1].oid, 2);
+        assert_eq!(undo.records[1].class, Value::None);
+
+        // Python 2: pickle.dumps(('\0\0\0\0\0\0\0\x80', ('app', 'Folder')), 1),
+        // with the oid decoded as Latin-1
+        let data = b"(U\x08\x00\x00\x00\x00\x00\x00\x00\x80q\x00(U\x03appq\x01U\x06Folderq\x02\
+                     tq\x03tq\x04.";
+        let options = DeOptions::new().str_encoding(StrEncoding::Latin1, StrErrors::Strict);
+        let pid = value_from_slice(data, options).unwrap();
+        assert_eq!(
+            Reference::from_pid(&pid).unwrap(),
+            Reference::Object {
+                oid: 0x80,
+                class: Some("app.Folder".into())
+            }
+        );
+    }
+
+    #[test]
+    fn keep_objects() {
+        // pickle.dumps(Point(1, 2, "origin"), 3), see struct_tests::decode_class
+        let data = b"\x80\x03cshapes\nPoint\nq\x00K\x01K\x02\x86q\x01\x81q\x02}q\x03(X\x01\x00\x00\x00\
+                     xq\x04K\x01X\x01\x00\x00\x00yq\x05K\x02X\x04\x00\x00\x00nameq\x06X\x06\x00\x00\x00\
+                     originq\x07ub.";
+        let val = value_from_slice(data, Default::default()).unwrap();
+        assert_eq!(
+            val,
+            pyobj!(d={s="x" => i=1, s="y" => i=2, s="name" => s="origin"})
+        );
+
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        let object = object.inner().clone();
+        assert_eq!(object.class_path(), "shapes.Point");
+        assert!(object.new);
+        assert_eq!(object.args, vec![pyobj!(i = 1), pyobj!(i = 2)]);
+        assert_eq!(object.attr("name"), Some(pyobj!(s = "origin")));
+
+        // Objects survive a roundtrip through pickle and through serde.
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+        let copy: Value = from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        assert_eq!(copy, val);
+
+        // An object built twice, from a dict that is also in the list: the
+        // second BUILD doesn't change that dict.
+        let data = b"\x80\x02]q\x00(capp\nC\nq\x01)\x81q\x02}q\x03X\x01\x00\x00\x00aK\x01sb}\
+                     X\x01\x00\x00\x00bK\x02sbh\x03e.";
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::List(ref list) = val else {
+            panic!("expected a list, got {val}");
+        };
+        let list = list.inner();
+        let Value::Object(ref object) = list[0] else {
+            panic!("expected an object, got {}", list[0]);
+        };
+        assert_eq!(
+            object.inner().state,
+            Some(pyobj!(d={s="a" => i=1, s="b" => i=2}))
+        );
+        assert_eq!(list[1], pyobj!(d={s="a" => i=1}));
+    }
+
     #[test]
     fn unresolvable_global() {
         let data = std::fs::read("test/data/test_unresolvable_global.pickle").unwrap();
@@ -494,4 +2108,49 @@ mod value_tests {
             from_slice(&data, DeOptions::new().replace_unresolved_globals()).unwrap();
         assert_eq!(serde_val, serde_json::Value::Null);
     }
+
+    #[test]
+    fn reduced_object_with_arguments() {
+        // pickle.dumps(decimal.Decimal("1.5"), 3): unlike namedtuples, objects
+        // created by REDUCE aren't replaced by their arguments.
+        let data = b"\x80\x03cdecimal\nDecimal\nq\x00X\x03\x00\x00\x001.5q\x01\x85q\x02Rq\x03.";
+        assert!(matches!(
+            value_from_slice(data, Default::default()),
+            Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
+        ));
+        assert!(matches!(
+            from_slice::<String>(data, Default::default()),
+            Err(Error::Syntax(ErrorCode::UnresolvedGlobal))
+        ));
+        let val = value_from_slice(data, DeOptions::new().replace_unresolved_globals()).unwrap();
+        assert_eq!(val, Value::None);
+        let serde_val: serde_json::Value =
+            from_slice(data, DeOptions::new().replace_unresolved_globals()).unwrap();
+        assert_eq!(serde_val, serde_json::Value::Null);
+    }
+
+    #[test]
+    fn class_arguments() {
+        // pickle.dumps(app.make(list), 3)
+        let data = b"\x80\x03capp\nmake\nq\x00cbuiltins\nlist\nq\x01\x85q\x02Rq\x03.";
+        let val = value_from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        let Value::Object(ref object) = val else {
+            panic!("expected an object, got {val}");
+        };
+        assert_eq!(
+            object.inner().args,
+            [Value::Global("builtins".into(), "list".into())]
+        );
+        let vec = value_to_vec(&val, Default::default()).unwrap();
+        assert_eq!(
+            value_from_slice(&vec, DeOptions::new().keep_objects()).unwrap(),
+            val
+        );
+        // Serde sees the class path.
+        let serde_val: serde_json::Value =
+            from_slice(data, DeOptions::new().keep_objects()).unwrap();
+        assert_eq!(serde_val["args"], serde_json::json!(["builtins.list"]));
+        // Without keeping objects, the global is still unresolved.
+        assert!(value_from_slice(data, Default::default()).is_err());
+    }
 }